@@ -0,0 +1,906 @@
+use evdev::{AbsoluteAxisCode, KeyCode};
+use std::collections::hash_map;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::names::{axis_from_name, axis_name, key_from_name, key_name};
+
+/// Presses per second of a turbo binding unless set otherwise.
+pub const DEFAULT_TURBO_HZ: u32 = 15;
+
+/// Highest turbo rate. Faster than this the half period rounds down toward
+/// nothing and the timers would flip on every wakeup.
+pub const MAX_TURBO_HZ: u32 = 1000;
+
+/// Whether `hz` is a usable turbo rate, from 1 to [`MAX_TURBO_HZ`].
+pub fn valid_turbo_hz(hz: u32) -> bool {
+    (1..=MAX_TURBO_HZ).contains(&hz)
+}
+
+/// Whether a held axis' ease can start `deadzone_pct` percent of the way to
+/// its deflection: from 0 to 100.
+pub fn valid_deadzone_pct(deadzone_pct: u32) -> bool {
+    deadzone_pct <= 100
+}
+
+/// How long a double-tap waits for the second press unless set otherwise.
+pub const DEFAULT_DOUBLE_TAP_WINDOW_MS: u64 = 250;
+
+// Priorities of the rules a key press can go to unless set otherwise. When
+// several rules of a controller want the same press, the highest priority
+// takes it, so by default a shortcut comes before a chord, a chord before
+// the held layer's binding and that before the key's own. The gaps leave
+// room to slot a rule in between.
+
+pub const DEFAULT_SHORTCUT_PRIORITY: i32 = 30;
+pub const DEFAULT_CHORD_PRIORITY: i32 = 20;
+pub const DEFAULT_LAYER_PRIORITY: i32 = 10;
+pub const DEFAULT_BINDING_PRIORITY: i32 = 0;
+
+/// A button or axis on the virtual controller that bindings write to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    Button(KeyCode),
+    Axis(AbsoluteAxisCode),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Button(button) => write!(f, "button {}", button.0),
+            Target::Axis(axis) => write!(f, "axis {}", axis.0),
+        }
+    }
+}
+
+impl Target {
+    /// Parses the `button <code>` / `axis <code>` form written by `Display`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (kind, code) = s.trim().split_once(' ')?;
+        let code = code.trim().parse().ok()?;
+
+        match kind {
+            "button" => Some(Target::Button(KeyCode::new(code))),
+            "axis" => Some(Target::Axis(AbsoluteAxisCode(code))),
+            _ => None,
+        }
+    }
+}
+
+/// What a keyboard key drives on a virtual controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// Press and release a controller button together with the key.
+    Button(KeyCode),
+    /// Press and release several controller buttons at once with the key,
+    /// e.g. Start and Select together. Never empty.
+    Combo(Vec<KeyCode>),
+    /// Each press latches `axis` at `value`; the next press returns it to
+    /// center. Releases and autorepeat leave the latch alone.
+    AxisToggle { axis: AbsoluteAxisCode, value: i32 },
+    /// A key press taps the button: it goes down and is released again after
+    /// the session's pulse duration, however long the key is held.
+    Tap(KeyCode),
+    /// Arms sticky mode: the next button pressed on the controller stays
+    /// held after its key is released, until that key is pressed again.
+    /// Pressing the sticky key twice disarms it.
+    Sticky,
+    /// Each press moves `axis` to the next value in `values`, wrapping around
+    /// after the last, e.g. for a throttle with notches. Never empty.
+    AxisCycle {
+        axis: AbsoluteAxisCode,
+        values: Vec<i32>,
+    },
+    /// While the key is held the button is pressed and released `hz` times a
+    /// second. It is always left up when the key is released, unless another
+    /// key still holds the button. `hz` is from 1 to [`MAX_TURBO_HZ`].
+    Turbo { button: KeyCode, hz: u32 },
+    /// While the key is held `axis` is deflected to `value`, easing there
+    /// from its rest over `ramp_ms`; 0 jumps straight there. The ease starts
+    /// `deadzone_pct` percent of the way, so none of it is lost in the
+    /// game's deadzone. It returns to rest on release.
+    Axis {
+        axis: AbsoluteAxisCode,
+        value: i32,
+        ramp_ms: u64,
+        deadzone_pct: u32,
+    },
+    /// While the key is held, axes driven by held keys on the controller go
+    /// at most halfway, for walking instead of running.
+    HalfPress,
+    /// While the key is held, keys bound in the named layer of the mapping
+    /// do what the layer says instead. Buttons pressed under the layer are
+    /// let go when the key is released.
+    Layer(String),
+    /// Runs a shell command on each press, without waiting for it. Only
+    /// honored with `--allow-exec`; see
+    /// [`crate::mapping::DeviceMapper::allow_exec`].
+    Exec(String),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Button(button) => write!(f, "button {}", button.0),
+            Binding::Combo(buttons) => {
+                write!(f, "combo")?;
+                for button in buttons {
+                    write!(f, " {}", button.0)?;
+                }
+                Ok(())
+            }
+            Binding::AxisToggle { axis, value } => write!(f, "axis-toggle {} {}", axis.0, value),
+            Binding::Tap(button) => write!(f, "tap {}", button.0),
+            Binding::Sticky => write!(f, "sticky"),
+            Binding::AxisCycle { axis, values } => {
+                write!(f, "axis-cycle {}", axis.0)?;
+                for value in values {
+                    write!(f, " {}", value)?;
+                }
+                Ok(())
+            }
+            Binding::Turbo { button, hz } => write!(f, "turbo {} {}", button.0, hz),
+            Binding::Axis {
+                axis,
+                value,
+                ramp_ms,
+                deadzone_pct: 0,
+            } => write!(f, "axis {} {} {}", axis.0, value, ramp_ms),
+            Binding::Axis {
+                axis,
+                value,
+                ramp_ms,
+                deadzone_pct,
+            } => write!(f, "axis {} {} {} {}", axis.0, value, ramp_ms, deadzone_pct),
+            Binding::HalfPress => write!(f, "half-press"),
+            Binding::Layer(name) => write!(f, "layer {}", name),
+            Binding::Exec(command) => write!(f, "exec {}", command),
+        }
+    }
+}
+
+impl Binding {
+    /// Parses the textual form written by `Display`.
+    pub fn parse(s: &str) -> Option<Self> {
+        // The command is everything after the keyword, spaces included
+        if let Some(command) = s.trim().strip_prefix("exec ") {
+            let command = command.trim();
+            return (!command.is_empty()).then(|| Binding::Exec(command.to_string()));
+        }
+
+        let fields: Vec<&str> = s.split_whitespace().collect();
+
+        match fields.as_slice() {
+            ["button", code] => Some(Binding::Button(KeyCode::new(code.parse().ok()?))),
+            ["axis-toggle", axis, value] => Some(Binding::AxisToggle {
+                axis: AbsoluteAxisCode(axis.parse().ok()?),
+                value: value.parse().ok()?,
+            }),
+            ["combo", codes @ ..] if !codes.is_empty() => Some(Binding::Combo(
+                codes
+                    .iter()
+                    .map(|code| code.parse().ok().map(KeyCode::new))
+                    .collect::<Option<_>>()?,
+            )),
+            ["tap", code] => Some(Binding::Tap(KeyCode::new(code.parse().ok()?))),
+            ["sticky"] => Some(Binding::Sticky),
+            ["half-press"] => Some(Binding::HalfPress),
+            ["layer", name] => Some(Binding::Layer(name.to_string())),
+            ["axis", axis, value, ease @ ..] if ease.len() <= 2 => Some(Binding::Axis {
+                axis: AbsoluteAxisCode(axis.parse().ok()?),
+                value: value.parse().ok()?,
+                ramp_ms: match ease.first() {
+                    Some(ramp_ms) => ramp_ms.parse().ok()?,
+                    None => 0,
+                },
+                deadzone_pct: match ease.get(1) {
+                    Some(pct) => pct.parse().ok().filter(|pct| valid_deadzone_pct(*pct))?,
+                    None => 0,
+                },
+            }),
+            ["turbo", code, hz] => Some(Binding::Turbo {
+                button: KeyCode::new(code.parse().ok()?),
+                hz: hz.parse().ok().filter(|hz| valid_turbo_hz(*hz))?,
+            }),
+            ["axis-cycle", axis, values @ ..] if !values.is_empty() => Some(Binding::AxisCycle {
+                axis: AbsoluteAxisCode(axis.parse().ok()?),
+                values: values
+                    .iter()
+                    .map(|value| value.parse().ok())
+                    .collect::<Option<_>>()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The text form with evdev names in place of codes, e.g. `BTN_SOUTH` for
+    /// a plain button or `axis ABS_X -32768 0` for a held axis, for files
+    /// people edit by hand.
+    pub fn to_named_string(&self) -> String {
+        match self {
+            Binding::Button(button) => key_name(*button).to_string(),
+            Binding::Combo(buttons) => {
+                let names: Vec<&str> = buttons.iter().copied().map(key_name).collect();
+                format!("combo {}", names.join(" "))
+            }
+            Binding::AxisToggle { axis, value } => {
+                format!("axis-toggle {} {}", axis_name(*axis), value)
+            }
+            Binding::Tap(button) => format!("tap {}", key_name(*button)),
+            Binding::AxisCycle { axis, values } => {
+                let values: Vec<String> = values.iter().map(i32::to_string).collect();
+                format!("axis-cycle {} {}", axis_name(*axis), values.join(" "))
+            }
+            Binding::Turbo { button, hz } => format!("turbo {} {}", key_name(*button), hz),
+            Binding::Axis {
+                axis,
+                value,
+                ramp_ms,
+                deadzone_pct: 0,
+            } => format!("axis {} {} {}", axis_name(*axis), value, ramp_ms),
+            Binding::Axis {
+                axis,
+                value,
+                ramp_ms,
+                deadzone_pct,
+            } => format!(
+                "axis {} {} {} {}",
+                axis_name(*axis),
+                value,
+                ramp_ms,
+                deadzone_pct
+            ),
+            Binding::Sticky | Binding::HalfPress | Binding::Layer(_) | Binding::Exec(_) => {
+                self.to_string()
+            }
+        }
+    }
+
+    /// Parses the form written by [`Binding::to_named_string`]. Raw codes
+    /// are accepted wherever a name is.
+    pub fn parse_named(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.starts_with("exec ") || s.starts_with("layer ") {
+            return Binding::parse(s);
+        }
+
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if let [name] = fields.as_slice()
+            && let Some(button) = key_from_name(name)
+        {
+            return Some(Binding::Button(button));
+        }
+
+        // Swap names for codes and let the plain parser do the rest
+        let coded: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                // Numbers are left alone, as values and codes look the same
+                let code = (i > 0 && field.parse::<i32>().is_err())
+                    .then(|| {
+                        key_from_name(field)
+                            .map(|key| key.0)
+                            .or_else(|| axis_from_name(field).map(|axis| axis.0))
+                    })
+                    .flatten();
+                code.map_or_else(|| field.to_string(), |code| code.to_string())
+            })
+            .collect();
+
+        Binding::parse(&coded.join(" "))
+    }
+
+    /// The controller buttons and axes this binding writes to. Only combos
+    /// have more than one.
+    pub fn targets(&self) -> Vec<Target> {
+        match self {
+            Binding::Button(button) | Binding::Tap(button) | Binding::Turbo { button, .. } => {
+                vec![Target::Button(*button)]
+            }
+            Binding::Combo(buttons) => buttons.iter().copied().map(Target::Button).collect(),
+            Binding::AxisToggle { axis, .. }
+            | Binding::AxisCycle { axis, .. }
+            | Binding::Axis { axis, .. } => vec![Target::Axis(*axis)],
+            Binding::Sticky | Binding::HalfPress | Binding::Layer(_) | Binding::Exec(_) => {
+                Vec::new()
+            }
+        }
+    }
+
+    /// The controller button this binding presses, if it is a plain button.
+    pub fn button(&self) -> Option<KeyCode> {
+        match self {
+            Binding::Button(button) => Some(*button),
+            Binding::Combo(_)
+            | Binding::AxisToggle { .. }
+            | Binding::Tap(_)
+            | Binding::Sticky
+            | Binding::AxisCycle { .. }
+            | Binding::Turbo { .. }
+            | Binding::Axis { .. }
+            | Binding::HalfPress
+            | Binding::Layer(_)
+            | Binding::Exec(_) => None,
+        }
+    }
+
+    /// Makes the binding press `button_b` wherever it pressed `button_a`, and
+    /// the other way around.
+    pub fn swap_buttons(&mut self, button_a: KeyCode, button_b: KeyCode) {
+        match self {
+            Binding::Button(button) | Binding::Tap(button) | Binding::Turbo { button, .. } => {
+                swap_button(button, button_a, button_b)
+            }
+            Binding::Combo(buttons) => {
+                for button in buttons {
+                    swap_button(button, button_a, button_b);
+                }
+            }
+            Binding::AxisToggle { .. }
+            | Binding::Sticky
+            | Binding::AxisCycle { .. }
+            | Binding::Axis { .. }
+            | Binding::HalfPress
+            | Binding::Layer(_)
+            | Binding::Exec(_) => {}
+        }
+    }
+}
+
+/// Turns `button` into `button_b` if it is `button_a`, and the other way
+/// around.
+pub fn swap_button(button: &mut KeyCode, button_a: KeyCode, button_b: KeyCode) {
+    if *button == button_a {
+        *button = button_b;
+    } else if *button == button_b {
+        *button = button_a;
+    }
+}
+
+/// Source keys that together press `button` when they all go down within
+/// the controller's chord window, instead of each doing what it's bound to
+/// alone. Never fewer than two keys. It only takes its keys while its
+/// `priority` is at least that of each key's binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub keys: Vec<KeyCode>,
+    pub button: KeyCode,
+    pub priority: i32,
+}
+
+impl Chord {
+    /// Whether `held` is exactly the chord's keys, in any order.
+    pub fn matches(&self, held: &[KeyCode]) -> bool {
+        held.len() == self.keys.len() && self.keys.iter().all(|key| held.contains(key))
+    }
+
+    /// Whether more keys going down could still complete the chord after
+    /// `held`.
+    pub fn could_match(&self, held: &[KeyCode]) -> bool {
+        held.iter().all(|key| self.keys.contains(key))
+    }
+}
+
+/// A key that taps `button` for `pulse_ms` when pressed twice within
+/// `window_ms`, e.g. a dash on a double-tapped direction. Both presses still
+/// do what the key is bound to; the tap comes on top. Neither duration is
+/// ever 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoubleTap {
+    pub key: KeyCode,
+    pub button: KeyCode,
+    pub window_ms: u64,
+    pub pulse_ms: u64,
+}
+
+impl DoubleTap {
+    /// Whether a press `since_first` after the previous one makes a
+    /// double-tap.
+    pub fn completes(&self, since_first: Duration) -> bool {
+        since_first <= Duration::from_millis(self.window_ms)
+    }
+}
+
+/// A modifier a [`Shortcut`] needs held. The left and right keys both count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Modifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Meta,
+}
+
+impl Modifier {
+    /// The modifier `key` is, if it is one.
+    pub fn of(key: KeyCode) -> Option<Self> {
+        match key {
+            KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL => Some(Modifier::Ctrl),
+            KeyCode::KEY_LEFTSHIFT | KeyCode::KEY_RIGHTSHIFT => Some(Modifier::Shift),
+            KeyCode::KEY_LEFTALT | KeyCode::KEY_RIGHTALT => Some(Modifier::Alt),
+            KeyCode::KEY_LEFTMETA | KeyCode::KEY_RIGHTMETA => Some(Modifier::Meta),
+            _ => None,
+        }
+    }
+
+    /// The modifier named `s`, in any case, as written by `Display`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ctrl" => Some(Modifier::Ctrl),
+            "shift" => Some(Modifier::Shift),
+            "alt" => Some(Modifier::Alt),
+            "meta" => Some(Modifier::Meta),
+            _ => None,
+        }
+    }
+}
+
+/// The modifiers named in `names`, sorted and without duplicates, as a
+/// [`Shortcut`] keeps them. `None` if any name isn't a modifier.
+pub fn parse_modifiers<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Vec<Modifier>> {
+    let modifiers: BTreeSet<Modifier> = names
+        .into_iter()
+        .map(Modifier::parse)
+        .collect::<Option<_>>()?;
+
+    Some(modifiers.into_iter().collect())
+}
+
+impl fmt::Display for Modifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Modifier::Ctrl => write!(f, "Ctrl"),
+            Modifier::Shift => write!(f, "Shift"),
+            Modifier::Alt => write!(f, "Alt"),
+            Modifier::Meta => write!(f, "Meta"),
+        }
+    }
+}
+
+/// A source key pressed while exactly `modifiers` are held, e.g. Shift+1,
+/// which presses `button` instead of doing what the key does alone. The
+/// modifier keys still do what they are bound to, or reach the system when
+/// unbound. `modifiers` is sorted, without duplicates, and never empty.
+/// It only takes the key while its `priority` is at least that of the key's
+/// chords and binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortcut {
+    pub modifiers: Vec<Modifier>,
+    pub key: KeyCode,
+    pub button: KeyCode,
+    pub priority: i32,
+}
+
+impl Shortcut {
+    /// Whether `held`, sorted and without duplicates, is exactly the
+    /// shortcut's modifiers.
+    pub fn matches(&self, held: &[Modifier]) -> bool {
+        self.modifiers == held
+    }
+}
+
+impl fmt::Display for Shortcut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in &self.modifiers {
+            write!(f, "{}+", modifier)?;
+        }
+        write!(f, "{}", key_name(self.key))
+    }
+}
+
+/// What a raw evdev key value means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Release,
+    Press,
+    /// Autorepeat while the key is held.
+    Repeat,
+}
+
+impl KeyState {
+    /// Classifies a key event value: 0 release, 1 press, 2 autorepeat.
+    /// Anything else isn't a key state.
+    pub fn from_value(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(KeyState::Release),
+            1 => Some(KeyState::Press),
+            2 => Some(KeyState::Repeat),
+            _ => None,
+        }
+    }
+}
+
+/// Translates a raw evdev key value into the value a binding acts on, or
+/// `None` when the event should be ignored.
+///
+/// Buttons, combos, turbo buttons and held axis, half-press and layer keys pass
+/// presses and releases through and drop autorepeat, since the key is
+/// already known to be held. Everything else only acts on a fresh press.
+/// Forwarded keys don't come through here and keep their repeats.
+pub fn normalize_value(binding: &Binding, raw: i32) -> Option<i32> {
+    let state = KeyState::from_value(raw)?;
+
+    match binding {
+        Binding::Button(_)
+        | Binding::Combo(_)
+        | Binding::Turbo { .. }
+        | Binding::Axis { .. }
+        | Binding::HalfPress
+        | Binding::Layer(_) => (state != KeyState::Repeat).then_some(raw),
+        Binding::AxisToggle { .. }
+        | Binding::Tap(_)
+        | Binding::Sticky
+        | Binding::AxisCycle { .. }
+        | Binding::Exec(_) => (state == KeyState::Press).then_some(raw),
+    }
+}
+
+/// The keyboard bindings of one virtual controller, keyed by source key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mapping {
+    bindings: HashMap<KeyCode, Binding>,
+    /// Bindings that take over while a [`Binding::Layer`] key is held, by
+    /// layer name. Keys a layer doesn't bind keep their usual binding.
+    layers: BTreeMap<String, HashMap<KeyCode, Binding>>,
+    /// Bindings for keys of one source device only, by device path. They
+    /// take the place of the key's usual binding for that device, so a
+    /// macropad's keys can do something else than the same keys on the
+    /// keyboard.
+    devices: BTreeMap<PathBuf, HashMap<KeyCode, Binding>>,
+    /// Priorities of keys' own bindings, base or for one device, where not
+    /// [`DEFAULT_BINDING_PRIORITY`].
+    priorities: HashMap<KeyCode, i32>,
+    /// Priorities of layers, where not [`DEFAULT_LAYER_PRIORITY`].
+    layer_priorities: BTreeMap<String, i32>,
+}
+
+/// Whether `name` can name a layer: non-empty and without whitespace, so it
+/// survives the text form of [`Binding::Layer`].
+pub fn valid_layer_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace)
+}
+
+/// Binding counts broken down by kind, for display in the controller list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SummaryCounts {
+    pub total: usize,
+    pub buttons: usize,
+    pub combos: usize,
+    pub axis_toggles: usize,
+    pub taps: usize,
+    pub sticky_keys: usize,
+    pub axis_cycles: usize,
+    pub turbo_buttons: usize,
+    pub held_axes: usize,
+    pub half_press_keys: usize,
+    pub layer_keys: usize,
+    pub commands: usize,
+    /// Bindings inside layers, which `total` doesn't include.
+    pub layered: usize,
+    /// Bindings for one device only, which `total` doesn't include either.
+    pub per_device: usize,
+}
+
+impl fmt::Display for SummaryCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let noun = if self.total == 1 {
+            "binding"
+        } else {
+            "bindings"
+        };
+        write!(f, "{} {}", self.total, noun)?;
+
+        // Plain buttons are the norm; only call out the other kinds
+        let extras: Vec<String> = [
+            (self.combos, "combo"),
+            (self.axis_toggles, "axis toggle"),
+            (self.taps, "tap"),
+            (self.sticky_keys, "sticky key"),
+            (self.axis_cycles, "axis cycle"),
+            (self.turbo_buttons, "turbo button"),
+            (self.held_axes, "axis key"),
+            (self.half_press_keys, "half-press key"),
+            (self.layer_keys, "layer key"),
+            (self.commands, "command"),
+            (self.layered, "in layers"),
+            (self.per_device, "for one device"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| format!("{} {}", count, kind))
+        .collect();
+
+        if !extras.is_empty() {
+            write!(f, " ({})", extras.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Mapping {
+    pub fn new() -> Self {
+        Mapping::default()
+    }
+
+    pub fn get(&self, key: &KeyCode) -> Option<&Binding> {
+        self.bindings.get(key)
+    }
+
+    pub fn insert(&mut self, key: KeyCode, binding: Binding) -> Option<Binding> {
+        self.bindings.insert(key, binding)
+    }
+
+    pub fn remove(&mut self, key: &KeyCode) -> Option<Binding> {
+        if !self.devices.values().any(|device| device.contains_key(key)) {
+            self.priorities.remove(key);
+        }
+        self.bindings.remove(key)
+    }
+
+    pub fn clear(&mut self) {
+        self.bindings.clear();
+        self.layers.clear();
+        self.devices.clear();
+        self.priorities.clear();
+        self.layer_priorities.clear();
+    }
+
+    /// Priority of `key`'s own binding, base or for one device.
+    pub fn priority(&self, key: &KeyCode) -> i32 {
+        self.priorities
+            .get(key)
+            .copied()
+            .unwrap_or(DEFAULT_BINDING_PRIORITY)
+    }
+
+    pub fn set_priority(&mut self, key: KeyCode, priority: i32) {
+        if priority == DEFAULT_BINDING_PRIORITY {
+            self.priorities.remove(&key);
+        } else {
+            self.priorities.insert(key, priority);
+        }
+    }
+
+    /// The keys whose own binding has a priority other than the default,
+    /// with that priority.
+    pub fn priorities(&self) -> impl Iterator<Item = (KeyCode, i32)> + '_ {
+        self.priorities
+            .iter()
+            .map(|(key, priority)| (*key, *priority))
+    }
+
+    /// Priority of the bindings in the layer `name`.
+    pub fn layer_priority(&self, name: &str) -> i32 {
+        self.layer_priorities
+            .get(name)
+            .copied()
+            .unwrap_or(DEFAULT_LAYER_PRIORITY)
+    }
+
+    pub fn set_layer_priority(&mut self, name: &str, priority: i32) {
+        if priority == DEFAULT_LAYER_PRIORITY {
+            self.layer_priorities.remove(name);
+        } else {
+            self.layer_priorities.insert(name.to_string(), priority);
+        }
+    }
+
+    /// Every key with a binding, in the base mapping, any layer or for any
+    /// device. Keys bound in several places come up more than once.
+    pub fn all_keys(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.bindings
+            .keys()
+            .chain(self.layers.values().flat_map(|layer| layer.keys()))
+            .chain(self.devices.values().flat_map(|device| device.keys()))
+            .copied()
+    }
+
+    /// Every key with a binding when it comes from `device`: all but the
+    /// ones bound for other devices only.
+    pub fn keys_for_device<'a>(&'a self, device: &Path) -> impl Iterator<Item = KeyCode> + 'a {
+        self.bindings
+            .keys()
+            .chain(self.layers.values().flat_map(|layer| layer.keys()))
+            .chain(
+                self.devices
+                    .get(device)
+                    .into_iter()
+                    .flat_map(|keys| keys.keys()),
+            )
+            .copied()
+    }
+
+    /// What `key` does when it comes from `device`, if it is bound for that
+    /// device only.
+    pub fn device_binding(&self, device: &Path, key: &KeyCode) -> Option<&Binding> {
+        self.devices.get(device)?.get(key)
+    }
+
+    /// Binds `key` for keys from `device` only.
+    pub fn insert_device_binding(
+        &mut self,
+        device: &Path,
+        key: KeyCode,
+        binding: Binding,
+    ) -> Option<Binding> {
+        self.devices
+            .entry(device.to_path_buf())
+            .or_default()
+            .insert(key, binding)
+    }
+
+    /// The bindings for one device only, by device path.
+    pub fn device_bindings(&self) -> impl Iterator<Item = (&Path, &HashMap<KeyCode, Binding>)> {
+        self.devices
+            .iter()
+            .map(|(device, bindings)| (device.as_path(), bindings))
+    }
+
+    /// What `key` does while the layer `name` is active, if the layer binds
+    /// it.
+    pub fn layer_binding(&self, name: &str, key: &KeyCode) -> Option<&Binding> {
+        self.layers.get(name)?.get(key)
+    }
+
+    /// Binds `key` in the layer `name`, creating the layer if needed.
+    pub fn insert_layer_binding(
+        &mut self,
+        name: &str,
+        key: KeyCode,
+        binding: Binding,
+    ) -> Option<Binding> {
+        self.layers
+            .entry(name.to_string())
+            .or_default()
+            .insert(key, binding)
+    }
+
+    /// The layers with their bindings, by name.
+    pub fn layers(&self) -> impl Iterator<Item = (&str, &HashMap<KeyCode, Binding>)> {
+        self.layers
+            .iter()
+            .map(|(name, bindings)| (name.as_str(), bindings))
+    }
+
+    /// Names of the layers some key activates or some key is bound in,
+    /// sorted.
+    pub fn layer_names(&self) -> Vec<String> {
+        let names: BTreeSet<&str> = self
+            .bindings
+            .values()
+            .filter_map(|binding| match binding {
+                Binding::Layer(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .chain(self.layers.keys().map(String::as_str))
+            .collect();
+
+        names.into_iter().map(str::to_string).collect()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, KeyCode, Binding> {
+        self.bindings.iter()
+    }
+
+    /// Every binding, in the base mapping, any layer or for any device.
+    pub fn all_bindings_mut(&mut self) -> impl Iterator<Item = &mut Binding> {
+        self.bindings
+            .values_mut()
+            .chain(
+                self.layers
+                    .values_mut()
+                    .flat_map(|layer| layer.values_mut()),
+            )
+            .chain(
+                self.devices
+                    .values_mut()
+                    .flat_map(|device| device.values_mut()),
+            )
+    }
+
+    /// Source keys bound to `button`, sorted by key code.
+    pub fn keys_for_button(&self, button: KeyCode) -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = self
+            .bindings
+            .iter()
+            .filter(|(_, binding)| binding.button() == Some(button))
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort_by_key(|key| key.0);
+
+        keys
+    }
+
+    pub fn summary_counts(&self) -> SummaryCounts {
+        let mut counts = SummaryCounts {
+            total: self.bindings.len(),
+            layered: self.layers.values().map(HashMap::len).sum(),
+            per_device: self.devices.values().map(HashMap::len).sum(),
+            ..SummaryCounts::default()
+        };
+
+        for binding in self.bindings.values() {
+            match binding {
+                Binding::Button(_) => counts.buttons += 1,
+                Binding::Combo(_) => counts.combos += 1,
+                Binding::AxisToggle { .. } => counts.axis_toggles += 1,
+                Binding::Tap(_) => counts.taps += 1,
+                Binding::Sticky => counts.sticky_keys += 1,
+                Binding::AxisCycle { .. } => counts.axis_cycles += 1,
+                Binding::Turbo { .. } => counts.turbo_buttons += 1,
+                Binding::Axis { .. } => counts.held_axes += 1,
+                Binding::HalfPress => counts.half_press_keys += 1,
+                Binding::Layer(_) => counts.layer_keys += 1,
+                Binding::Exec(_) => counts.commands += 1,
+            }
+        }
+
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_rate_is_bounded() {
+        assert_eq!(
+            Binding::parse("turbo 304 1000"),
+            Some(Binding::Turbo {
+                button: KeyCode::BTN_SOUTH,
+                hz: MAX_TURBO_HZ,
+            })
+        );
+        assert_eq!(Binding::parse("turbo 304 0"), None);
+        assert_eq!(Binding::parse("turbo 304 1001"), None);
+        assert_eq!(Binding::parse("turbo 304 4294967295"), None);
+    }
+
+    #[test]
+    fn held_axes_keep_their_ease_in_text() {
+        let eased = Binding::Axis {
+            axis: AbsoluteAxisCode::ABS_X,
+            value: -32768,
+            ramp_ms: 200,
+            deadzone_pct: 25,
+        };
+        assert_eq!(eased.to_string(), "axis 0 -32768 200 25");
+        assert_eq!(eased.to_named_string(), "axis ABS_X -32768 200 25");
+        assert_eq!(Binding::parse(&eased.to_string()), Some(eased.clone()));
+        assert_eq!(Binding::parse_named(&eased.to_named_string()), Some(eased));
+
+        // Older text without a deadzone, or without a ramp, still reads
+        let plain = Binding::Axis {
+            axis: AbsoluteAxisCode::ABS_X,
+            value: 100,
+            ramp_ms: 0,
+            deadzone_pct: 0,
+        };
+        assert_eq!(plain.to_string(), "axis 0 100 0");
+        assert_eq!(Binding::parse("axis 0 100"), Some(plain));
+        assert_eq!(Binding::parse("axis 0 100 200 101"), None);
+    }
+
+    #[test]
+    fn key_values_classify_as_states() {
+        assert_eq!(KeyState::from_value(0), Some(KeyState::Release));
+        assert_eq!(KeyState::from_value(1), Some(KeyState::Press));
+        assert_eq!(KeyState::from_value(2), Some(KeyState::Repeat));
+        for value in [-1, 3, i32::MAX, i32::MIN] {
+            assert_eq!(KeyState::from_value(value), None);
+        }
+    }
+
+    #[test]
+    fn buttons_drop_repeats() {
+        let button = Binding::Button(KeyCode::BTN_SOUTH);
+        assert_eq!(normalize_value(&button, 1), Some(1));
+        assert_eq!(normalize_value(&button, 0), Some(0));
+        assert_eq!(normalize_value(&button, 2), None);
+        assert_eq!(normalize_value(&button, 7), None);
+    }
+}
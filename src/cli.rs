@@ -0,0 +1,193 @@
+use crate::config;
+use crate::error::AppError;
+use anyhow::Result;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: inputmaster [OPTIONS]
+
+Options:
+  --quick                     Map the last used (or first) keyboard to one default controller and
+                              start right away;
+                              $XDG_CONFIG_HOME/inputmaster/default.toml replaces the built-in layout
+  --start                     Start mapping right away without the menus, until interrupted
+  --profile <NAME|FILE>       With --start, load the controllers and settings from a profile
+  --device <DEVICE>           With --start, read from this device (e.g. /dev/input/event3)
+                              instead of the last used keyboard; repeat for several keyboards
+  --compact                   Show mapping status below the menu instead of clearing the screen
+  --quiet                     Hide the banner and skip non-essential pauses
+  --banner <TEXT>             Replace the title shown at the top of the screen
+  --simple                    Use a line-based interface instead of the full-screen one
+  --training                  Show inputs per second and the last few inputs while mapping
+  --hotbar                    While mapping, F1-F4 pick a controller, Right Ctrl+Up/Down adjust its axis scale and Right Ctrl+Space switch it on or off
+  --forward-name <NAME>       Name of the virtual keyboard passing unmapped keys through
+                              (default inputmaster-forward-<keyboard>-<pid>)
+  --pulse-ms <MS>             How long tap bindings hold the button down (default 16)
+  --stop-signals <LIST>       Comma-separated signals that stop mapping, e.g. INT,TERM
+                              (default INT,TERM,HUP)
+  --check-fixture <FILE>      Replay a session fixture and report whether the outputs match
+  --dump-caps <DEVICE>        Print the capabilities of an input device (e.g. /dev/input/event3) and exit
+  --cleanup                   List forwarded keyboards left behind by other sessions and exit
+  --force                     Start even if another instance holds the lock
+  --allow-exec                Let exec bindings run their commands (as this user, often root)
+  -v, --verbose               Log to $XDG_STATE_HOME/inputmaster/log; repeat for more detail
+  -h, --help                  Print this help and exit";
+
+pub const DEFAULT_BANNER: &str = "Keyboard to Controller Mapper";
+
+/// Command-line options. The flag set is small enough that it is parsed by
+/// hand rather than pulling in an argument parsing crate.
+#[derive(Debug)]
+pub struct Options {
+    pub quick: bool,
+    pub start: bool,
+    pub profile: Option<PathBuf>,
+    pub devices: Vec<PathBuf>,
+    pub compact: bool,
+    pub simple: bool,
+    pub quiet: bool,
+    pub banner: String,
+    pub training: bool,
+    pub hotbar: bool,
+    /// The `--pulse-ms` given, if any.
+    pub pulse_ms: Option<u64>,
+    pub forward_name: Option<String>,
+    pub stop_signals: Option<Vec<i32>>,
+    pub check_fixture: Option<PathBuf>,
+    pub dump_caps: Option<PathBuf>,
+    pub cleanup: bool,
+    pub force: bool,
+    pub allow_exec: bool,
+    /// How many times `--verbose` was given.
+    pub verbose: u8,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            quick: false,
+            start: false,
+            profile: None,
+            devices: Vec::new(),
+            compact: false,
+            simple: false,
+            quiet: false,
+            banner: DEFAULT_BANNER.to_string(),
+            training: false,
+            hotbar: false,
+            pulse_ms: None,
+            forward_name: None,
+            stop_signals: None,
+            check_fixture: None,
+            dump_caps: None,
+            cleanup: false,
+            force: false,
+            allow_exec: false,
+            verbose: 0,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse() -> Result<Self> {
+        Self::from_args(std::env::args().skip(1))
+    }
+
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut options = Options::default();
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--quick" => options.quick = true,
+                "--start" => options.start = true,
+                "--profile" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg))?;
+                    options.profile = Some(config::profile_path(&value)?);
+                }
+                "--device" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg))?;
+                    options.devices.push(PathBuf::from(value));
+                }
+                "--compact" => options.compact = true,
+                "--simple" => options.simple = true,
+                "--quiet" => options.quiet = true,
+                "--training" => options.training = true,
+                "--hotbar" => options.hotbar = true,
+                "--allow-exec" => options.allow_exec = true,
+                "--banner" => {
+                    options.banner = args.next().ok_or(AppError::MissingValue(arg))?;
+                }
+                "--pulse-ms" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg.clone()))?;
+                    options.pulse_ms = Some(
+                        value
+                            .parse()
+                            .map_err(|_| AppError::InvalidValue(arg, value))?,
+                    );
+                }
+                "--forward-name" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg.clone()))?;
+                    if value.is_empty() {
+                        return Err(AppError::InvalidValue(arg, value).into());
+                    }
+                    options.forward_name = Some(value);
+                }
+                "--stop-signals" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg.clone()))?;
+                    let signals = value
+                        .split(',')
+                        .map(parse_signal)
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| AppError::InvalidValue(arg, value.clone()))?;
+                    options.stop_signals = Some(signals);
+                }
+                "--check-fixture" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg))?;
+                    options.check_fixture = Some(PathBuf::from(value));
+                }
+                "--dump-caps" => {
+                    let value = args.next().ok_or(AppError::MissingValue(arg))?;
+                    options.dump_caps = Some(PathBuf::from(value));
+                }
+                "--cleanup" => options.cleanup = true,
+                "--force" => options.force = true,
+                "-v" | "--verbose" => options.verbose = options.verbose.saturating_add(1),
+                "-vv" => options.verbose = options.verbose.saturating_add(2),
+                "-h" | "--help" => options.help = true,
+                _ => return Err(AppError::UnknownArgument(arg).into()),
+            }
+        }
+
+        // Both only mean something without the menus, where they replace
+        // the device selection and the profile load
+        if !options.start && !options.quick {
+            if options.profile.is_some() {
+                return Err(AppError::RequiresStart("--profile".to_string()).into());
+            }
+            if !options.devices.is_empty() {
+                return Err(AppError::RequiresStart("--device".to_string()).into());
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// A signal by name, with or without the `SIG` prefix, e.g. `TERM` or
+/// `SIGTERM`. Only signals that make sense for stopping a session are known.
+fn parse_signal(name: &str) -> Option<i32> {
+    let name = name.trim().to_ascii_uppercase();
+    match name.strip_prefix("SIG").unwrap_or(&name) {
+        "INT" => Some(libc::SIGINT),
+        "TERM" => Some(libc::SIGTERM),
+        "HUP" => Some(libc::SIGHUP),
+        "QUIT" => Some(libc::SIGQUIT),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        _ => None,
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+/// Source of the current time for timer-based bindings. The mapping loop uses
+/// the system clock; replay drives a [`ManualClock`] so timers fire at the
+/// same points in the input every time.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The system's monotonic clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Clone, Copy)]
+pub struct ManualClock {
+    now: Instant,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            now: Instant::now(),
+        }
+    }
+
+    /// Moves the clock forward to `time`. Earlier times are ignored, so the
+    /// clock never runs backwards.
+    pub fn advance_to(&mut self, time: Instant) {
+        self.now = self.now.max(time);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn manual_clock_moves_only_forward_when_told() {
+        let mut clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        clock.advance_to(start + Duration::from_millis(50));
+        assert_eq!(clock.now(), start + Duration::from_millis(50));
+
+        clock.advance_to(start);
+        assert_eq!(clock.now(), start + Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::error::AppError;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const APP_DIR_NAME: &str = "inputmaster";
+
+/// Subdirectory of [`config_dir`] holding profiles saved by name.
+const PROFILES_DIR_NAME: &str = "profiles";
+
+const PROFILE_EXTENSION: &str = "toml";
+
+const LOG_FILE_NAME: &str = "log";
+
+const DEFAULT_MAPPING_FILE_NAME: &str = "default.toml";
+
+/// `$XDG_CONFIG_HOME/inputmaster`, or `~/.config/inputmaster` when the
+/// variable isn't set or isn't absolute, as the XDG spec asks. Without a
+/// home directory either it fails, rather than fall back to a shared place
+/// like /tmp where any user could plant symlinks for root to write through.
+pub fn config_dir() -> Result<PathBuf, AppError> {
+    app_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME/inputmaster`, or `~/.local/state/inputmaster`, failing
+/// the same way as [`config_dir`].
+pub fn state_dir() -> Result<PathBuf, AppError> {
+    app_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// This program's directory under the absolute directory in `var`, or under
+/// `home_relative` in the home directory.
+fn app_dir(var: &str, home_relative: &str) -> Result<PathBuf, AppError> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .filter(|home| home.is_absolute())
+                .map(|home| home.join(home_relative))
+        })
+        .map(|base| base.join(APP_DIR_NAME))
+        .ok_or_else(|| {
+            AppError::ConfigDir(
+                format!("${} or $HOME", var),
+                io::Error::new(io::ErrorKind::NotFound, "neither is set"),
+            )
+        })
+}
+
+/// Where `--verbose` writes the log.
+pub fn log_path() -> Result<PathBuf, AppError> {
+    Ok(state_dir()?.join(LOG_FILE_NAME))
+}
+
+/// The profile whose first controller's bindings the first new controller
+/// gets in place of the built-in layout, when the user has written one.
+pub fn default_mapping_path() -> Result<PathBuf, AppError> {
+    Ok(config_dir()?.join(DEFAULT_MAPPING_FILE_NAME))
+}
+
+/// Creates [`config_dir`] and its profiles directory if they are missing,
+/// and returns the config directory.
+pub fn ensure_dir() -> Result<PathBuf, AppError> {
+    let dir = config_dir()?;
+    let profiles = dir.join(PROFILES_DIR_NAME);
+    fs::create_dir_all(&profiles)
+        .map_err(|e| AppError::ConfigDir(profiles.display().to_string(), e))?;
+
+    Ok(dir)
+}
+
+/// Where the profile called `name` lives. A name with a `/` or an extension
+/// in it, or naming a file that exists, is a path taken as it is, so
+/// `racing.toml` and `./racing` mean the current directory. Any other name
+/// is a saved profile: `racing` is `profiles/racing.toml` in [`config_dir`].
+pub fn profile_path(name: &str) -> Result<PathBuf, AppError> {
+    let path = PathBuf::from(name);
+    if name.contains('/') || path.extension().is_some() || path.is_file() {
+        return Ok(path);
+    }
+
+    Ok(config_dir()?
+        .join(PROFILES_DIR_NAME)
+        .join(path)
+        .with_extension(PROFILE_EXTENSION))
+}
+
+/// The profiles saved by name in [`config_dir`], sorted. None when there is
+/// no config directory or nothing was saved yet.
+pub fn saved_profiles() -> Vec<PathBuf> {
+    config_dir()
+        .map(|dir| profiles_in(&dir.join(PROFILES_DIR_NAME)))
+        .unwrap_or_default()
+}
+
+fn profiles_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == PROFILE_EXTENSION))
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names_with_a_slash_or_extension_are_paths() {
+        assert_eq!(
+            profile_path("racing.toml").unwrap(),
+            PathBuf::from("racing.toml")
+        );
+        assert_eq!(profile_path("./racing").unwrap(), PathBuf::from("./racing"));
+        assert_eq!(
+            profile_path("/etc/racing").unwrap(),
+            PathBuf::from("/etc/racing")
+        );
+    }
+
+    #[test]
+    fn existing_files_are_paths() {
+        assert_eq!(profile_path("LICENSE").unwrap(), PathBuf::from("LICENSE"));
+    }
+
+    #[test]
+    fn saved_profiles_are_the_toml_files_by_name() {
+        let dir = std::env::temp_dir().join(format!("inputmaster-profiles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["racing.toml", "flight.toml", "shared.json", "notes"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+
+        assert_eq!(
+            profiles_in(&dir),
+            [dir.join("flight.toml"), dir.join("racing.toml")]
+        );
+        assert!(profiles_in(&dir.join("missing")).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,104 +1,1433 @@
 use anyhow::Result;
-use evdev::{AttributeSet, EventType, InputEvent, KeyCode, uinput::VirtualDevice};
+use evdev::{
+    AbsInfo, AbsoluteAxisCode, AttributeSet, BusType, Device, EventSummary, EventType,
+    FFEffectCode, FFEffectData, FFEffectKind, FFReplay, FFTrigger, InputEvent, InputId, KeyCode,
+    UInputCode, UinputAbsSetup, uinput::VirtualDevice,
+};
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+
+use crate::binding::{Binding, Chord, DoubleTap, Mapping, Shortcut, Target, swap_button};
+use crate::device;
+
+/// Range of the analog stick axes, matching an Xbox-style pad.
+pub const STICK_MIN: i32 = -32768;
+pub const STICK_MAX: i32 = 32767;
+
+/// Fully pulled value of the analog trigger axes. They rest at 0.
+pub const TRIGGER_MAX: i32 = 255;
+
+/// IDs of a wired Xbox 360 controller, which gamepads present themselves
+/// with. SDL and Steam only pick up pads they recognize by ID; with these
+/// they do without a custom mapping. Change them here to pose as another
+/// pad.
+pub const XBOX_360_BUS: BusType = BusType::BUS_USB;
+pub const XBOX_360_VENDOR: u16 = 0x045e;
+pub const XBOX_360_PRODUCT: u16 = 0x028e;
+pub const XBOX_360_VERSION: u16 = 0x0110;
+
+/// Right stick deflection per count of mouse motion, unless set otherwise.
+pub const DEFAULT_MOUSE_SENSITIVITY: f32 = 1000.0;
+
+/// How long the mouse-driven stick holds still after the mouse stops, unless
+/// set otherwise.
+pub const DEFAULT_MOUSE_DECAY_MS: u64 = 50;
+
+/// How long a chord waits for the rest of its keys after the first goes
+/// down, unless set otherwise.
+pub const DEFAULT_CHORD_WINDOW_MS: u64 = 20;
+
+/// Longest a chord may wait for its keys. Every chord key's own binding is
+/// held back that long, so much more would make them feel broken.
+pub const MAX_CHORD_WINDOW_MS: u64 = 500;
+
+/// Most force-feedback effects a game can have uploaded to one controller.
+const FF_EFFECTS_MAX: u32 = 16;
+
+/// Longest name uinput takes, in bytes (UINPUT_MAX_NAME_SIZE less the NUL).
+const DEVICE_NAME_MAX: usize = 79;
+
+/// Device name of a controller whose name has nothing printable left.
+const FALLBACK_DEVICE_NAME: &str = "inputmaster controller";
+
+/// How long the rumble test plays its effect.
+const RUMBLE_TEST_LENGTH: Duration = Duration::from_millis(500);
+
+/// The kind of device a controller presents itself as.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerKind {
+    /// An Xbox-style pad.
+    #[default]
+    Gamepad,
+    /// A gamepad plus flight stick controls: throttle, rudder and the
+    /// `BTN_TRIGGER`..`BTN_BASE4` buttons, for simulators.
+    Joystick,
+}
+
+impl fmt::Display for ControllerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerKind::Gamepad => write!(f, "gamepad"),
+            ControllerKind::Joystick => write!(f, "joystick"),
+        }
+    }
+}
+
+/// Which pad's names a controller gives its buttons. The device poses as an
+/// Xbox 360 pad whatever the layout, so the buttons games see don't change;
+/// the layout picks the labels shown while binding and which buttons the
+/// default mapping's face keys press.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerLayout {
+    /// A, B, X and Y with A at the bottom.
+    #[default]
+    Xbox,
+    /// Cross, circle, square and triangle, in the Xbox buttons' places.
+    PlayStation,
+    /// A, B, X and Y with A on the right, so A and B, and X and Y, trade
+    /// places with the Xbox ones.
+    Switch,
+}
+
+impl ControllerLayout {
+    pub const ALL: [ControllerLayout; 3] = [
+        ControllerLayout::Xbox,
+        ControllerLayout::PlayStation,
+        ControllerLayout::Switch,
+    ];
+
+    /// The button this layout labels the way Xbox labels `button`, e.g.
+    /// `BTN_EAST` for `BTN_SOUTH` on a Switch layout, where A is on the
+    /// right. PlayStation labels have no Xbox letters, so its buttons keep
+    /// their places.
+    pub fn labelled(self, button: KeyCode) -> KeyCode {
+        match (self, button) {
+            (ControllerLayout::Switch, KeyCode::BTN_SOUTH) => KeyCode::BTN_EAST,
+            (ControllerLayout::Switch, KeyCode::BTN_EAST) => KeyCode::BTN_SOUTH,
+            (ControllerLayout::Switch, KeyCode::BTN_NORTH) => KeyCode::BTN_WEST,
+            (ControllerLayout::Switch, KeyCode::BTN_WEST) => KeyCode::BTN_NORTH,
+            _ => button,
+        }
+    }
+
+    /// The gamepad buttons with this layout's names for them.
+    fn buttons(self) -> [(KeyCode, &'static str); 17] {
+        match self {
+            ControllerLayout::Xbox => [
+                (KeyCode::BTN_SOUTH, "A Button"),
+                (KeyCode::BTN_EAST, "B Button"),
+                (KeyCode::BTN_NORTH, "X Button"),
+                (KeyCode::BTN_WEST, "Y Button"),
+                (KeyCode::BTN_TL, "Left Shoulder"),
+                (KeyCode::BTN_TR, "Right Shoulder"),
+                (KeyCode::BTN_TL2, "Left Trigger"),
+                (KeyCode::BTN_TR2, "Right Trigger"),
+                (KeyCode::BTN_SELECT, "Select Button"),
+                (KeyCode::BTN_START, "Start Button"),
+                (KeyCode::BTN_MODE, "Guide Button"),
+                (KeyCode::BTN_THUMBL, "Left Thumb"),
+                (KeyCode::BTN_THUMBR, "Right Thumb"),
+                (KeyCode::BTN_DPAD_UP, "D-Pad Up"),
+                (KeyCode::BTN_DPAD_DOWN, "D-Pad Down"),
+                (KeyCode::BTN_DPAD_LEFT, "D-Pad Left"),
+                (KeyCode::BTN_DPAD_RIGHT, "D-Pad Right"),
+            ],
+            ControllerLayout::PlayStation => [
+                (KeyCode::BTN_SOUTH, "Cross Button"),
+                (KeyCode::BTN_EAST, "Circle Button"),
+                (KeyCode::BTN_NORTH, "Square Button"),
+                (KeyCode::BTN_WEST, "Triangle Button"),
+                (KeyCode::BTN_TL, "L1"),
+                (KeyCode::BTN_TR, "R1"),
+                (KeyCode::BTN_TL2, "L2"),
+                (KeyCode::BTN_TR2, "R2"),
+                (KeyCode::BTN_SELECT, "Share Button"),
+                (KeyCode::BTN_START, "Options Button"),
+                (KeyCode::BTN_MODE, "PS Button"),
+                (KeyCode::BTN_THUMBL, "L3"),
+                (KeyCode::BTN_THUMBR, "R3"),
+                (KeyCode::BTN_DPAD_UP, "D-Pad Up"),
+                (KeyCode::BTN_DPAD_DOWN, "D-Pad Down"),
+                (KeyCode::BTN_DPAD_LEFT, "D-Pad Left"),
+                (KeyCode::BTN_DPAD_RIGHT, "D-Pad Right"),
+            ],
+            ControllerLayout::Switch => [
+                (KeyCode::BTN_EAST, "A Button"),
+                (KeyCode::BTN_SOUTH, "B Button"),
+                (KeyCode::BTN_WEST, "X Button"),
+                (KeyCode::BTN_NORTH, "Y Button"),
+                (KeyCode::BTN_TL, "L Button"),
+                (KeyCode::BTN_TR, "R Button"),
+                (KeyCode::BTN_TL2, "ZL Button"),
+                (KeyCode::BTN_TR2, "ZR Button"),
+                (KeyCode::BTN_SELECT, "Minus Button"),
+                (KeyCode::BTN_START, "Plus Button"),
+                (KeyCode::BTN_MODE, "Home Button"),
+                (KeyCode::BTN_THUMBL, "Left Stick Press"),
+                (KeyCode::BTN_THUMBR, "Right Stick Press"),
+                (KeyCode::BTN_DPAD_UP, "D-Pad Up"),
+                (KeyCode::BTN_DPAD_DOWN, "D-Pad Down"),
+                (KeyCode::BTN_DPAD_LEFT, "D-Pad Left"),
+                (KeyCode::BTN_DPAD_RIGHT, "D-Pad Right"),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for ControllerLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerLayout::Xbox => write!(f, "Xbox"),
+            ControllerLayout::PlayStation => write!(f, "PlayStation"),
+            ControllerLayout::Switch => write!(f, "Switch"),
+        }
+    }
+}
+
+/// Flight stick buttons declared by [`ControllerKind::Joystick`].
+const JOYSTICK_BUTTONS: [(KeyCode, &str); 10] = [
+    (KeyCode::BTN_TRIGGER, "Trigger"),
+    (KeyCode::BTN_THUMB, "Thumb Button"),
+    (KeyCode::BTN_THUMB2, "Thumb Button 2"),
+    (KeyCode::BTN_TOP, "Top Button"),
+    (KeyCode::BTN_TOP2, "Top Button 2"),
+    (KeyCode::BTN_PINKIE, "Pinkie Button"),
+    (KeyCode::BTN_BASE, "Base Button 1"),
+    (KeyCode::BTN_BASE2, "Base Button 2"),
+    (KeyCode::BTN_BASE3, "Base Button 3"),
+    (KeyCode::BTN_BASE4, "Base Button 4"),
+];
+
+/// Prompt order for mapping a new controller: face buttons, D-pad,
+/// shoulders, then the special buttons. Users who only map a few buttons get
+/// to the common ones first.
+pub const COMMON_FIRST_ORDER: [KeyCode; 17] = [
+    KeyCode::BTN_SOUTH,
+    KeyCode::BTN_EAST,
+    KeyCode::BTN_NORTH,
+    KeyCode::BTN_WEST,
+    KeyCode::BTN_DPAD_UP,
+    KeyCode::BTN_DPAD_DOWN,
+    KeyCode::BTN_DPAD_LEFT,
+    KeyCode::BTN_DPAD_RIGHT,
+    KeyCode::BTN_TL,
+    KeyCode::BTN_TR,
+    KeyCode::BTN_TL2,
+    KeyCode::BTN_TR2,
+    KeyCode::BTN_START,
+    KeyCode::BTN_SELECT,
+    KeyCode::BTN_MODE,
+    KeyCode::BTN_THUMBL,
+    KeyCode::BTN_THUMBR,
+];
+
+/// How a controller reports its D-pad. Some emulators only read the hat
+/// axes, while registering both confuses other games, so each controller
+/// uses one or the other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DpadMode {
+    /// `BTN_DPAD_*` buttons.
+    #[default]
+    Buttons,
+    /// `ABS_HAT0X`/`ABS_HAT0Y` axes ranging from -1 to 1.
+    Hat,
+}
+
+impl DpadMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "buttons" => Some(DpadMode::Buttons),
+            "hat" => Some(DpadMode::Hat),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DpadMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DpadMode::Buttons => write!(f, "buttons"),
+            DpadMode::Hat => write!(f, "hat"),
+        }
+    }
+}
+
+/// The name the uinput device is created with for a controller called
+/// `name`: without control characters, which confuse tools that print or
+/// parse device names, and cut to what uinput accepts without splitting a
+/// character. A name with nothing left gets [`FALLBACK_DEVICE_NAME`]. The
+/// controller keeps `name` as its label.
+fn device_name(name: &str) -> String {
+    let mut device_name = String::new();
+    for c in name.chars().filter(|c| !c.is_control()) {
+        if device_name.len() + c.len_utf8() > DEVICE_NAME_MAX {
+            break;
+        }
+        device_name.push(c);
+    }
+
+    if device_name.trim().is_empty() {
+        return FALLBACK_DEVICE_NAME.to_string();
+    }
+    device_name
+}
+
+/// The hat axis and direction a D-pad button stands for in [`DpadMode::Hat`].
+pub fn dpad_hat_direction(button: KeyCode) -> Option<(AbsoluteAxisCode, i32)> {
+    match button {
+        KeyCode::BTN_DPAD_UP => Some((AbsoluteAxisCode::ABS_HAT0Y, -1)),
+        KeyCode::BTN_DPAD_DOWN => Some((AbsoluteAxisCode::ABS_HAT0Y, 1)),
+        KeyCode::BTN_DPAD_LEFT => Some((AbsoluteAxisCode::ABS_HAT0X, -1)),
+        KeyCode::BTN_DPAD_RIGHT => Some((AbsoluteAxisCode::ABS_HAT0X, 1)),
+        _ => None,
+    }
+}
+
+/// The analog axis a trigger button also reports on. Games read either the
+/// `BTN_TL2`/`BTN_TR2` buttons or the `ABS_Z`/`ABS_RZ` axes, so triggers drive
+/// both.
+pub fn analog_trigger(button: KeyCode) -> Option<AbsoluteAxisCode> {
+    match button {
+        KeyCode::BTN_TL2 => Some(AbsoluteAxisCode::ABS_Z),
+        KeyCode::BTN_TR2 => Some(AbsoluteAxisCode::ABS_RZ),
+        _ => None,
+    }
+}
+
+/// Mouse motion driving the right stick, for twin-stick and FPS-style play.
+/// Each frame of motion sets the stick's deflection, so the faster the mouse
+/// moves the further the stick goes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MouseStick {
+    /// Stick units per count of motion.
+    pub sensitivity: f32,
+    /// How long the stick keeps its deflection after the mouse stops before
+    /// it returns to rest.
+    pub decay_ms: u64,
+}
+
+impl Default for MouseStick {
+    fn default() -> Self {
+        MouseStick {
+            sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+            decay_ms: DEFAULT_MOUSE_DECAY_MS,
+        }
+    }
+}
+
+/// Whether `sensitivity` can drive a stick: finite and above 0.
+pub fn valid_mouse_sensitivity(sensitivity: f32) -> bool {
+    sensitivity.is_finite() && sensitivity > 0.0
+}
+
+/// Converts one frame of mouse motion into right stick deflection, capped at
+/// the stick range on each axis.
+pub fn mouse_to_axis(dx: i32, dy: i32, sensitivity: f32) -> (i32, i32) {
+    let deflect = |delta: i32| {
+        (delta as f32 * sensitivity)
+            .round()
+            .clamp(STICK_MIN as f32, STICK_MAX as f32) as i32
+    };
+
+    (deflect(dx), deflect(dy))
+}
+
+/// The two axes of each stick, for rounding their diagonals.
+pub const STICK_AXIS_PAIRS: [(AbsoluteAxisCode, AbsoluteAxisCode); 2] = [
+    (AbsoluteAxisCode::ABS_X, AbsoluteAxisCode::ABS_Y),
+    (AbsoluteAxisCode::ABS_RX, AbsoluteAxisCode::ABS_RY),
+];
+
+/// Pulls a stick deflection of (`x`, `y`) back onto the circle of radius
+/// `max` if it lies outside, keeping its direction. Two keys held together
+/// otherwise push a stick to (max, max), which is further out than any real
+/// stick reaches.
+pub fn normalize_axis_pair(x: i32, y: i32, max: i32) -> (i32, i32) {
+    let length = (x as f64).hypot(y as f64);
+    if length <= max as f64 {
+        return (x, y);
+    }
+
+    let factor = max as f64 / length;
+    (
+        (x as f64 * factor).round() as i32,
+        (y as f64 * factor).round() as i32,
+    )
+}
+
+/// Everything needed to recreate a controller, minus the uinput device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControllerConfig {
+    pub name: String,
+    pub mapping: Mapping,
+    pub sources: Vec<PathBuf>,
+    pub kind: ControllerKind,
+    pub layout: ControllerLayout,
+    pub dpad_mode: DpadMode,
+    /// Multiplier applied to every stick and throttle deflection.
+    pub axis_scale: f32,
+    /// Whether stick diagonals are pulled in to the stick's circle rather
+    /// than reaching the corners of its square. See [`normalize_axis_pair`].
+    pub normalize_diagonals: bool,
+    /// Where axes return to on release, for those that don't rest at their
+    /// default. See [`AxisCapability::rest`].
+    pub axis_rests: HashMap<AbsoluteAxisCode, i32>,
+    /// Mouse motion driving the right stick, if any.
+    pub mouse_stick: Option<MouseStick>,
+    /// Key chords, kept apart from `mapping` as they take over the bindings
+    /// of their keys.
+    pub chords: Vec<Chord>,
+    /// How long a chord waits for the rest of its keys, in milliseconds.
+    pub chord_window_ms: u64,
+    /// Keys that tap a button when pressed twice quickly.
+    pub double_taps: Vec<DoubleTap>,
+    /// Modifier+key shortcuts. Their keys aren't bound by them alone, so the
+    /// key pressed without its modifiers still reaches the system.
+    pub shortcuts: Vec<Shortcut>,
+}
+
+impl ControllerConfig {
+    /// What the controller's device declares, with its rest values applied.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::new(self.kind, self.dpad_mode).with_axis_rests(&self.axis_rests)
+    }
+
+    /// Makes every binding, chord, double-tap and shortcut that pressed
+    /// `button_a` press `button_b` instead, and the other way around.
+    pub fn swap_bindings(&mut self, button_a: KeyCode, button_b: KeyCode) {
+        for binding in self.mapping.all_bindings_mut() {
+            binding.swap_buttons(button_a, button_b);
+        }
+        let buttons = self
+            .chords
+            .iter_mut()
+            .map(|chord| &mut chord.button)
+            .chain(self.double_taps.iter_mut().map(|tap| &mut tap.button))
+            .chain(
+                self.shortcuts
+                    .iter_mut()
+                    .map(|shortcut| &mut shortcut.button),
+            );
+        for button in buttons {
+            swap_button(button, button_a, button_b);
+        }
+    }
+
+    /// Every key some binding, layer, chord or double-tap of the controller
+    /// uses.
+    pub fn bound_keys(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.mapping
+            .all_keys()
+            .chain(
+                self.chords
+                    .iter()
+                    .flat_map(|chord| chord.keys.iter().copied()),
+            )
+            .chain(self.double_taps.iter().map(|double_tap| double_tap.key))
+    }
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        ControllerConfig {
+            name: String::new(),
+            mapping: Mapping::new(),
+            sources: Vec::new(),
+            kind: ControllerKind::default(),
+            layout: ControllerLayout::default(),
+            dpad_mode: DpadMode::default(),
+            axis_scale: 1.0,
+            normalize_diagonals: false,
+            axis_rests: HashMap::new(),
+            mouse_stick: None,
+            chords: Vec::new(),
+            chord_window_ms: DEFAULT_CHORD_WINDOW_MS,
+            double_taps: Vec::new(),
+            shortcuts: Vec::new(),
+        }
+    }
+}
+
+/// Whether a chord can wait `window_ms` for its keys: up to
+/// [`MAX_CHORD_WINDOW_MS`].
+pub fn valid_chord_window(window_ms: u64) -> bool {
+    window_ms <= MAX_CHORD_WINDOW_MS
+}
+
+/// Whether `scale` can be used as an axis scale: finite and not negative.
+/// Flipping an axis isn't what a scale is for.
+pub fn valid_axis_scale(scale: f32) -> bool {
+    scale.is_finite() && scale >= 0.0
+}
+
+/// How hard a game asked a controller to rumble: the magnitudes of the
+/// rumble effect it played, or zero once it stops.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RumbleStrength {
+    pub strong: u16,
+    pub weak: u16,
+}
+
+/// Called with every rumble a game starts or stops, e.g. to forward it to a
+/// real pad.
+pub type RumbleCallback = Box<dyn FnMut(RumbleStrength) + Send>;
 
 pub struct VirtualController {
     pub device: VirtualDevice,
     pub name: String,
-    pub key_mapping: Arc<RwLock<HashMap<KeyCode, KeyCode>>>,
+    pub key_mapping: Arc<RwLock<Mapping>>,
+    /// Source devices this controller reads from; empty means every source.
+    pub sources: Vec<PathBuf>,
+    pub kind: ControllerKind,
+    pub layout: ControllerLayout,
+    pub dpad_mode: DpadMode,
+    /// Multiplier applied to every stick and throttle deflection; 1.0 leaves
+    /// them as bound.
+    pub axis_scale: f32,
+    pub normalize_diagonals: bool,
+    pub axis_rests: HashMap<AbsoluteAxisCode, i32>,
+    pub mouse_stick: Option<MouseStick>,
+    pub chords: Vec<Chord>,
+    pub chord_window_ms: u64,
+    pub double_taps: Vec<DoubleTap>,
+    pub shortcuts: Vec<Shortcut>,
+    /// Whether mapping drives the controller. Shared with a running session,
+    /// whose hotbar can flip it; not saved in profiles.
+    pub enabled: Arc<AtomicBool>,
+    /// What `device` was built with.
+    capabilities: Capabilities,
+    /// Rumble effects the game has uploaded, by effect ID.
+    rumble_effects: HashMap<i16, RumbleStrength>,
+    rumble_callback: Option<RumbleCallback>,
 }
 
 impl VirtualController {
-    pub fn new(name: &str) -> Result<Self> {
-        let mut keys = AttributeSet::<KeyCode>::new();
-
-        keys.insert(KeyCode::BTN_SOUTH); // A
-        keys.insert(KeyCode::BTN_EAST); // B
-        keys.insert(KeyCode::BTN_NORTH); // X
-        keys.insert(KeyCode::BTN_WEST); // Y
-        keys.insert(KeyCode::BTN_TL); // Left Shoulder
-        keys.insert(KeyCode::BTN_TR); // Right Shoulder
-        keys.insert(KeyCode::BTN_SELECT); // Back
-        keys.insert(KeyCode::BTN_START); // Start
-        keys.insert(KeyCode::BTN_MODE); // Guide
-        keys.insert(KeyCode::BTN_THUMBL); // Left Thumb
-        keys.insert(KeyCode::BTN_THUMBR); // Right Thumb
-
-        keys.insert(KeyCode::BTN_DPAD_UP);
-        keys.insert(KeyCode::BTN_DPAD_DOWN);
-        keys.insert(KeyCode::BTN_DPAD_LEFT);
-        keys.insert(KeyCode::BTN_DPAD_RIGHT);
-
-        let device = VirtualDevice::builder()?
-            .name(name)
-            .with_keys(&keys)?
-            .build()?;
-
-        Ok(VirtualController {
-            device,
+    pub fn new(name: &str, layout: ControllerLayout) -> Result<Self> {
+        Self::from_config(&ControllerConfig {
             name: name.to_string(),
-            key_mapping: Arc::new(RwLock::new(HashMap::new())),
+            layout,
+            ..ControllerConfig::default()
         })
     }
 
+    pub fn from_config(config: &ControllerConfig) -> Result<Self> {
+        let capabilities = config.capabilities();
+
+        let mut controller = VirtualController {
+            device: Self::build_device(&config.name, &capabilities)?,
+            name: config.name.clone(),
+            key_mapping: Arc::new(RwLock::new(config.mapping.clone())),
+            sources: config.sources.clone(),
+            kind: config.kind,
+            layout: config.layout,
+            dpad_mode: config.dpad_mode,
+            axis_scale: config.axis_scale,
+            normalize_diagonals: config.normalize_diagonals,
+            axis_rests: config.axis_rests.clone(),
+            mouse_stick: config.mouse_stick,
+            chords: config.chords.clone(),
+            chord_window_ms: config.chord_window_ms,
+            double_taps: config.double_taps.clone(),
+            shortcuts: config.shortcuts.clone(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            capabilities,
+            rumble_effects: HashMap::new(),
+            rumble_callback: None,
+        };
+        controller.release_all()?;
+
+        Ok(controller)
+    }
+
+    /// Sends every axis to its rest value and releases every button in one
+    /// frame. Done when the device is created, so a game opening it reads a
+    /// neutral pad rather than whatever it assumes before the first event,
+    /// and when a session ends, so nothing can stay held.
+    pub fn release_all(&mut self) -> Result<()> {
+        let buttons = self
+            .capabilities
+            .buttons
+            .iter()
+            .map(|button| InputEvent::new(EventType::KEY.0, button.0, 0));
+        let axes = self
+            .capabilities
+            .axes
+            .iter()
+            .map(|axis| InputEvent::new(EventType::ABSOLUTE.0, axis.axis.0, axis.rest));
+        let events: Vec<InputEvent> = buttons.chain(axes).collect();
+        self.device.emit(&events)?;
+
+        Ok(())
+    }
+
+    /// Snapshot of the controller's settings and bindings.
+    pub fn config(&self) -> ControllerConfig {
+        ControllerConfig {
+            name: self.name.clone(),
+            mapping: self.key_mapping.read().clone(),
+            sources: self.sources.clone(),
+            kind: self.kind,
+            layout: self.layout,
+            dpad_mode: self.dpad_mode,
+            axis_scale: self.axis_scale,
+            normalize_diagonals: self.normalize_diagonals,
+            axis_rests: self.axis_rests.clone(),
+            mouse_stick: self.mouse_stick,
+            chords: self.chords.clone(),
+            chord_window_ms: self.chord_window_ms,
+            double_taps: self.double_taps.clone(),
+            shortcuts: self.shortcuts.clone(),
+        }
+    }
+
+    /// Number of keys bound outside layers. Takes the mapping lock only for
+    /// the count.
+    pub fn mapping_count(&self) -> usize {
+        self.key_mapping.read().iter().len()
+    }
+
+    /// Owned copy of the bindings outside layers, sorted by key code, so
+    /// callers can draw them without holding the mapping lock.
+    pub fn bindings_snapshot(&self) -> Vec<(KeyCode, Binding)> {
+        let mut bindings: Vec<(KeyCode, Binding)> = self
+            .key_mapping
+            .read()
+            .iter()
+            .map(|(key, binding)| (*key, binding.clone()))
+            .collect();
+        bindings.sort_by_key(|(key, _)| key.0);
+
+        bindings
+    }
+
+    /// Every key some binding, layer, chord or double-tap of the controller
+    /// uses.
+    pub fn bound_keys(&self) -> Vec<KeyCode> {
+        let mapping = self.key_mapping.read();
+        mapping
+            .all_keys()
+            .chain(
+                self.chords
+                    .iter()
+                    .flat_map(|chord| chord.keys.iter().copied()),
+            )
+            .chain(self.double_taps.iter().map(|double_tap| double_tap.key))
+            .collect()
+    }
+
+    /// Switches the D-pad representation. The uinput device is recreated,
+    /// since its capabilities can't change once built.
+    pub fn set_dpad_mode(&mut self, mode: DpadMode) -> Result<()> {
+        let capabilities = Capabilities::new(self.kind, mode).with_axis_rests(&self.axis_rests);
+        self.device = Self::build_device(&self.name, &capabilities)?;
+        self.capabilities = capabilities;
+        self.dpad_mode = mode;
+
+        self.release_all()
+    }
+
+    /// Switches the kind of device presented, recreating the uinput device.
+    pub fn set_kind(&mut self, kind: ControllerKind) -> Result<()> {
+        let capabilities =
+            Capabilities::new(kind, self.dpad_mode).with_axis_rests(&self.axis_rests);
+        self.device = Self::build_device(&self.name, &capabilities)?;
+        self.capabilities = capabilities;
+        self.kind = kind;
+
+        self.release_all()
+    }
+
+    /// The buttons and axes the uinput device declares.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Whether a binding to `button` produces output. In hat mode D-pad
+    /// buttons come out on the hat axes.
+    pub fn supports_button(&self, button: KeyCode) -> bool {
+        match dpad_hat_direction(button) {
+            Some((axis, _)) if self.dpad_mode == DpadMode::Hat => {
+                self.capabilities().has_axis(axis)
+            }
+            _ => self.capabilities().has_button(button),
+        }
+    }
+
+    /// Source keys whose binding drives something this controller doesn't
+    /// declare, e.g. a joystick button on a gamepad. Sorted by key code.
+    pub fn unsupported_bindings(&self) -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = self
+            .key_mapping
+            .read()
+            .iter()
+            .filter(|(_, binding)| {
+                binding.targets().into_iter().any(|target| match target {
+                    Target::Button(button) => !self.supports_button(button),
+                    Target::Axis(axis) => !self.capabilities().has_axis(axis),
+                })
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort_by_key(|key| key.0);
+
+        keys
+    }
+
+    fn build_device(name: &str, capabilities: &Capabilities) -> Result<VirtualDevice> {
+        let keys: AttributeSet<KeyCode> = capabilities.buttons.iter().copied().collect();
+        let device_name = device_name(name);
+        let mut builder = VirtualDevice::builder()
+            .map_err(device::uinput_error)?
+            .name(&device_name)
+            .with_phys(device::VIRTUAL_PHYS)?
+            .with_keys(&keys)?;
+        if let Some(id) = &capabilities.id {
+            builder = builder.input_id(id.clone());
+        }
+
+        for capability in &capabilities.axes {
+            let info = AbsInfo::new(
+                capability.rest,
+                capability.min,
+                capability.max,
+                capability.fuzz,
+                capability.flat,
+                0,
+            );
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(capability.axis, info))?;
+        }
+
+        if !capabilities.force_feedback.is_empty() {
+            let effects: AttributeSet<FFEffectCode> =
+                capabilities.force_feedback.iter().copied().collect();
+            builder = builder
+                .with_ff(&effects)?
+                .with_ff_effects_max(FF_EFFECTS_MAX);
+        }
+
+        let device = builder.build()?;
+
+        // Force-feedback requests are polled for, so reading them must not
+        // block when there are none
+        // SAFETY: the fd belongs to `device` and stays open for the call.
+        unsafe {
+            let flags = libc::fcntl(device.as_raw_fd(), libc::F_GETFL);
+            if flags < 0
+                || libc::fcntl(device.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+            {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(device)
+    }
+
+    /// Has `callback` called whenever a game starts or stops a rumble effect
+    /// on this controller. It runs on whichever thread answers
+    /// force-feedback requests, so it should return quickly.
+    pub fn on_rumble(&mut self, callback: impl FnMut(RumbleStrength) + Send + 'static) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    /// Answers pending force-feedback requests so a game's effect uploads
+    /// succeed instead of waiting out the kernel's timeout. Effects are
+    /// accepted but not played anywhere; the rumble callback, if any, hears
+    /// when they start and stop.
+    pub fn answer_force_feedback(&mut self) -> Result<()> {
+        loop {
+            let events: Vec<InputEvent> = match self.device.fetch_events() {
+                Ok(events) => events.collect(),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+
+            for event in events {
+                // Dropping the processed request reports success to the kernel
+                match event.destructure() {
+                    EventSummary::UInput(event, UInputCode::UI_FF_UPLOAD, ..) => {
+                        let upload = self.device.process_ff_upload(event)?;
+                        if let FFEffectKind::Rumble {
+                            strong_magnitude,
+                            weak_magnitude,
+                        } = upload.effect().kind
+                        {
+                            self.rumble_effects.insert(
+                                upload.effect_id(),
+                                RumbleStrength {
+                                    strong: strong_magnitude,
+                                    weak: weak_magnitude,
+                                },
+                            );
+                        }
+                    }
+                    EventSummary::UInput(event, UInputCode::UI_FF_ERASE, ..) => {
+                        let erase = self.device.process_ff_erase(event)?;
+                        self.rumble_effects.remove(&(erase.effect_id() as i16));
+                    }
+                    // The code is the effect's ID and the value how many
+                    // times to play it, 0 to stop
+                    EventSummary::ForceFeedback(_, code, value) => {
+                        let Some(&strength) = self.rumble_effects.get(&(code.0 as i16)) else {
+                            continue;
+                        };
+                        if let Some(callback) = &mut self.rumble_callback {
+                            callback(if value > 0 {
+                                strength
+                            } else {
+                                RumbleStrength::default()
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Uploads a short rumble effect through the controller's event node and
+    /// plays it, the way a game would. Fails if the device rejects the
+    /// upload or playback.
+    pub fn test_rumble(&mut self) -> Result<()> {
+        let node = self
+            .device
+            .enumerate_dev_nodes_blocking()?
+            .filter_map(|node| node.ok())
+            .find(|node| {
+                node.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+            })
+            .ok_or_else(|| anyhow::anyhow!("{} has no event node", self.name))?;
+
+        // The upload blocks until answered, so it runs on its own thread
+        // while this one answers
+        let player = thread::spawn(move || -> Result<()> {
+            let mut device = Device::open(&node)?;
+            let mut effect = device.upload_ff_effect(FFEffectData {
+                direction: 0,
+                trigger: FFTrigger::default(),
+                replay: FFReplay {
+                    length: RUMBLE_TEST_LENGTH.as_millis() as u16,
+                    delay: 0,
+                },
+                kind: FFEffectKind::Rumble {
+                    strong_magnitude: 0xc000,
+                    weak_magnitude: 0xc000,
+                },
+            })?;
+
+            effect.play(1)?;
+            thread::sleep(RUMBLE_TEST_LENGTH);
+            effect.stop()?;
+
+            Ok(())
+        });
+
+        while !player.is_finished() {
+            self.answer_force_feedback()?;
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        player.join().expect("Failed to join rumble test thread")
+    }
+
+    /// Binds the built-in layout: WASD and the arrows to the D-pad, IJKL to
+    /// the face buttons, Q/E to the shoulders.
     pub fn apply_default_mapping(&mut self) {
         let mut mapping = self.key_mapping.write();
         mapping.clear();
 
         // WASD for D-pad
-        mapping.insert(KeyCode::KEY_W, KeyCode::BTN_DPAD_UP);
-        mapping.insert(KeyCode::KEY_S, KeyCode::BTN_DPAD_DOWN);
-        mapping.insert(KeyCode::KEY_A, KeyCode::BTN_DPAD_LEFT);
-        mapping.insert(KeyCode::KEY_D, KeyCode::BTN_DPAD_RIGHT);
+        mapping.insert(KeyCode::KEY_W, Binding::Button(KeyCode::BTN_DPAD_UP));
+        mapping.insert(KeyCode::KEY_S, Binding::Button(KeyCode::BTN_DPAD_DOWN));
+        mapping.insert(KeyCode::KEY_A, Binding::Button(KeyCode::BTN_DPAD_LEFT));
+        mapping.insert(KeyCode::KEY_D, Binding::Button(KeyCode::BTN_DPAD_RIGHT));
 
         // Arrow keys also for D-pad
-        mapping.insert(KeyCode::KEY_UP, KeyCode::BTN_DPAD_UP);
-        mapping.insert(KeyCode::KEY_DOWN, KeyCode::BTN_DPAD_DOWN);
-        mapping.insert(KeyCode::KEY_LEFT, KeyCode::BTN_DPAD_LEFT);
-        mapping.insert(KeyCode::KEY_RIGHT, KeyCode::BTN_DPAD_RIGHT);
+        mapping.insert(KeyCode::KEY_UP, Binding::Button(KeyCode::BTN_DPAD_UP));
+        mapping.insert(KeyCode::KEY_DOWN, Binding::Button(KeyCode::BTN_DPAD_DOWN));
+        mapping.insert(KeyCode::KEY_LEFT, Binding::Button(KeyCode::BTN_DPAD_LEFT));
+        mapping.insert(KeyCode::KEY_RIGHT, Binding::Button(KeyCode::BTN_DPAD_RIGHT));
 
-        // Face buttons
-        mapping.insert(KeyCode::KEY_K, KeyCode::BTN_SOUTH); // A
-        mapping.insert(KeyCode::KEY_L, KeyCode::BTN_EAST); // B
-        mapping.insert(KeyCode::KEY_I, KeyCode::BTN_NORTH); // X
-        mapping.insert(KeyCode::KEY_J, KeyCode::BTN_WEST); // Y
+        // Face buttons, by label, so K is A on every layout that has one
+        let face = |button| Binding::Button(self.layout.labelled(button));
+        mapping.insert(KeyCode::KEY_K, face(KeyCode::BTN_SOUTH)); // A
+        mapping.insert(KeyCode::KEY_L, face(KeyCode::BTN_EAST)); // B
+        mapping.insert(KeyCode::KEY_I, face(KeyCode::BTN_NORTH)); // X
+        mapping.insert(KeyCode::KEY_J, face(KeyCode::BTN_WEST)); // Y
 
         // Shoulders
-        mapping.insert(KeyCode::KEY_Q, KeyCode::BTN_TL); // Left Shoulder
-        mapping.insert(KeyCode::KEY_E, KeyCode::BTN_TR); // Right Shoulder
+        mapping.insert(KeyCode::KEY_Q, Binding::Button(KeyCode::BTN_TL)); // Left Shoulder
+        mapping.insert(KeyCode::KEY_E, Binding::Button(KeyCode::BTN_TR)); // Right Shoulder
 
         // Special buttons
-        mapping.insert(KeyCode::KEY_TAB, KeyCode::BTN_SELECT); // Back
-        mapping.insert(KeyCode::KEY_ENTER, KeyCode::BTN_START); // Start
-        mapping.insert(KeyCode::KEY_SPACE, KeyCode::BTN_MODE); // Guide
+        mapping.insert(KeyCode::KEY_TAB, Binding::Button(KeyCode::BTN_SELECT)); // Back
+        mapping.insert(KeyCode::KEY_ENTER, Binding::Button(KeyCode::BTN_START)); // Start
+        mapping.insert(KeyCode::KEY_SPACE, Binding::Button(KeyCode::BTN_MODE)); // Guide
+    }
+
+    /// Exchanges the keyboard keys bound to `button_a` and `button_b`, in
+    /// layers, chords, double-taps and shortcuts too. If only one of the
+    /// buttons is bound, its keys move over to the other button.
+    pub fn swap_bindings(&mut self, button_a: KeyCode, button_b: KeyCode) {
+        let mut config = self.config();
+        config.swap_bindings(button_a, button_b);
+
+        *self.key_mapping.write() = config.mapping;
+        self.chords = config.chords;
+        self.double_taps = config.double_taps;
+        self.shortcuts = config.shortcuts;
+    }
+
+    /// Replaces the bindings with each of `buttons` the controller has bound
+    /// to itself, so a gamepad source comes through unchanged until some of
+    /// its buttons are swapped or rebound. Returns how many were bound.
+    pub fn mirror_buttons(&mut self, buttons: impl IntoIterator<Item = KeyCode>) -> usize {
+        let mut mapping = Mapping::new();
+        let mut count = 0;
+        for button in buttons {
+            if self.supports_button(button) {
+                mapping.insert(button, Binding::Button(button));
+                count += 1;
+            }
+        }
+
+        *self.key_mapping.write() = mapping;
+        count
+    }
+
+    /// Replaces the keys bound to `button` with `key`, or leaves the button
+    /// unbound when `key` is `None`. Whatever `key` was bound to before is
+    /// replaced.
+    pub fn rebind(&mut self, button: KeyCode, key: Option<KeyCode>) {
+        let mut mapping = self.key_mapping.write();
+        for old in mapping.keys_for_button(button) {
+            mapping.remove(&old);
+        }
+
+        if let Some(key) = key {
+            mapping.insert(key, Binding::Button(button));
+        }
     }
 
-    pub fn handle_key_event(&mut self, controller_key: KeyCode, value: i32) -> Result<()> {
-        let events = [InputEvent::new(EventType::KEY.0, controller_key.0, value)];
+    /// Sets each target to its value in a single write, ended by one
+    /// SYN_REPORT, so a game sees the changes as one frame.
+    pub fn emit_frame(&mut self, frame: &[(Target, i32)]) -> Result<()> {
+        let events: Vec<InputEvent> = frame
+            .iter()
+            .map(|&(target, value)| match target {
+                Target::Button(button) => InputEvent::new(EventType::KEY.0, button.0, value),
+                Target::Axis(axis) => InputEvent::new(EventType::ABSOLUTE.0, axis.0, value),
+            })
+            .collect();
         self.device.emit(&events)?;
 
         Ok(())
     }
 
-    pub fn get_available_button_mappings() -> Vec<(KeyCode, &'static str)> {
-        vec![
-            (KeyCode::BTN_SOUTH, "A Button"),
-            (KeyCode::BTN_EAST, "B Button"),
-            (KeyCode::BTN_NORTH, "X Button"),
-            (KeyCode::BTN_WEST, "Y Button"),
-            (KeyCode::BTN_TL, "Left Shoulder"),
-            (KeyCode::BTN_TR, "Right Shoulder"),
-            (KeyCode::BTN_SELECT, "Select Button"),
-            (KeyCode::BTN_START, "Start Button"),
-            (KeyCode::BTN_MODE, "Guide Button"),
-            (KeyCode::BTN_THUMBL, "Left Thumb"),
-            (KeyCode::BTN_THUMBR, "Right Thumb"),
-            (KeyCode::BTN_DPAD_UP, "D-Pad Up"),
-            (KeyCode::BTN_DPAD_DOWN, "D-Pad Down"),
-            (KeyCode::BTN_DPAD_LEFT, "D-Pad Left"),
-            (KeyCode::BTN_DPAD_RIGHT, "D-Pad Right"),
+    /// Axis directions that can be driven from a key, with the axis value
+    /// each one deflects to. Only axes the device declares are offered.
+    pub fn get_available_axis_targets(&self) -> Vec<(AbsoluteAxisCode, i32, &'static str)> {
+        let targets = [
+            (AbsoluteAxisCode::ABS_X, STICK_MIN, "Left Stick Left"),
+            (AbsoluteAxisCode::ABS_X, STICK_MAX, "Left Stick Right"),
+            (AbsoluteAxisCode::ABS_Y, STICK_MIN, "Left Stick Up"),
+            (AbsoluteAxisCode::ABS_Y, STICK_MAX, "Left Stick Down"),
+            (AbsoluteAxisCode::ABS_RX, STICK_MIN, "Right Stick Left"),
+            (AbsoluteAxisCode::ABS_RX, STICK_MAX, "Right Stick Right"),
+            (AbsoluteAxisCode::ABS_RY, STICK_MIN, "Right Stick Up"),
+            (AbsoluteAxisCode::ABS_RY, STICK_MAX, "Right Stick Down"),
+            (AbsoluteAxisCode::ABS_THROTTLE, STICK_MAX, "Throttle Full"),
+            (AbsoluteAxisCode::ABS_RUDDER, STICK_MIN, "Rudder Left"),
+            (AbsoluteAxisCode::ABS_RUDDER, STICK_MAX, "Rudder Right"),
+        ];
+
+        targets
+            .into_iter()
+            .filter(|(axis, _, _)| self.capabilities().has_axis(*axis))
+            .collect()
+    }
+
+    /// The available buttons with those listed in `order` moved to the
+    /// front, in that order. Buttons not in `order` follow in their usual
+    /// order, so a partial list is fine.
+    pub fn buttons_in_order(&self, order: &[KeyCode]) -> Vec<(KeyCode, &'static str)> {
+        let mut buttons = self.get_available_button_mappings();
+        buttons.sort_by_key(|(button, _)| {
+            order
+                .iter()
+                .position(|ordered| ordered == button)
+                .unwrap_or(order.len())
+        });
+
+        buttons
+    }
+
+    /// Buttons that can be bound, named for the controller's layout and
+    /// limited to what the device declares.
+    pub fn get_available_button_mappings(&self) -> Vec<(KeyCode, &'static str)> {
+        self.layout
+            .buttons()
+            .into_iter()
+            .chain(JOYSTICK_BUTTONS)
+            .filter(|(button, _)| self.supports_button(*button))
+            .collect()
+    }
+}
+
+/// A range declared for an absolute axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisCapability {
+    pub axis: AbsoluteAxisCode,
+    pub min: i32,
+    pub max: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    /// The value the axis returns to when released.
+    pub rest: i32,
+}
+
+impl AxisCapability {
+    /// Rests at 0, or at `min` for axes like triggers and throttles whose
+    /// range starts there.
+    fn new(axis: AbsoluteAxisCode, min: i32, max: i32, fuzz: i32, flat: i32) -> Self {
+        AxisCapability {
+            axis,
+            min,
+            max,
+            fuzz,
+            flat,
+            rest: 0.clamp(min, max),
+        }
+    }
+}
+
+/// The buttons and axes a controller's uinput device declares. Events for
+/// anything else are silently dropped by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub buttons: Vec<KeyCode>,
+    pub axes: Vec<AxisCapability>,
+    pub force_feedback: Vec<FFEffectCode>,
+    /// Bus, vendor and product the device reports; `None` leaves evdev's
+    /// placeholder IDs.
+    pub id: Option<InputId>,
+}
+
+impl Capabilities {
+    /// What a controller of `kind` in `dpad_mode` declares.
+    pub fn new(kind: ControllerKind, dpad_mode: DpadMode) -> Self {
+        let mut buttons = vec![
+            KeyCode::BTN_SOUTH,  // A
+            KeyCode::BTN_EAST,   // B
+            KeyCode::BTN_NORTH,  // X
+            KeyCode::BTN_WEST,   // Y
+            KeyCode::BTN_TL,     // Left Shoulder
+            KeyCode::BTN_TR,     // Right Shoulder
+            KeyCode::BTN_TL2,    // Left Trigger
+            KeyCode::BTN_TR2,    // Right Trigger
+            KeyCode::BTN_SELECT, // Back
+            KeyCode::BTN_START,  // Start
+            KeyCode::BTN_MODE,   // Guide
+            KeyCode::BTN_THUMBL, // Left Thumb
+            KeyCode::BTN_THUMBR, // Right Thumb
+        ];
+
+        if dpad_mode == DpadMode::Buttons {
+            buttons.extend([
+                KeyCode::BTN_DPAD_UP,
+                KeyCode::BTN_DPAD_DOWN,
+                KeyCode::BTN_DPAD_LEFT,
+                KeyCode::BTN_DPAD_RIGHT,
+            ]);
+        }
+
+        if kind == ControllerKind::Joystick {
+            buttons.extend(JOYSTICK_BUTTONS.map(|(button, _)| button));
+        }
+
+        // Left and right analog sticks
+        let mut axes: Vec<AxisCapability> = [
+            AbsoluteAxisCode::ABS_X,
+            AbsoluteAxisCode::ABS_Y,
+            AbsoluteAxisCode::ABS_RX,
+            AbsoluteAxisCode::ABS_RY,
         ]
+        .into_iter()
+        .map(|axis| AxisCapability::new(axis, STICK_MIN, STICK_MAX, 16, 128))
+        .collect();
+
+        // Analog triggers
+        for axis in [AbsoluteAxisCode::ABS_Z, AbsoluteAxisCode::ABS_RZ] {
+            axes.push(AxisCapability::new(axis, 0, TRIGGER_MAX, 0, 0));
+        }
+
+        if dpad_mode == DpadMode::Hat {
+            axes.push(AxisCapability::new(
+                AbsoluteAxisCode::ABS_HAT0X,
+                -1,
+                1,
+                0,
+                0,
+            ));
+            axes.push(AxisCapability::new(
+                AbsoluteAxisCode::ABS_HAT0Y,
+                -1,
+                1,
+                0,
+                0,
+            ));
+        }
+
+        if kind == ControllerKind::Joystick {
+            // Throttle rests at idle rather than centered
+            axes.push(AxisCapability::new(
+                AbsoluteAxisCode::ABS_THROTTLE,
+                0,
+                STICK_MAX,
+                0,
+                0,
+            ));
+            axes.push(AxisCapability::new(
+                AbsoluteAxisCode::ABS_RUDDER,
+                STICK_MIN,
+                STICK_MAX,
+                16,
+                128,
+            ));
+        }
+
+        // A joystick has more than an Xbox pad, so it would be misread as one
+        let id = (kind == ControllerKind::Gamepad).then(|| {
+            InputId::new(
+                XBOX_360_BUS,
+                XBOX_360_VENDOR,
+                XBOX_360_PRODUCT,
+                XBOX_360_VERSION,
+            )
+        });
+
+        Capabilities {
+            buttons,
+            axes,
+            force_feedback: vec![FFEffectCode::FF_RUMBLE],
+            id,
+        }
+    }
+
+    pub fn has_button(&self, button: KeyCode) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    pub fn has_axis(&self, axis: AbsoluteAxisCode) -> bool {
+        self.axis(axis).is_some()
+    }
+
+    pub fn axis(&self, axis: AbsoluteAxisCode) -> Option<&AxisCapability> {
+        self.axes.iter().find(|capability| capability.axis == axis)
+    }
+
+    /// Replaces the rest value of each declared axis in `rests`. Axes the
+    /// device doesn't declare are ignored.
+    pub fn with_axis_rests(mut self, rests: &HashMap<AbsoluteAxisCode, i32>) -> Self {
+        for capability in &mut self.axes {
+            if let Some(rest) = rests.get(&capability.axis) {
+                capability.rest = *rest;
+            }
+        }
+
+        self
+    }
+}
+
+/// Whether `rest` can be where `axis` returns to on a controller with
+/// `capabilities`: the axis must be declared and `rest` within its range. The
+/// D-pad hat always centers, as its position is the sum of held directions.
+pub fn valid_axis_rest(capabilities: &Capabilities, axis: AbsoluteAxisCode, rest: i32) -> bool {
+    !matches!(
+        axis,
+        AbsoluteAxisCode::ABS_HAT0X | AbsoluteAxisCode::ABS_HAT0Y
+    ) && capabilities
+        .axis(axis)
+        .is_some_and(|capability| (capability.min..=capability.max).contains(&rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binding::{DEFAULT_CHORD_PRIORITY, DEFAULT_SHORTCUT_PRIORITY, Modifier};
+    use std::path::Path;
+
+    fn rest(capabilities: &Capabilities, axis: AbsoluteAxisCode) -> i32 {
+        capabilities.axis(axis).unwrap().rest
+    }
+
+    #[test]
+    fn axes_rest_by_type() {
+        let capabilities = Capabilities::new(ControllerKind::Joystick, DpadMode::Hat);
+
+        // Sticks, the rudder and the hat center; triggers and the throttle
+        // rest at the bottom of their range
+        for axis in [
+            AbsoluteAxisCode::ABS_X,
+            AbsoluteAxisCode::ABS_RY,
+            AbsoluteAxisCode::ABS_RUDDER,
+            AbsoluteAxisCode::ABS_HAT0X,
+        ] {
+            assert_eq!(rest(&capabilities, axis), 0);
+        }
+        for axis in [
+            AbsoluteAxisCode::ABS_Z,
+            AbsoluteAxisCode::ABS_RZ,
+            AbsoluteAxisCode::ABS_THROTTLE,
+        ] {
+            assert_eq!(
+                rest(&capabilities, axis),
+                capabilities.axis(axis).unwrap().min
+            );
+        }
+    }
+
+    #[test]
+    fn rests_stay_within_each_axis_range() {
+        let capabilities = Capabilities::new(ControllerKind::Joystick, DpadMode::Hat);
+
+        assert!(valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_X,
+            STICK_MIN
+        ));
+        assert!(valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_X,
+            STICK_MAX
+        ));
+        assert!(valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_Z,
+            TRIGGER_MAX
+        ));
+        assert!(!valid_axis_rest(&capabilities, AbsoluteAxisCode::ABS_Z, -1));
+        assert!(!valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_Z,
+            TRIGGER_MAX + 1
+        ));
+        assert!(valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_THROTTLE,
+            STICK_MAX / 2
+        ));
+        assert!(!valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_THROTTLE,
+            -1
+        ));
+
+        // The hat always centers
+        assert!(!valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_HAT0X,
+            0
+        ));
+        assert!(!valid_axis_rest(
+            &capabilities,
+            AbsoluteAxisCode::ABS_HAT0Y,
+            1
+        ));
+
+        // A gamepad has no throttle to rest
+        let gamepad = Capabilities::new(ControllerKind::Gamepad, DpadMode::Buttons);
+        assert!(!valid_axis_rest(
+            &gamepad,
+            AbsoluteAxisCode::ABS_THROTTLE,
+            0
+        ));
+    }
+
+    #[test]
+    fn set_rests_replace_the_defaults() {
+        let rests = HashMap::from([
+            (AbsoluteAxisCode::ABS_Z, 128),
+            (AbsoluteAxisCode::ABS_THROTTLE, 100),
+        ]);
+        let capabilities =
+            Capabilities::new(ControllerKind::Gamepad, DpadMode::Buttons).with_axis_rests(&rests);
+
+        assert_eq!(rest(&capabilities, AbsoluteAxisCode::ABS_Z), 128);
+        assert_eq!(rest(&capabilities, AbsoluteAxisCode::ABS_RZ), 0);
+        assert!(!capabilities.has_axis(AbsoluteAxisCode::ABS_THROTTLE));
+    }
+
+    #[test]
+    fn mouse_motion_scales_and_caps_at_the_stick_range() {
+        assert_eq!(mouse_to_axis(0, 0, 1000.0), (0, 0));
+        assert_eq!(mouse_to_axis(3, -2, 1000.0), (3000, -2000));
+        assert_eq!(mouse_to_axis(1, 1, 0.4), (0, 0));
+        assert_eq!(mouse_to_axis(1, -1, 0.6), (1, -1));
+        assert_eq!(mouse_to_axis(100, -100, 1000.0), (STICK_MAX, STICK_MIN));
+        assert_eq!(
+            mouse_to_axis(i32::MAX, i32::MIN, f32::MAX),
+            (STICK_MAX, STICK_MIN)
+        );
+    }
+
+    #[test]
+    fn device_names_drop_control_characters() {
+        assert_eq!(device_name("Pad\n1\t\u{1b}[31m"), "Pad1[31m");
+        assert_eq!(device_name("Player 🎮 One"), "Player 🎮 One");
+    }
+
+    #[test]
+    fn device_names_are_cut_to_whole_characters() {
+        let ascii = "a".repeat(100);
+        assert_eq!(device_name(&ascii).len(), DEVICE_NAME_MAX);
+
+        // Three-byte characters: 26 of them fill 78 bytes and a 27th won't fit
+        let wide = "€".repeat(40);
+        let cut = device_name(&wide);
+        assert_eq!(cut, "€".repeat(26));
+        assert!(cut.len() <= DEVICE_NAME_MAX);
+
+        let mixed = format!("{}{}", "a".repeat(78), "€");
+        assert_eq!(device_name(&mixed), "a".repeat(78));
+    }
+
+    #[test]
+    fn empty_device_names_fall_back() {
+        for name in ["", "\n\t", "   "] {
+            assert_eq!(device_name(name), FALLBACK_DEVICE_NAME);
+        }
+    }
+
+    #[test]
+    fn diagonals_land_on_the_circle() {
+        let (x, y) = normalize_axis_pair(STICK_MAX, STICK_MAX, STICK_MAX);
+        assert_eq!((x, y), (23170, 23170));
+        assert!(((x as f64).hypot(y as f64) - STICK_MAX as f64).abs() < 1.0);
+
+        let (x, y) = normalize_axis_pair(STICK_MIN, STICK_MAX, STICK_MAX);
+        assert_eq!((x, y), (-23170, 23169));
+
+        // Within the circle, and straight along an axis, nothing changes
+        assert_eq!(normalize_axis_pair(1000, -1000, STICK_MAX), (1000, -1000));
+        assert_eq!(normalize_axis_pair(STICK_MAX, 0, STICK_MAX), (STICK_MAX, 0));
+        assert_eq!(normalize_axis_pair(0, 0, STICK_MAX), (0, 0));
+    }
+
+    #[test]
+    fn swapping_buttons_moves_every_binding_of_them() {
+        let macropad = Path::new("/dev/input/event7");
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.insert(
+            KeyCode::KEY_L,
+            Binding::Turbo {
+                button: KeyCode::BTN_SOUTH,
+                hz: 10,
+            },
+        );
+        mapping.insert(
+            KeyCode::KEY_U,
+            Binding::Combo(vec![KeyCode::BTN_EAST, KeyCode::BTN_START]),
+        );
+        mapping.insert_layer_binding("alt", KeyCode::KEY_J, Binding::Tap(KeyCode::BTN_EAST));
+        mapping.insert_device_binding(
+            macropad,
+            KeyCode::KEY_1,
+            Binding::Button(KeyCode::BTN_SOUTH),
+        );
+        let mut config = ControllerConfig {
+            mapping,
+            chords: vec![Chord {
+                keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                button: KeyCode::BTN_SOUTH,
+                priority: DEFAULT_CHORD_PRIORITY,
+            }],
+            double_taps: vec![DoubleTap {
+                key: KeyCode::KEY_H,
+                button: KeyCode::BTN_EAST,
+                window_ms: 250,
+                pulse_ms: 16,
+            }],
+            shortcuts: vec![Shortcut {
+                modifiers: vec![Modifier::Ctrl],
+                key: KeyCode::KEY_S,
+                button: KeyCode::BTN_SOUTH,
+                priority: DEFAULT_SHORTCUT_PRIORITY,
+            }],
+            ..ControllerConfig::default()
+        };
+
+        config.swap_bindings(KeyCode::BTN_SOUTH, KeyCode::BTN_EAST);
+
+        let mapping = &config.mapping;
+        assert_eq!(
+            mapping.get(&KeyCode::KEY_J),
+            Some(&Binding::Button(KeyCode::BTN_EAST))
+        );
+        assert_eq!(
+            mapping.get(&KeyCode::KEY_K),
+            Some(&Binding::Button(KeyCode::BTN_SOUTH))
+        );
+        assert_eq!(
+            mapping.get(&KeyCode::KEY_L),
+            Some(&Binding::Turbo {
+                button: KeyCode::BTN_EAST,
+                hz: 10
+            })
+        );
+        assert_eq!(
+            mapping.get(&KeyCode::KEY_U),
+            Some(&Binding::Combo(vec![
+                KeyCode::BTN_SOUTH,
+                KeyCode::BTN_START
+            ]))
+        );
+        assert_eq!(
+            mapping.layer_binding("alt", &KeyCode::KEY_J),
+            Some(&Binding::Tap(KeyCode::BTN_SOUTH))
+        );
+        assert_eq!(
+            mapping.device_binding(macropad, &KeyCode::KEY_1),
+            Some(&Binding::Button(KeyCode::BTN_EAST))
+        );
+        assert_eq!(config.chords[0].button, KeyCode::BTN_EAST);
+        assert_eq!(config.double_taps[0].button, KeyCode::BTN_SOUTH);
+        assert_eq!(config.shortcuts[0].button, KeyCode::BTN_EAST);
+    }
+
+    #[test]
+    fn swapping_with_an_unbound_button_moves_the_keys_over() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_NORTH));
+        let mut config = ControllerConfig {
+            mapping,
+            ..ControllerConfig::default()
+        };
+
+        config.swap_bindings(KeyCode::BTN_SOUTH, KeyCode::BTN_WEST);
+
+        assert_eq!(
+            config.mapping.get(&KeyCode::KEY_J),
+            Some(&Binding::Button(KeyCode::BTN_WEST))
+        );
+        assert_eq!(
+            config.mapping.get(&KeyCode::KEY_K),
+            Some(&Binding::Button(KeyCode::BTN_NORTH))
+        );
     }
 }
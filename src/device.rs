@@ -1,27 +1,219 @@
+use crate::config;
 use crate::error::AppError;
 use anyhow::Result;
-use evdev::{Device, KeyCode};
-use std::path::PathBuf;
+use evdev::uinput::VirtualDevice;
+use evdev::{
+    AttributeSet, BusType, Device, EventType, InputEvent, KeyCode, LedCode, RelativeAxisCode,
+};
+use std::ffi::CStr;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// Start of the default name of the virtual keyboard that passes unmapped
+/// input through. See [`forward_device_name`].
+const FORWARD_NAME_PREFIX: &str = "inputmaster-forward-";
+
+/// What the forwarded keyboard was called before its name said whose it is.
+/// Still matched so older versions' devices are recognized.
+const OLD_FORWARD_DEVICE_NAME: &str = "Forwarded Keyboard";
+
+/// File in the config directory naming the keyboard selected last.
+const LAST_KEYBOARD_FILE: &str = "last-keyboard";
+
+/// Longest device name uinput keeps, leaving room for the NUL.
+const MAX_DEVICE_NAME_LEN: usize = 79;
+
+/// Physical path given to every device this program creates, so its own
+/// controllers are never offered as sources.
+pub const VIRTUAL_PHYS: &CStr = c"inputmaster/virtual";
+
+/// Lock keys and the LED each one lights.
+const LOCK_LEDS: [(KeyCode, LedCode); 3] = [
+    (KeyCode::KEY_CAPSLOCK, LedCode::LED_CAPSL),
+    (KeyCode::KEY_NUMLOCK, LedCode::LED_NUML),
+    (KeyCode::KEY_SCROLLLOCK, LedCode::LED_SCROLLL),
+];
+
+/// Builds the virtual keyboard that passes `source`'s unmapped input back to
+/// the system. It declares the source's keys, pointer axes, MSC events and
+/// switches, and carries its bus, vendor and product IDs, so keyboard
+/// quirks keyed on them still apply.
+///
+/// evdev's builder can't declare LEDs or autorepeat. See [`LockLeds`] for
+/// how the LEDs are kept working.
+pub fn build_forward_device(source: &Device, name: &str) -> io::Result<VirtualDevice> {
+    let mut builder = VirtualDevice::builder()?
+        .name(name)
+        .with_phys(VIRTUAL_PHYS)?
+        .input_id(source.input_id())
+        .with_keys(source.supported_keys().unwrap_or_default())?;
+
+    if let Some(axes) = source.supported_relative_axes() {
+        builder = builder.with_relative_axes(axes)?;
+    }
+    if let Some(misc) = source.misc_properties() {
+        builder = builder.with_msc(misc)?;
+    }
+    if let Some(switches) = source.supported_switches() {
+        builder = builder.with_switches(switches)?;
+    }
+
+    builder.build()
+}
+
+/// Keeps a grabbed keyboard's lock LEDs in step with its lock keys. While
+/// grabbed, the LED writes of the desktop are dropped by the kernel, as only
+/// the grabbing fd may write to the device, so the LEDs are toggled from
+/// here on each forwarded lock key press instead.
+pub struct LockLeds {
+    lit: AttributeSet<LedCode>,
+}
+
+impl LockLeds {
+    /// Starts from the LEDs lit when the keyboard was grabbed. `None` for
+    /// devices without lock LEDs.
+    pub fn new(device: &Device) -> Option<Self> {
+        let leds = device.supported_leds()?;
+        if !LOCK_LEDS.iter().any(|(_, led)| leds.contains(*led)) {
+            return None;
+        }
+
+        Some(LockLeds {
+            lit: device.get_led_state().unwrap_or_default(),
+        })
+    }
+
+    /// Toggles the LED of a lock key on its press. Other keys and values
+    /// are ignored.
+    pub fn handle_key(&mut self, device: &mut Device, key: KeyCode, value: i32) -> io::Result<()> {
+        let Some((_, led)) = LOCK_LEDS.iter().find(|(lock, _)| *lock == key) else {
+            return Ok(());
+        };
+        if value != 1 {
+            return Ok(());
+        }
+
+        let lit = !self.lit.contains(*led);
+        if lit {
+            self.lit.insert(*led);
+        } else {
+            self.lit.remove(*led);
+        }
+
+        device.send_events(&[InputEvent::new(EventType::LED.0, led.0, lit as i32)])
+    }
+}
+
+/// Broad category of a source device, used to decide what it can drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// A full keyboard with modifier keys.
+    Keyboard,
+    /// Keys without the usual modifiers, such as a macropad or numpad.
+    Keypad,
+    /// Relative pointer motion plus buttons.
+    Mouse,
+    /// A gamepad or joystick, whose buttons can drive bindings.
+    Gamepad,
+}
+
+impl DeviceKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            DeviceKind::Keyboard => "keyboard",
+            DeviceKind::Keypad => "keypad",
+            DeviceKind::Mouse => "mouse",
+            DeviceKind::Gamepad => "gamepad",
+        }
+    }
+}
 
 #[allow(dead_code)]
 pub struct InputDevice {
     pub path: PathBuf,
     pub device: Device,
     pub name: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus_type: BusType,
+    /// Where the device is attached, e.g. `usb-0000:00:14.0-2/input0`.
+    pub phys: Option<String>,
+    /// Serial number or similar, which most keyboards leave empty.
+    pub uniq: Option<String>,
     pub is_keyboard: bool,
+    /// `None` for devices that can't act as a mapping source.
+    pub kind: Option<DeviceKind>,
+    /// Whether mapping takes the device exclusively (EVIOCGRAB). Without the
+    /// grab, mapped keys also keep reaching the system as normal input.
+    pub grab: bool,
 }
 
 impl InputDevice {
     pub fn new(path: PathBuf, device: Device) -> Self {
         let name = device.name().unwrap_or("Unknown device").to_string();
+        let id = device.input_id();
+        let phys = device.physical_path().map(str::to_string);
+        let uniq = device
+            .unique_name()
+            .filter(|uniq| !uniq.is_empty())
+            .map(str::to_string);
         let is_keyboard = Self::is_keyboard(&device);
+        let kind = Self::classify(&device, is_keyboard);
 
         InputDevice {
             path,
             device,
             name,
+            vendor_id: id.vendor(),
+            product_id: id.product(),
+            bus_type: id.bus_type(),
+            phys,
+            uniq,
             is_keyboard,
+            kind,
+            grab: true,
+        }
+    }
+
+    /// Identifies the device across reboots and replugs, unlike its
+    /// `/dev/input/eventN` path: `vendor:product:uniq`, with the name standing
+    /// in for a missing `uniq`.
+    pub fn stable_key(&self) -> String {
+        format!(
+            "{:04x}:{:04x}:{}",
+            self.vendor_id,
+            self.product_id,
+            self.uniq.as_deref().unwrap_or(&self.name)
+        )
+    }
+
+    fn classify(device: &Device, is_keyboard: bool) -> Option<DeviceKind> {
+        if is_keyboard {
+            return Some(DeviceKind::Keyboard);
         }
+
+        let keys = device.supported_keys()?;
+
+        let has_pointer = device.supported_relative_axes().is_some_and(|axes| {
+            axes.contains(RelativeAxisCode::REL_X) && axes.contains(RelativeAxisCode::REL_Y)
+        });
+        if has_pointer && keys.contains(KeyCode::BTN_LEFT) {
+            return Some(DeviceKind::Mouse);
+        }
+
+        if keys.contains(KeyCode::BTN_SOUTH) || keys.contains(KeyCode::BTN_TRIGGER) {
+            return Some(DeviceKind::Gamepad);
+        }
+
+        // Anything else with a handful of regular keys can still trigger
+        // bindings. Power buttons and the like only report one or two keys.
+        let regular_keys = keys.iter().filter(|k| k.0 < KeyCode::BTN_0.0).count();
+        (regular_keys >= 4).then_some(DeviceKind::Keypad)
     }
 
     fn is_keyboard(device: &Device) -> bool {
@@ -54,19 +246,464 @@ impl InputDevice {
 }
 
 pub fn discover_keyboards() -> Result<Vec<InputDevice>> {
-    let mut keyboards = Vec::new();
+    let keyboards: Vec<_> = discover_sources()
+        .into_iter()
+        .filter(|d| d.kind == Some(DeviceKind::Keyboard))
+        .collect();
+
+    if keyboards.is_empty() {
+        return Err(AppError::NoKeyboardsFound.into());
+    }
 
-    for (path, device) in evdev::enumerate() {
-        let input_device = InputDevice::new(path, device);
+    Ok(keyboards)
+}
 
-        if input_device.is_keyboard {
-            keyboards.push(input_device);
+/// Converts an error opening `/dev/uinput` into
+/// [`AppError::UinputUnavailable`] with what to do about it, for the
+/// failures first-time users run into. Other errors pass through.
+pub fn uinput_error(error: io::Error) -> anyhow::Error {
+    let advice = match error.kind() {
+        io::ErrorKind::NotFound => {
+            "/dev/uinput does not exist. Load the uinput module with `sudo modprobe uinput`, \
+             and add it to /etc/modules-load.d to load it at boot"
+        }
+        io::ErrorKind::PermissionDenied => {
+            "no permission to open /dev/uinput. Run as root, or give a group access with a \
+             udev rule such as KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\" and add \
+             yourself to that group"
         }
+        _ => return error.into(),
+    };
+
+    AppError::UinputUnavailable(advice.to_string()).into()
+}
+
+/// Opens a source device, reporting a device that is gone as
+/// [`AppError::DeviceDisconnected`].
+pub fn open_source(path: &Path) -> Result<Device> {
+    Device::open(path).map_err(|e| read_error(path, e))
+}
+
+/// Converts an error opening or reading `path`, turning the errors an
+/// unplugged device gives into [`AppError::DeviceDisconnected`].
+pub fn read_error(path: &Path, error: io::Error) -> anyhow::Error {
+    if error.kind() == io::ErrorKind::NotFound || error.raw_os_error() == Some(libc::ENODEV) {
+        return AppError::DeviceDisconnected(path.display().to_string()).into();
     }
 
-    if keyboards.is_empty() {
-        return Err(AppError::NoKeyboardsFound.into());
+    error.into()
+}
+
+/// Index of the keyboard selected last time in `keyboards`, if it is there.
+pub fn find_last_keyboard(keyboards: &[InputDevice]) -> Option<usize> {
+    let saved = fs::read_to_string(config::config_dir().ok()?.join(LAST_KEYBOARD_FILE)).ok()?;
+    keyboards
+        .iter()
+        .position(|keyboard| keyboard.stable_key() == saved.trim())
+}
+
+/// Remembers `keyboard` as the one to offer first next time.
+pub fn save_last_keyboard(keyboard: &InputDevice) -> Result<()> {
+    let path = config::ensure_dir()?.join(LAST_KEYBOARD_FILE);
+    fs::write(path, format!("{}\n", keyboard.stable_key()))?;
+
+    Ok(())
+}
+
+/// Every device that can feed a mapping session: keyboards, keypads, mice
+/// and gamepads, leaving out the devices created here.
+pub fn discover_sources() -> Vec<InputDevice> {
+    evdev::enumerate()
+        .filter(|(_, device)| !is_own_device(device))
+        .map(|(path, device)| InputDevice::new(path, device))
+        .filter(|d| d.kind.is_some())
+        .collect()
+}
+
+/// Whether `device` was created by this program, in this or another
+/// running instance. Older versions didn't set [`VIRTUAL_PHYS`], so their
+/// forwarded keyboards are matched by name. Controllers are named by the
+/// user and can only be told apart by their physical path.
+fn is_own_device(device: &Device) -> bool {
+    device.physical_path() == VIRTUAL_PHYS.to_str().ok()
+        || device.name().is_some_and(is_forward_device_name)
+}
+
+/// Default name of the keyboard forwarding `source_name`'s unmapped input,
+/// `inputmaster-forward-<source>-<pid>`, so instances and sources can be
+/// told apart in device lists. Long source names are cut to fit uinput.
+pub fn forward_device_name(source_name: &str) -> String {
+    let pid = process::id().to_string();
+    let room = MAX_DEVICE_NAME_LEN - FORWARD_NAME_PREFIX.len() - pid.len() - 1;
+
+    let mut end = source_name.len().min(room);
+    while !source_name.is_char_boundary(end) {
+        end -= 1;
     }
 
-    Ok(keyboards)
+    format!("{}{}-{}", FORWARD_NAME_PREFIX, &source_name[..end], pid)
+}
+
+/// Name of the keyboard forwarding the `index`th grabbed source, called
+/// `source_name`. A `custom` name from `--forward-name` is used as given for
+/// the first and numbered from `-2` for the rest, so they can still be told
+/// apart; without one each is named after its own source.
+pub fn source_forward_name(custom: Option<&str>, source_name: &str, index: usize) -> String {
+    match custom {
+        Some(custom) if index == 0 => custom.to_string(),
+        Some(custom) => format!("{}-{}", custom, index + 1),
+        None => forward_device_name(source_name),
+    }
+}
+
+/// Whether `name` is one this program gives its forwarded keyboards by
+/// default, now or in older versions.
+fn is_forward_device_name(name: &str) -> bool {
+    name.starts_with(FORWARD_NAME_PREFIX) || name == OLD_FORWARD_DEVICE_NAME
+}
+
+/// Whether `name` is a forwarded keyboard's, by default or as named by
+/// [`source_forward_name`] after `custom`.
+fn is_forward_name(name: &str, custom: Option<&str>) -> bool {
+    is_forward_device_name(name)
+        || custom.is_some_and(|custom| {
+            name.strip_prefix(custom).is_some_and(|rest| {
+                rest.is_empty()
+                    || rest
+                        .strip_prefix('-')
+                        .is_some_and(|n| n.parse::<usize>().is_ok())
+            })
+        })
+}
+
+/// Everything a device reports about itself, for attaching to bug reports.
+/// Codes are listed by name with the raw number in parentheses.
+pub fn describe_capabilities(source: &InputDevice) -> String {
+    let device = &source.device;
+    let id = device.input_id();
+    let mut out = String::new();
+
+    let _ = writeln!(out, "Name: {}", source.name);
+    let _ = writeln!(out, "Path: {}", source.path.display());
+    if let Some(phys) = device.physical_path() {
+        let _ = writeln!(out, "Physical path: {}", phys);
+    }
+    let _ = writeln!(
+        out,
+        "Id: bus {}, vendor {:#06x}, product {:#06x}, version {:#06x}",
+        id.bus_type(),
+        id.vendor(),
+        id.product(),
+        id.version()
+    );
+    let _ = writeln!(
+        out,
+        "Detected as: {}",
+        source
+            .kind
+            .map_or("not a mapping source", DeviceKind::label)
+    );
+    let _ = writeln!(
+        out,
+        "Event types: {}",
+        list_codes(device.supported_events().iter().map(|t| (t, t.0)))
+    );
+    let _ = writeln!(
+        out,
+        "Properties: {}",
+        list_codes(device.properties().iter().map(|p| (p, p.0)))
+    );
+
+    if let Some(keys) = device.supported_keys() {
+        let _ = writeln!(
+            out,
+            "Keys ({}): {}",
+            keys.iter().count(),
+            list_codes(keys.iter().map(|k| (k, k.0)))
+        );
+    }
+    if let Some(axes) = device.supported_relative_axes() {
+        let _ = writeln!(
+            out,
+            "Relative axes: {}",
+            list_codes(axes.iter().map(|a| (a, a.0)))
+        );
+    }
+    if let Ok(axes) = device.get_absinfo() {
+        for (axis, info) in axes {
+            let _ = writeln!(
+                out,
+                "Absolute axis {:?} ({}): {}..{}, fuzz {}, flat {}",
+                axis,
+                axis.0,
+                info.minimum(),
+                info.maximum(),
+                info.fuzz(),
+                info.flat()
+            );
+        }
+    }
+    if let Some(switches) = device.supported_switches() {
+        let _ = writeln!(
+            out,
+            "Switches: {}",
+            list_codes(switches.iter().map(|s| (s, s.0)))
+        );
+    }
+    if let Some(leds) = device.supported_leds() {
+        let _ = writeln!(out, "LEDs: {}", list_codes(leds.iter().map(|l| (l, l.0))));
+    }
+    if let Some(misc) = device.misc_properties() {
+        let _ = writeln!(out, "Misc: {}", list_codes(misc.iter().map(|m| (m, m.0))));
+    }
+    if let Some(effects) = device.supported_ff() {
+        let _ = writeln!(
+            out,
+            "Force feedback: {}",
+            list_codes(effects.iter().map(|e| (e, e.0)))
+        );
+    }
+
+    out
+}
+
+/// `NAME (code), ...`, or `none`.
+fn list_codes<T: fmt::Debug>(codes: impl Iterator<Item = (T, u16)>) -> String {
+    let names: Vec<String> = codes
+        .map(|(code, raw)| format!("{:?} ({})", code, raw))
+        .collect();
+
+    if names.is_empty() {
+        return "none".to_string();
+    }
+
+    names.join(", ")
+}
+
+/// Paths of the forwarded keyboards currently present on the system.
+///
+/// A uinput device only lives as long as the process that created it, so when
+/// no session is running here these belong to another instance that is still
+/// alive, typically one that hung or lost its terminal. Those named with
+/// `--forward-name` are found when `custom` is that name.
+pub fn find_forwarded_keyboards(custom: Option<&str>) -> Vec<PathBuf> {
+    evdev::enumerate()
+        .filter(|(_, device)| {
+            device
+                .name()
+                .is_some_and(|name| is_forward_name(name, custom))
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// PIDs of other running processes with the same executable name as this one.
+pub fn other_instances() -> Vec<u32> {
+    let own_pid = process::id();
+    let Ok(own_comm) = fs::read_to_string("/proc/self/comm") else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    let mut pids: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| pid != own_pid)
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{}/comm", pid)).is_ok_and(|comm| comm == own_comm)
+        })
+        .collect();
+    pids.sort_unstable();
+
+    pids
+}
+
+/// An eventfd that cuts a [`wait_readable`] short when written to, so a stop
+/// request doesn't wait out the poll timeout.
+pub struct Waker {
+    fd: OwnedFd,
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: eventfd has no pointer arguments; the result is checked.
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `fd` was just opened and nothing else owns it.
+        Ok(Waker {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Makes the waker's fd readable until [`Waker::reset`].
+    pub fn wake(&self) {
+        let one: u64 = 1;
+        // SAFETY: writes the 8 bytes of `one` to our own eventfd. A full
+        // counter fails with EAGAIN, which still leaves the fd readable.
+        unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &one as *const u64 as *const libc::c_void,
+                size_of::<u64>(),
+            );
+        }
+    }
+
+    /// Clears pending wakes.
+    pub fn reset(&self) {
+        let mut count: u64 = 0;
+        // SAFETY: reads 8 bytes into `count` from our own non-blocking
+        // eventfd. Nothing pending fails with EAGAIN, which is fine.
+        unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut count as *mut u64 as *mut libc::c_void,
+                size_of::<u64>(),
+            );
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Waits up to `timeout` for any of `fds` to become readable and returns the
+/// indices of the ready ones. An interrupted wait returns no indices.
+pub fn wait_readable(fds: &[RawFd], timeout: Duration) -> io::Result<Vec<usize>> {
+    let mut poll_fds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    // SAFETY: `poll_fds` is a valid, exclusively borrowed array of pollfd
+    // structs whose length is passed alongside it.
+    let ready = unsafe {
+        libc::poll(
+            poll_fds.as_mut_ptr(),
+            poll_fds.len() as libc::nfds_t,
+            timeout_ms,
+        )
+    };
+
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(Vec::new());
+        }
+        return Err(err);
+    }
+
+    Ok(poll_fds
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn forwarded_keyboards_are_known_by_name() {
+        assert!(is_forward_device_name(&forward_device_name(
+            "AT Translated Set 2 keyboard"
+        )));
+        assert!(is_forward_device_name(&forward_device_name("")));
+        assert!(is_forward_device_name(OLD_FORWARD_DEVICE_NAME));
+        assert!(!is_forward_device_name("AT Translated Set 2 keyboard"));
+        assert!(!is_forward_device_name("Logitech USB Receiver"));
+    }
+
+    #[test]
+    fn forward_names_end_with_the_pid() {
+        let suffix = format!("-{}", process::id());
+        let name = forward_device_name("AT Translated Set 2 keyboard");
+        assert_eq!(
+            name,
+            format!("inputmaster-forward-AT Translated Set 2 keyboard{}", suffix)
+        );
+
+        // Long source names are cut, never the PID
+        let name = forward_device_name(&"é".repeat(100));
+        assert!(name.ends_with(&suffix));
+        assert!(name.len() <= MAX_DEVICE_NAME_LEN);
+    }
+
+    #[test]
+    fn each_source_gets_its_own_forward_name() {
+        assert_eq!(source_forward_name(Some("fwd"), "Keyboard", 0), "fwd");
+        assert_eq!(source_forward_name(Some("fwd"), "Keyboard", 1), "fwd-2");
+        assert_eq!(
+            source_forward_name(None, "Keypad", 1),
+            forward_device_name("Keypad")
+        );
+    }
+
+    #[test]
+    fn custom_forward_names_are_found() {
+        assert!(is_forward_name("fwd", Some("fwd")));
+        assert!(is_forward_name("fwd-3", Some("fwd")));
+        assert!(is_forward_name(
+            &forward_device_name("Keyboard"),
+            Some("fwd")
+        ));
+        assert!(!is_forward_name("fwd", None));
+        assert!(!is_forward_name("fwdx", Some("fwd")));
+        assert!(!is_forward_name("fwd-pad", Some("fwd")));
+    }
+
+    #[test]
+    fn waker_wakes_until_reset() {
+        let waker = Waker::new().unwrap();
+        let idle = Duration::from_millis(10);
+        assert!(wait_readable(&[waker.fd()], idle).unwrap().is_empty());
+
+        // Several wakes are drained by one reset
+        waker.wake();
+        waker.wake();
+        assert_eq!(wait_readable(&[waker.fd()], idle).unwrap(), [0]);
+        assert_eq!(wait_readable(&[waker.fd()], idle).unwrap(), [0]);
+
+        waker.reset();
+        assert!(wait_readable(&[waker.fd()], idle).unwrap().is_empty());
+        waker.reset();
+    }
+
+    #[test]
+    fn a_wake_from_another_thread_cuts_the_wait_short() {
+        let waker = Arc::new(Waker::new().unwrap());
+        let remote = waker.clone();
+        let wakes = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let woken = Instant::now();
+            remote.wake();
+            woken
+        });
+
+        assert_eq!(
+            wait_readable(&[waker.fd()], Duration::from_secs(10)).unwrap(),
+            [0]
+        );
+        let returned = Instant::now();
+
+        // Stopping an idle session takes no longer than this
+        let woken = wakes.join().unwrap();
+        assert!(returned.duration_since(woken) < Duration::from_millis(50));
+    }
 }
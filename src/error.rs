@@ -7,4 +7,33 @@ pub enum AppError {
 
     #[error("Failed to find any keyboard devices")]
     NoKeyboardsFound,
+
+    #[error("Unknown argument: {0}")]
+    UnknownArgument(String),
+
+    #[error("Missing value for argument: {0}")]
+    MissingValue(String),
+
+    #[error("Invalid value for argument {0}: {1}")]
+    InvalidValue(String, String),
+
+    #[error("{0} only applies with --start")]
+    RequiresStart(String),
+
+    #[error("Permission denied grabbing {0}")]
+    GrabDenied(String),
+
+    #[error(
+        "Another instance is already running (lock {0} held by PID {1}); use --force to start anyway"
+    )]
+    AlreadyRunning(String, String),
+
+    #[error("Cannot create virtual devices: {0}")]
+    UinputUnavailable(String),
+
+    #[error("Cannot create config directory {0}: {1}")]
+    ConfigDir(String, std::io::Error),
+
+    #[error("Keyboard {0} disconnected, please reselect")]
+    DeviceDisconnected(String),
 }
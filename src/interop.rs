@@ -0,0 +1,293 @@
+//! Importing layouts from other tools' configuration files.
+//!
+//! # Steam Input
+//!
+//! Only the part of a Steam Input controller config (`.vdf`) that binds
+//! controller buttons to keyboard keys is read. The first block named after
+//! one of these inputs is searched for a `"binding" "key_press <KEY>"` entry:
+//!
+//! - `button_a`, `button_b`, `button_x`, `button_y`: face buttons
+//! - `left_bumper`, `right_bumper`: shoulders
+//! - `button_escape`, `button_menu`: Start and Select
+//! - `dpad_north`, `dpad_south`, `dpad_west`, `dpad_east`: D-pad
+//!
+//! Triggers, sticks, mouse bindings, action sets, mode shifts and activators
+//! other than a plain key press are ignored.
+
+use anyhow::Result;
+use evdev::KeyCode;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Steam Input names for the buttons that can be imported.
+const STEAM_BUTTONS: [(&str, KeyCode); 12] = [
+    ("button_a", KeyCode::BTN_SOUTH),
+    ("button_b", KeyCode::BTN_EAST),
+    ("button_x", KeyCode::BTN_NORTH),
+    ("button_y", KeyCode::BTN_WEST),
+    ("left_bumper", KeyCode::BTN_TL),
+    ("right_bumper", KeyCode::BTN_TR),
+    ("button_escape", KeyCode::BTN_START),
+    ("button_menu", KeyCode::BTN_SELECT),
+    ("dpad_north", KeyCode::BTN_DPAD_UP),
+    ("dpad_south", KeyCode::BTN_DPAD_DOWN),
+    ("dpad_west", KeyCode::BTN_DPAD_LEFT),
+    ("dpad_east", KeyCode::BTN_DPAD_RIGHT),
+];
+
+/// Steam key names that don't follow the evdev name once underscores are
+/// dropped (`LEFT_SHIFT` -> `KEY_LEFTSHIFT` works, these don't).
+const STEAM_KEY_ALIASES: [(&str, KeyCode); 12] = [
+    ("ESCAPE", KeyCode::KEY_ESC),
+    ("RETURN", KeyCode::KEY_ENTER),
+    ("LEFT_CONTROL", KeyCode::KEY_LEFTCTRL),
+    ("RIGHT_CONTROL", KeyCode::KEY_RIGHTCTRL),
+    ("UP_ARROW", KeyCode::KEY_UP),
+    ("DOWN_ARROW", KeyCode::KEY_DOWN),
+    ("LEFT_ARROW", KeyCode::KEY_LEFT),
+    ("RIGHT_ARROW", KeyCode::KEY_RIGHT),
+    ("PERIOD", KeyCode::KEY_DOT),
+    ("DASH", KeyCode::KEY_MINUS),
+    ("FORWARD_SLASH", KeyCode::KEY_SLASH),
+    ("PAGE_UP", KeyCode::KEY_PAGEUP),
+];
+
+/// A VDF value: either a string or a block of nested key/value pairs.
+enum Vdf {
+    String(String),
+    Block(Vec<(String, Vdf)>),
+}
+
+/// Reads the key-to-button bindings from a Steam Input config, as
+/// (keyboard key, controller button) pairs. See the module docs for what is
+/// supported.
+pub fn import_steam_vdf(path: &Path) -> Result<Vec<(KeyCode, KeyCode)>> {
+    let contents = fs::read_to_string(path)?;
+    let root = parse_vdf(&contents)?;
+
+    let mut bindings = Vec::new();
+    for (name, button) in STEAM_BUTTONS {
+        if let Some(key) = find_input(&root, name).and_then(find_key_press) {
+            bindings.push((key, button));
+        }
+    }
+
+    Ok(bindings)
+}
+
+/// The first block named `name` anywhere below `entries`.
+fn find_input<'a>(entries: &'a [(String, Vdf)], name: &str) -> Option<&'a [(String, Vdf)]> {
+    entries.iter().find_map(|(key, value)| match value {
+        Vdf::Block(block) if key.eq_ignore_ascii_case(name) => Some(block.as_slice()),
+        Vdf::Block(block) => find_input(block, name),
+        Vdf::String(_) => None,
+    })
+}
+
+/// The key of the first `"binding" "key_press <KEY>"` below `entries`.
+fn find_key_press(entries: &[(String, Vdf)]) -> Option<KeyCode> {
+    entries.iter().find_map(|(key, value)| match value {
+        Vdf::String(binding) if key.eq_ignore_ascii_case("binding") => {
+            let name = binding.strip_prefix("key_press ")?;
+            // Bindings may carry a label after a comma
+            steam_key(name.split(',').next()?.trim())
+        }
+        Vdf::Block(block) => find_key_press(block),
+        Vdf::String(_) => None,
+    })
+}
+
+fn steam_key(name: &str) -> Option<KeyCode> {
+    if let Some((_, key)) = STEAM_KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+    {
+        return Some(*key);
+    }
+
+    let evdev_name = format!("KEY_{}", name.replace('_', "").to_ascii_uppercase());
+    KeyCode::from_str(&evdev_name).ok()
+}
+
+/// Parses VDF text: quoted keys followed by a quoted value or a `{ }` block.
+/// `//` starts a comment. Conditionals like `[$WIN32]` are not supported.
+fn parse_vdf(text: &str) -> Result<Vec<(String, Vdf)>> {
+    let tokens = tokenize(text)?;
+    let mut pos = 0;
+    let entries = parse_block(&tokens, &mut pos)?;
+
+    if pos < tokens.len() {
+        return Err(anyhow::anyhow!("Unbalanced closing brace in VDF"));
+    }
+
+    Ok(entries)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Open,
+    Close,
+}
+
+fn parse_block(tokens: &[Token], pos: &mut usize) -> Result<Vec<(String, Vdf)>> {
+    let mut entries = Vec::new();
+
+    while let Some(token) = tokens.get(*pos) {
+        let key = match token {
+            Token::Str(key) => key.clone(),
+            Token::Close => break,
+            Token::Open => return Err(anyhow::anyhow!("Block without a name in VDF")),
+        };
+        *pos += 1;
+
+        let value = match tokens.get(*pos) {
+            Some(Token::Str(value)) => {
+                *pos += 1;
+                Vdf::String(value.clone())
+            }
+            Some(Token::Open) => {
+                *pos += 1;
+                let block = parse_block(tokens, pos)?;
+                if tokens.get(*pos) != Some(&Token::Close) {
+                    return Err(anyhow::anyhow!("Unterminated block \"{}\" in VDF", key));
+                }
+                *pos += 1;
+                Vdf::Block(block)
+            }
+            _ => return Err(anyhow::anyhow!("Missing value for \"{}\" in VDF", key)),
+        };
+
+        entries.push((key, value));
+    }
+
+    Ok(entries)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => s.extend(chars.next()),
+                        Some(c) => s.push(c),
+                        None => return Err(anyhow::anyhow!("Unterminated string in VDF")),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            // Unquoted tokens run until whitespace or a brace
+            c => {
+                let mut s = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '{' || next == '}' || next == '"' {
+                        break;
+                    }
+                    s.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Str(s));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch Steam config holding `contents`, named after `name`.
+    fn vdf_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("inputmaster-{}-{}.vdf", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn imports_key_presses_of_known_buttons() {
+        let path = vdf_file(
+            "sample",
+            r#"
+            "controller_mappings"
+            {
+                "version" "3"
+                // Face buttons
+                "group"
+                {
+                    "mode" "four_buttons"
+                    "inputs"
+                    {
+                        "button_a" { "activators" { "Full_Press" { "bindings" {
+                            "binding" "key_press SPACE"
+                        } } } }
+                        "button_b" { "activators" { "Full_Press" { "bindings" {
+                            "binding" "key_press E, Use"
+                        } } } }
+                        "button_x" { "activators" { "Full_Press" { "bindings" {
+                            "binding" "mouse_button LEFT"
+                        } } } }
+                    }
+                }
+                "dpad_north" { "binding" "key_press LEFT_CONTROL" }
+            }
+            "#,
+        );
+
+        let bindings = import_steam_vdf(&path).unwrap();
+        assert_eq!(
+            bindings,
+            [
+                (KeyCode::KEY_SPACE, KeyCode::BTN_SOUTH),
+                (KeyCode::KEY_E, KeyCode::BTN_EAST),
+                (KeyCode::KEY_LEFTCTRL, KeyCode::BTN_DPAD_UP),
+            ]
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn key_press_labels_are_dropped() {
+        let entries = parse_vdf(r#""bindings" { "binding" "key_press X, Reload" }"#).unwrap();
+        assert_eq!(find_key_press(&entries), Some(KeyCode::KEY_X));
+    }
+
+    #[test]
+    fn steam_key_names_map_to_evdev_keys() {
+        assert_eq!(steam_key("LEFT_CONTROL"), Some(KeyCode::KEY_LEFTCTRL));
+        assert_eq!(steam_key("escape"), Some(KeyCode::KEY_ESC));
+        assert_eq!(steam_key("LEFT_SHIFT"), Some(KeyCode::KEY_LEFTSHIFT));
+        assert_eq!(steam_key("NOT_A_KEY"), None);
+    }
+
+    #[test]
+    fn malformed_vdf_is_rejected() {
+        let error = |text| parse_vdf(text).err().unwrap().to_string();
+        assert_eq!(
+            error(r#""a" { "b" "c" } }"#),
+            "Unbalanced closing brace in VDF"
+        );
+        assert_eq!(error(r#""a" { "b" "c""#), "Unterminated block \"a\" in VDF");
+        assert_eq!(error(r#""a" "unterminated"#), "Unterminated string in VDF");
+    }
+}
@@ -0,0 +1,71 @@
+use crate::error::AppError;
+use anyhow::Result;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+
+const LOCK_FILE_NAME: &str = "inputmaster.lock";
+
+/// Held for the lifetime of a session so a second instance can't grab the
+/// same keyboards. The lock is an flock on the file rather than the file's
+/// existence, so the kernel drops it when a crashed instance's fd closes and
+/// a leftover file doesn't keep the next instance out.
+///
+/// The file is never removed. Unlinking it, even while locked, lets an
+/// instance that already opened it lock the deleted file while a third one
+/// creates and locks a new one, and both would run.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Takes the lock, or fails with [`AppError::AlreadyRunning`] if another
+    /// instance holds it. The file holds this process's PID for the message.
+    pub fn acquire() -> Result<Self> {
+        let path = lock_path();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        // SAFETY: the fd belongs to `file` and stays open for the call.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::WouldBlock {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                return Err(AppError::AlreadyRunning(
+                    path.display().to_string(),
+                    holder.trim().to_string(),
+                )
+                .into());
+            }
+            return Err(error.into());
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(InstanceLock { _file: file })
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/inputmaster.lock`, falling back to `/run/user/$UID` and
+/// then the temp directory when there is no runtime directory.
+fn lock_path() -> PathBuf {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        // SAFETY: getuid has no preconditions and can't fail.
+        .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })));
+
+    let dir = if runtime_dir.is_dir() {
+        runtime_dir
+    } else {
+        std::env::temp_dir()
+    };
+
+    dir.join(LOCK_FILE_NAME)
+}
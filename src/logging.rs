@@ -0,0 +1,91 @@
+use crate::config;
+use anyhow::{Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::time::Instant;
+
+/// Where log records go.
+enum Sink {
+    Stderr,
+    /// Lines are prefixed with the seconds since startup and the level.
+    File(Mutex<File>),
+}
+
+struct Logger {
+    sink: Sink,
+    start: Instant,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match &self.sink {
+            Sink::Stderr => eprintln!("{}", record.args()),
+            Sink::File(file) => {
+                // A failed write has nowhere better to be reported
+                let _ = writeln!(
+                    file.lock(),
+                    "{:>10.3} {:<5} {}",
+                    self.start.elapsed().as_secs_f64(),
+                    record.level(),
+                    record.args()
+                );
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Sink::File(file) = &self.sink {
+            let _ = file.lock().flush();
+        }
+    }
+}
+
+/// Installs the logger. With `verbosity` above zero, records go to
+/// [`config::log_path`], debug ones too from two up. Otherwise they go to
+/// stderr when `stderr` is set, i.e. when there is no full-screen interface
+/// for them to draw over, and are dropped when it isn't.
+pub fn init(verbosity: u8, stderr: bool) -> Result<()> {
+    let (sink, level) = match verbosity {
+        0 if stderr => (Sink::Stderr, LevelFilter::Info),
+        0 => {
+            log::set_max_level(LevelFilter::Off);
+            return Ok(());
+        }
+        1 => (Sink::File(Mutex::new(open_log()?)), LevelFilter::Info),
+        _ => (Sink::File(Mutex::new(open_log()?)), LevelFilter::Debug),
+    };
+
+    log::set_boxed_logger(Box::new(Logger {
+        sink,
+        start: Instant::now(),
+    }))
+    .context("A logger is already installed")?;
+    log::set_max_level(level);
+    log::info!("inputmaster {} started", env!("CARGO_PKG_VERSION"));
+
+    Ok(())
+}
+
+/// Opens the log file for appending, creating it and its directory.
+fn open_log() -> Result<File> {
+    let path = config::log_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Cannot open {}", path.display()))
+}
@@ -1,18 +1,101 @@
+mod binding;
+mod cli;
+mod clock;
+mod config;
 mod controller;
 mod device;
 mod error;
+mod interop;
+mod lock;
+mod logging;
 mod mapping;
+mod names;
+mod profile;
 mod ui;
 
-use anyhow::Result;
-use crossbeam_channel::bounded;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
 
 fn main() -> Result<()> {
-    let mut ui = ui::UI::new();
+    let options = match cli::Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    // Only the full-screen interface keeps log lines off the terminal
+    let headless = options.quick || options.start;
+    if let Err(e) = logging::init(options.verbose, headless || options.simple) {
+        eprintln!("Not logging: {:#}", e);
+    }
+
+    if let Some(path) = &options.check_fixture {
+        let fixture = mapping::load_fixture(path)?;
+        let actual = fixture.replay();
+
+        if actual == fixture.expected {
+            println!("{}: ok ({} outputs)", path.display(), actual.len());
+            return Ok(());
+        }
+
+        eprintln!(
+            "{}: mismatch (expected {} outputs, got {})",
+            path.display(),
+            fixture.expected.len(),
+            actual.len()
+        );
+        process::exit(1);
+    }
+
+    if let Some(path) = &options.dump_caps {
+        let device = evdev::Device::open(path)?;
+        print!(
+            "{}",
+            device::describe_capabilities(&device::InputDevice::new(path.clone(), device))
+        );
+        return Ok(());
+    }
+
+    if options.cleanup {
+        report_leftovers(options.forward_name.as_deref());
+        return Ok(());
+    }
+
+    // Held until main returns. Paths that exit the process early leave the
+    // file behind, but the flock goes with the process.
+    let _lock = if options.force {
+        None
+    } else {
+        match lock::InstanceLock::acquire() {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    // Nothing below may touch the terminal, so this can run from a service
+    // or a launch script
+    if options.quick || options.start {
+        if let Err(e) = run_headless(&options) {
+            eprintln!("Error: {:#}", e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut ui = ui::UI::new(&options);
     ui.init()?;
 
     let keyboards = match device::discover_keyboards() {
@@ -24,43 +107,163 @@ fn main() -> Result<()> {
         }
     };
 
-    ui.show_devices(&keyboards)?;
-    let selected_idx = ui.select_device(&keyboards)?;
-    let selected_keyboard = keyboards
+    let last_used = device::find_last_keyboard(&keyboards);
+    let selected = ui.select_devices(&keyboards, last_used)?;
+    if selected.is_empty() {
+        ui.cleanup()?;
+        return Ok(());
+    }
+    let mut selected_keyboards: Vec<_> = keyboards
         .into_iter()
-        .nth(selected_idx)
-        .expect("Selected keyboard not found");
+        .enumerate()
+        .filter(|(idx, _)| selected.contains(idx))
+        .map(|(_, keyboard)| keyboard)
+        .collect();
+
+    // Only saves a key press next time, so a read-only config is no reason
+    // to stop
+    let _ = device::save_last_keyboard(&selected_keyboards[0]);
+
+    let question = match selected_keyboards.as_slice() {
+        [keyboard] => format!(
+            "Grab {} exclusively? Answer n to keep it typing normally while mapping",
+            keyboard.name
+        ),
+        keyboards => format!(
+            "Grab the {} keyboards exclusively? Answer n to keep them typing normally while mapping",
+            keyboards.len()
+        ),
+    };
+    let grab = ui.prompt_yes_no(&question)?;
+    for keyboard in &mut selected_keyboards {
+        keyboard.grab = grab;
+    }
 
-    let mut mapper = mapping::DeviceMapper::new(selected_keyboard);
+    let mut selected_keyboards = selected_keyboards.into_iter();
+    let mut mapper = mapping::DeviceMapper::new(
+        selected_keyboards
+            .next()
+            .expect("At least one keyboard was selected"),
+    );
+    for keyboard in selected_keyboards {
+        mapper.add_keyboard(keyboard)?;
+    }
+    if let Some(pulse_ms) = options.pulse_ms {
+        mapper.settings.pulse_ms = pulse_ms;
+    }
+    mapper.settings.hotbar = options.hotbar;
+    mapper.forward_name = options.forward_name.clone();
+    if let Some(signals) = &options.stop_signals {
+        mapper.stop_signals = signals.clone();
+    }
+    mapper.allow_exec = options.allow_exec;
 
     let mut mapping_thread = None;
+    // A profile picked from the session menu, loaded once that session is
+    // over before mapping starts again
+    let mut switch_to: Option<PathBuf> = None;
 
     'main_loop: loop {
-        match ui.show_main_menu()? {
+        let choice = match switch_to.take() {
+            Some(path) => match profile::load_profile(&path)
+                .and_then(|profile| mapper.apply_profile(profile))
+            {
+                Ok(()) => {
+                    mapper.profile_path = Some(path);
+                    3
+                }
+                Err(e) => {
+                    ui.prompt_yes_no(&format!("Failed to load profile: {}. Continue?", e))?;
+                    continue;
+                }
+            },
+            None => ui.show_main_menu()?,
+        };
+        match choice {
             1 => {
                 // Create a new controller
-                let controller_num = mapper.controllers.len() + 1;
-                let controller_name = format!("Controller {}", controller_num);
+                let controller_name = next_controller_name(&mapper.controllers);
+                let Some(layout) = ui.select_layout()? else {
+                    continue;
+                };
 
-                match controller::VirtualController::new(&controller_name) {
+                match controller::VirtualController::new(&controller_name, layout) {
                     Ok(mut controller) => {
-                        if controller_num == 1 {
-                            // First controller gets default mapping
-                            controller.apply_default_mapping();
-                            ui.prompt_yes_no("First controller auto-mapped with default settings. Press any key to continue")?;
+                        let mut edit_default = false;
+                        if mapper.controllers.is_empty() {
+                            // First controller gets the default mapping, as
+                            // a starting point the user can rebind
+                            let source = apply_default_mapping(&mut controller);
+                            if !ui.is_quiet() {
+                                edit_default = ui.prompt_yes_no(&format!(
+                                    "First controller auto-mapped with {}. Edit its bindings now?",
+                                    source
+                                ))?;
+                            }
                         } else {
-                            // Manual mapping for additional controllers
-                            ui.map_controller_buttons(&mut controller, &mut mapper)?;
+                            // Manual mapping for additional controllers, again
+                            // if it reuses keys the user didn't mean to share
+                            loop {
+                                let mapped = ui.map_controller_buttons(
+                                    &mut controller,
+                                    &mut mapper,
+                                    Some(&controller::COMMON_FIRST_ORDER),
+                                );
+                                match mapped {
+                                    Ok(true) => {}
+                                    // Cancelled; the controller is dropped
+                                    Ok(false) => continue 'main_loop,
+                                    Err(e) => {
+                                        reselect_keyboard(&mut ui, &mut mapper, e)?;
+                                        continue 'main_loop;
+                                    }
+                                }
+
+                                let mut configs: Vec<_> =
+                                    mapper.controllers.iter().map(|c| c.config()).collect();
+                                configs.push(controller.config());
+                                let conflicts: Vec<_> = mapping::find_conflicts(&configs)
+                                    .into_iter()
+                                    .filter(|conflict| {
+                                        conflict.controllers.contains(&mapper.controllers.len())
+                                    })
+                                    .collect();
+                                if conflicts.is_empty() {
+                                    break;
+                                }
+
+                                let names: Vec<String> =
+                                    configs.into_iter().map(|config| config.name).collect();
+                                let keep = ui.prompt_yes_no(&format!(
+                                    "These keys are bound on other controllers too, so one press \
+                                     drives them all: {}. Keep them, e.g. to mirror co-op input? \
+                                     Answer n to map {} again",
+                                    ui::describe_conflicts(&names, &conflicts),
+                                    controller.name
+                                ))?;
+                                if keep {
+                                    break;
+                                }
+                            }
                         }
 
                         mapper.add_controller(controller);
+                        if edit_default {
+                            edit_default_mapping(&mut ui, &mut mapper)?;
+                        }
                     }
                     Err(e) => {
                         eprintln!("Failed to create controller: {}", e);
-                        ui.prompt_yes_no(&format!(
-                            "Failed to create controller: {}. Continue?",
-                            e
-                        ))?;
+                        // Retrying can't help until uinput is set up, so
+                        // this one gets its own message
+                        let message = match e.downcast_ref::<error::AppError>() {
+                            Some(error::AppError::UinputUnavailable(advice)) => format!(
+                                "Controllers need uinput, which is unavailable: {}. Continue?",
+                                advice
+                            ),
+                            _ => format!("Failed to create controller: {}. Continue?", e),
+                        };
+                        ui.prompt_yes_no(&message)?;
                     }
                 }
             }
@@ -77,49 +280,93 @@ fn main() -> Result<()> {
 
                 // Start the mapping in a background thread
                 if mapping_thread.is_none() {
-                    ui.show_mapping_active()?;
-
-                    // Set up a way to detect when the user wants to stop mapping
-                    let (stop_tx, stop_rx) = bounded(1);
-
-                    // Create a separate thread to watch for user input to stop mapping
-                    let running = Arc::new(parking_lot::Mutex::new(true));
-                    let ui_running = running.clone();
-
-                    // This thread will watch for Delete key press from the UI
-                    let ui_thread = thread::spawn(move || {
-                        while *ui_running.lock() {
-                            // Check for Delete key press to quit
-                            if let Ok(crossterm::event::Event::Key(key)) =
-                                crossterm::event::poll(Duration::from_millis(100))
-                                    .and_then(|_| crossterm::event::read())
-                            {
-                                if let crossterm::event::KeyCode::Delete = key.code {
-                                    let _ = stop_tx.send(());
-                                    break;
-                                }
-                            }
+                    // Offer to carry on without the grab rather than fail
+                    let denied = mapper.denied_grabs();
+                    if !denied.is_empty() {
+                        let paths: Vec<String> =
+                            denied.iter().map(|p| p.display().to_string()).collect();
+                        let proceed = ui.prompt_yes_no(&format!(
+                            "Permission denied grabbing {}. Map without grabbing? \
+                             WARNING: other programs will also receive these keys, \
+                             so every mapped key is input twice.",
+                            paths.join(", ")
+                        ))?;
+                        if !proceed {
+                            continue;
                         }
-                    });
+                        mapper.ungrabbed.extend(denied);
+                    }
+
+                    ui.show_mapping_active(mapper.settings.stop_key)?;
 
                     // Start the mapping thread
                     match mapper.start_mapping() {
-                        Ok(thread_handle) => {
+                        Ok((thread_handle, session_rx)) => {
                             mapping_thread = Some(thread_handle);
 
-                            // Wait for signal from UI thread (user pressed Delete key)
-                            stop_rx.recv()?;
+                            // The stop key, the session menu and the panic
+                            // combo are all read by the mapping thread from
+                            // the grabbed keyboards, so each of them ends the
+                            // session with a Stopped event
+                            let mut profiles = Vec::new();
+                            loop {
+                                match session_rx.recv() {
+                                    Ok(mapping::SessionEvent::MenuOpened(status)) => {
+                                        profiles = config::saved_profiles();
+                                        ui.show_session_menu(&status, &profiles)?;
+                                    }
+                                    Ok(mapping::SessionEvent::SwitchProfile(index)) => {
+                                        // The session ends and the main loop
+                                        // starts the next one with the profile
+                                        if let Some(path) = profiles.get(index) {
+                                            switch_to = Some(path.clone());
+                                            mapper.stop_mapping();
+                                        }
+                                    }
+                                    Ok(mapping::SessionEvent::MenuClosed) => {
+                                        ui.show_mapping_active(mapper.settings.stop_key)?;
+                                    }
+                                    Ok(mapping::SessionEvent::Input { key, offset }) => {
+                                        ui.show_training_input(key, offset)?;
+                                    }
+                                    Ok(mapping::SessionEvent::Tuning {
+                                        controller,
+                                        axis_scale,
+                                    }) => {
+                                        // Kept for saving; the running session
+                                        // already uses it
+                                        mapper.controllers[controller].axis_scale = axis_scale;
+                                        ui.show_tuning(
+                                            &mapper.controllers[controller],
+                                            controller,
+                                        )?;
+                                    }
+                                    Ok(mapping::SessionEvent::Rumble { .. }) => {}
+                                    Ok(mapping::SessionEvent::Stopped) | Err(_) => {
+                                        // A profile switch goes straight on
+                                        // to the next session, not the menu
+                                        if switch_to.is_none() {
+                                            ui.notify_session_ended()?;
+                                        }
+                                        break;
+                                    }
+                                }
+                            }
 
                             // Stop the mapping process
-                            *running.lock() = false;
                             mapper.stop_mapping();
 
                             // Take the mapping thread out of the Option and join it
-                            if let Some(handle) = mapping_thread.take() {
-                                handle.join().expect("Failed to join mapping thread")?;
-                            }
+                            let result = match mapping_thread.take() {
+                                Some(handle) => {
+                                    handle.join().expect("Failed to join mapping thread")
+                                }
+                                None => Ok(()),
+                            };
 
-                            ui_thread.join().expect("Failed to join UI thread");
+                            if let Err(e) = result {
+                                reselect_keyboard(&mut ui, &mut mapper, e)?;
+                            }
                         }
                         Err(e) => {
                             ui.prompt_yes_no(&format!(
@@ -131,9 +378,163 @@ fn main() -> Result<()> {
                 }
             }
             4 => {
+                // Edit an existing controller
+                if mapper.controllers.is_empty() {
+                    ui.prompt_yes_no("No controllers created yet. Create one first?")?;
+                    continue;
+                }
+
+                if let Some(index) = ui.select_controller(&mapper.controllers)?
+                    && let Err(e) = ui.edit_controller(&mut mapper, index)
+                {
+                    reselect_keyboard(&mut ui, &mut mapper, e)?;
+                }
+            }
+            5 => {
+                // Remove a controller, destroying its device
+                if mapper.controllers.is_empty() {
+                    ui.prompt_yes_no("No controllers to remove. Continue?")?;
+                    continue;
+                }
+
+                if let Some(index) = ui.select_controller(&mapper.controllers)? {
+                    let name = mapper.controllers[index].name.clone();
+                    let message = match mapper.remove_controller(index) {
+                        Ok(()) => format!("Removed {}. Continue?", name),
+                        Err(e) => format!("Failed to remove {}: {}. Continue?", name, e),
+                    };
+                    ui.prompt_yes_no(&message)?;
+                }
+            }
+            6 => {
+                // Export the last mapping session as a regression fixture
+                let recording = mapper.recording.lock().clone();
+                if recording.is_empty() {
+                    ui.prompt_yes_no("No recorded session yet. Start mapping first. Continue?")?;
+                    continue;
+                }
+
+                if let Some(path) = ui
+                    .prompt_save_path("Fixture file to write (Esc to cancel):", |input| {
+                        Ok(PathBuf::from(input))
+                    })?
+                {
+                    let message = match mapping::export_fixture(
+                        &path,
+                        &mapper.controllers,
+                        mapper.settings.pulse_ms,
+                        &recording,
+                    ) {
+                        Ok(()) => format!(
+                            "Wrote {} events to {}. Continue?",
+                            recording.len(),
+                            path.display()
+                        ),
+                        Err(e) => write_failure("fixture", &path, &e),
+                    };
+                    ui.prompt_yes_no(&message)?;
+                }
+            }
+            7 => {
+                // Save the controllers and session settings. A .json file
+                // gets the shareable mappings instead.
+                if let Some(path) = ui.prompt_save_path(
+                    "Profile name or file to write (Esc to cancel):",
+                    config::profile_path,
+                )? {
+                    let result = if let Err(e) = config::ensure_dir() {
+                        Err(e.into())
+                    } else if is_json(&path) {
+                        let json = mapping::export_json(&mapper.profile().controllers);
+                        fs::write(&path, json).map_err(anyhow::Error::from)
+                    } else {
+                        profile::save_profile(&path, &mapper.profile())
+                    };
+                    let message = match result {
+                        Ok(()) if is_json(&path) => {
+                            format!("Exported mappings to {}. Continue?", path.display())
+                        }
+                        Ok(()) => {
+                            let message = format!("Saved profile to {}. Continue?", path.display());
+                            mapper.profile_path = Some(path);
+                            message
+                        }
+                        Err(e) => write_failure("profile", &path, &e),
+                    };
+                    ui.prompt_yes_no(&message)?;
+                }
+            }
+            8 => {
+                // Replace the session with a saved profile, or with shared
+                // mappings from a .json file, keeping the session settings
+                if let Some(name) =
+                    ui.prompt_input("Profile name or file to load (Esc to cancel):")?
+                    && !name.is_empty()
+                {
+                    let path = match config::profile_path(&name) {
+                        Ok(path) => path,
+                        Err(e) => {
+                            ui.prompt_yes_no(&format!("Failed to load profile: {}. Continue?", e))?;
+                            continue;
+                        }
+                    };
+                    let result = if is_json(&path) {
+                        fs::read_to_string(&path)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|json: String| mapping::import_json(&json))
+                            .and_then(|controllers| {
+                                mapper.apply_profile(profile::Profile {
+                                    global: mapper.settings.clone(),
+                                    controllers,
+                                })
+                            })
+                    } else {
+                        profile::load_profile(&path)
+                            .and_then(|profile| mapper.apply_profile(profile))
+                    };
+                    let message = match result {
+                        Ok(()) if is_json(&path) => format!(
+                            "Imported {} controller(s) from {}.{} Continue?",
+                            mapper.controllers.len(),
+                            path.display(),
+                            ui::describe_unsupported(&mapper.controllers)
+                        ),
+                        Ok(()) => {
+                            let message = format!(
+                                "Loaded {} controller(s) from {}.{} Continue?",
+                                mapper.controllers.len(),
+                                path.display(),
+                                ui::describe_unsupported(&mapper.controllers)
+                            );
+                            mapper.profile_path = Some(path);
+                            message
+                        }
+                        Err(e) => format!("Failed to load profile: {}. Continue?", e),
+                    };
+                    ui.prompt_yes_no(&message)?;
+                }
+            }
+            9 => {
                 // Exit
                 break 'main_loop;
             }
+            10 => {
+                // Swap controllers' places, which sets the order profiles
+                // save them in
+                if mapper.controllers.len() < 2 {
+                    ui.prompt_yes_no("Reordering needs two controllers or more. Continue?")?;
+                    continue;
+                }
+
+                ui.reorder_controllers(&mut mapper)?;
+                if !ui.is_quiet() {
+                    ui.prompt_yes_no(
+                        "Games that number players by device see the new order once the \
+                         controllers are recreated, e.g. by saving and loading the profile. \
+                         Continue?",
+                    )?;
+                }
+            }
             _ => unreachable!(),
         }
     }
@@ -147,3 +548,274 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Whether `path` names a shareable JSON mappings file rather than a profile.
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+/// `Controller N` for the lowest N from the controller count up that isn't
+/// taken, so names stay unique after a controller is removed.
+fn next_controller_name(controllers: &[controller::VirtualController]) -> String {
+    (controllers.len() + 1..)
+        .map(|n| format!("Controller {}", n))
+        .find(|name| controllers.iter().all(|c| c.name != *name))
+        .expect("controller numbers are unbounded")
+}
+
+/// Recovers from a source device being unplugged mid-session by having the
+/// user pick a keyboard again. Any other error is returned as is.
+fn reselect_keyboard(
+    ui: &mut ui::UI,
+    mapper: &mut mapping::DeviceMapper,
+    error: anyhow::Error,
+) -> Result<()> {
+    let Some(error::AppError::DeviceDisconnected(path)) = error.downcast_ref() else {
+        return Err(error);
+    };
+    let path = PathBuf::from(path);
+    let lost_key = mapper
+        .sources
+        .iter()
+        .find(|source| source.path == path)
+        .map(device::InputDevice::stable_key);
+
+    // Capturing leaves raw mode off when it fails
+    ui.init()?;
+    ui.prompt_yes_no(&format!("{}. Continue?", error))?;
+
+    // The same keyboard plugged back in, likely under a new path, comes
+    // first
+    let keyboards = device::discover_keyboards()?;
+    let replugged = lost_key.and_then(|key| keyboards.iter().position(|k| k.stable_key() == key));
+    let last_used = replugged.or_else(|| device::find_last_keyboard(&keyboards));
+    let Some(selected_idx) = ui.select_device(&keyboards, last_used)? else {
+        return Ok(());
+    };
+    let keyboard = keyboards
+        .into_iter()
+        .nth(selected_idx)
+        .expect("Selected keyboard not found");
+
+    mapper.replace_source(&path, keyboard)
+}
+
+/// Gives `controller` the bindings of the first controller in
+/// [`config::default_mapping_path`] when the user has written that file, or
+/// the built-in layout otherwise. Returns where the bindings came from.
+fn apply_default_mapping(controller: &mut controller::VirtualController) -> String {
+    let loaded = config::default_mapping_path()
+        .map_err(anyhow::Error::from)
+        .and_then(|path| Ok(profile::load_default_mapping(&path)?.map(|mapping| (path, mapping))));
+    match loaded {
+        Ok(Some((path, mapping))) => {
+            *controller.key_mapping.write() = mapping;
+            format!("the default from {}", path.display())
+        }
+        Ok(None) => {
+            controller.apply_default_mapping();
+            "the built-in default".to_string()
+        }
+        Err(e) => {
+            controller.apply_default_mapping();
+            format!("the built-in default, since {:#}", e)
+        }
+    }
+}
+
+/// Runs the rebind screen on the just created first controller, then offers
+/// to keep the result as the default mapping for later first controllers.
+fn edit_default_mapping(ui: &mut ui::UI, mapper: &mut mapping::DeviceMapper) -> Result<()> {
+    let index = mapper.controllers.len() - 1;
+    if let Err(e) = ui.edit_controller_bindings(mapper, index) {
+        return reselect_keyboard(ui, mapper, e);
+    }
+
+    let path = match config::default_mapping_path() {
+        Ok(path) => path,
+        Err(e) => {
+            ui.prompt_yes_no(&format!("{}. Continue?", e))?;
+            return Ok(());
+        }
+    };
+    if !ui.prompt_yes_no(&format!(
+        "Use these bindings for first controllers from now on ({})?",
+        path.display()
+    ))? {
+        return Ok(());
+    }
+
+    let default = profile::Profile {
+        global: mapper.settings.clone(),
+        controllers: vec![mapper.controllers[index].config()],
+    };
+    let result = config::ensure_dir()
+        .map_err(anyhow::Error::from)
+        .and_then(|_| profile::save_profile(&path, &default));
+    let message = match result {
+        Ok(()) => format!("Saved the default mapping to {}. Continue?", path.display()),
+        Err(e) => write_failure("default mapping", &path, &e),
+    };
+    ui.prompt_yes_no(&message)?;
+
+    Ok(())
+}
+
+/// `--start` and `--quick`: maps the `--device`s, or else the keyboard used
+/// last time (the first one if it is not plugged in), to the controllers of
+/// the `--profile`, or to a single controller with the default mapping, and
+/// runs until stopped, without any menus.
+fn run_headless(options: &cli::Options) -> Result<()> {
+    let mut keyboards = Vec::new();
+    for path in &options.devices {
+        let device =
+            evdev::Device::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+        keyboards.push(device::InputDevice::new(path.clone(), device));
+    }
+    if keyboards.is_empty() {
+        let mut discovered = device::discover_keyboards()?;
+        let index = device::find_last_keyboard(&discovered).unwrap_or(0);
+        keyboards.push(discovered.swap_remove(index));
+    }
+    let keyboard_name = keyboards
+        .iter()
+        .map(|keyboard| keyboard.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" and ");
+
+    let mut keyboards = keyboards.into_iter();
+    let mut mapper = mapping::DeviceMapper::new(keyboards.next().expect("keyboards is not empty"));
+    for keyboard in keyboards {
+        mapper.add_keyboard(keyboard)?;
+    }
+    match &options.profile {
+        Some(path) => {
+            mapper.apply_profile(profile::load_profile(path)?)?;
+            mapper.profile_path = Some(path.clone());
+            println!(
+                "Mapping {} to {} controller(s) from {}.",
+                keyboard_name,
+                mapper.controllers.len(),
+                path.display()
+            );
+        }
+        None => {
+            let mut controller = controller::VirtualController::new(
+                "Controller 1",
+                controller::ControllerLayout::default(),
+            )?;
+            let source = apply_default_mapping(&mut controller);
+            println!(
+                "Mapping {} to {} with {}.",
+                keyboard_name, controller.name, source
+            );
+            mapper.add_controller(controller);
+        }
+    }
+    let conflicts = mapper.conflicts();
+    if !conflicts.is_empty() {
+        let names: Vec<String> = mapper.controllers.iter().map(|c| c.name.clone()).collect();
+        eprintln!(
+            "Warning: these keys drive several controllers at once: {}.",
+            ui::describe_conflicts(&names, &conflicts)
+        );
+    }
+
+    // A profile keeps its own pulse length unless one was given
+    if let Some(pulse_ms) = options.pulse_ms {
+        mapper.settings.pulse_ms = pulse_ms;
+    }
+    mapper.forward_name = options.forward_name.clone();
+    if let Some(signals) = &options.stop_signals {
+        mapper.stop_signals = signals.clone();
+    }
+    mapper.allow_exec = options.allow_exec;
+
+    let (handle, session_rx) = mapper.start_mapping()?;
+    let stop_key = mapper.settings.stop_key;
+    if stop_key != 0 {
+        println!(
+            "Press Ctrl+C or {} to stop, or {} and then 2.",
+            names::key_name(evdev::KeyCode::new(stop_key)),
+            names::key_name(evdev::KeyCode::new(mapper.settings.menu_key))
+        );
+    } else {
+        println!(
+            "Press Ctrl+C to stop, or {} and then 2.",
+            names::key_name(evdev::KeyCode::new(mapper.settings.menu_key))
+        );
+    }
+
+    // The session ends on a signal or from its menu; the receiver
+    // disconnects once the mapping thread is done
+    for event in session_rx {
+        match event {
+            mapping::SessionEvent::MenuOpened(_) => {
+                println!("Paused. Press 1 to resume or 2 to stop.");
+            }
+            mapping::SessionEvent::MenuClosed => println!("Resumed."),
+            mapping::SessionEvent::Stopped => break,
+            mapping::SessionEvent::Rumble {
+                controller,
+                strength,
+            } => println!(
+                "{} rumble: strong {}, weak {}",
+                mapper.controllers[controller].name, strength.strong, strength.weak
+            ),
+            mapping::SessionEvent::Input { .. }
+            | mapping::SessionEvent::Tuning { .. }
+            | mapping::SessionEvent::SwitchProfile(_) => {}
+        }
+    }
+
+    mapper.stop_mapping();
+    handle.join().expect("Failed to join mapping thread")
+}
+
+/// Message for a failed file write, spelling out the common I/O causes.
+fn write_failure(what: &str, path: &Path, error: &anyhow::Error) -> String {
+    let reason = match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::PermissionDenied) => "permission denied".to_string(),
+        Some(io::ErrorKind::NotFound) => "the directory does not exist".to_string(),
+        Some(io::ErrorKind::StorageFull) => "the disk is full".to_string(),
+        Some(io::ErrorKind::ReadOnlyFilesystem) => "the file system is read-only".to_string(),
+        _ => error.to_string(),
+    };
+
+    format!(
+        "Could not write {} to {}: {}. Continue?",
+        what,
+        path.display(),
+        reason
+    )
+}
+
+/// Lists forwarded keyboards left behind by earlier sessions. They can't be
+/// removed from outside; they disappear once their owning process exits.
+fn report_leftovers(forward_name: Option<&str>) {
+    let leftovers = device::find_forwarded_keyboards(forward_name);
+    if leftovers.is_empty() {
+        println!("No leftover forwarded keyboards found.");
+        return;
+    }
+
+    println!("Leftover forwarded keyboards:");
+    for path in &leftovers {
+        println!("  {}", path.display());
+    }
+
+    let pids = device::other_instances();
+    if pids.is_empty() {
+        println!(
+            "\nNo other inputmaster process was found. Another program may own them; \
+             they are removed when it exits."
+        );
+        return;
+    }
+
+    println!("\nThey are removed when their session exits. Stop it with:");
+    for pid in pids {
+        println!("  kill {}", pid);
+    }
+}
@@ -1,235 +1,4922 @@
-use crate::controller::VirtualController;
-use crate::device::InputDevice;
-use anyhow::Result;
-use crossbeam_channel::{bounded, select, tick};
+use crate::binding::{
+    Binding, Chord, DEFAULT_CHORD_PRIORITY, DEFAULT_LAYER_PRIORITY, DEFAULT_SHORTCUT_PRIORITY,
+    DoubleTap, KeyState, MAX_TURBO_HZ, Mapping, Modifier, Shortcut, Target, normalize_value,
+    parse_modifiers,
+};
+use crate::clock::{Clock, ManualClock, SystemClock};
+use crate::controller::{
+    ControllerConfig, ControllerKind, ControllerLayout, DpadMode, MouseStick, RumbleStrength,
+    STICK_AXIS_PAIRS, STICK_MAX, STICK_MIN, TRIGGER_MAX, VirtualController, analog_trigger,
+    dpad_hat_direction, mouse_to_axis, normalize_axis_pair,
+};
+use crate::device::{self, InputDevice};
+use crate::error::AppError;
+use crate::names::{axis_from_name, axis_name, key_from_name, key_name};
+use crate::profile::{self, Profile};
+use anyhow::{Context, Result};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, bounded, select, unbounded};
+use evdev::AbsoluteAxisCode;
 use evdev::Device;
 use evdev::EventType;
 use evdev::InputEvent;
 use evdev::KeyCode;
+use evdev::RelativeAxisCode;
+use evdev::SynchronizationCode;
+use evdev::uinput::VirtualDevice;
 use parking_lot::{Mutex, RwLock};
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-pub struct DeviceMapper {
-    pub keyboard: InputDevice,
-    pub controllers: Vec<VirtualController>,
-    pub running: Arc<Mutex<bool>>,
-    pub mapped_keys: Arc<RwLock<HashSet<KeyCode>>>,
+/// Upper bound on the number of key events kept for a single session
+/// recording, so a long session can't grow the buffer without limit.
+const MAX_RECORDED_EVENTS: usize = 10_000;
+
+/// Default time a tapped button is held down: one frame at 60 Hz, so a game
+/// that polls once per frame still sees the press.
+pub const DEFAULT_PULSE_MS: u64 = 16;
+
+/// Resolved outputs that may queue up between the reader and emit threads
+/// before reading blocks.
+const EMIT_QUEUE_LEN: usize = 256;
+
+/// Work for the emit thread.
+enum Emit {
+    /// Changes to send, in order, and when the reader queued them.
+    Outputs(Vec<ControllerOutput>, Instant),
+    /// Release every button and center every axis on every controller,
+    /// whether or not the session saw it held, then answer on the sender.
+    ReleaseAll(Sender<()>),
 }
 
-impl DeviceMapper {
-    pub fn new(keyboard: InputDevice) -> Self {
-        DeviceMapper {
-            keyboard,
-            controllers: Vec::new(),
-            running: Arc::new(Mutex::new(false)),
-            mapped_keys: Arc::new(RwLock::new(HashSet::new())),
+impl Emit {
+    fn outputs(outputs: Vec<ControllerOutput>) -> Self {
+        Emit::Outputs(outputs, Instant::now())
+    }
+}
+
+/// How long outputs wait between the reader queuing them and the emit
+/// thread writing them, reported when a session ends. This is the delay
+/// running the emitter at real-time priority is meant to keep short.
+#[derive(Default)]
+struct EmitLatency {
+    batches: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl EmitLatency {
+    fn record(&mut self, latency: Duration) {
+        self.batches += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    fn mean(&self) -> Option<Duration> {
+        (self.batches > 0).then(|| self.total / self.batches)
+    }
+}
+
+/// Where the emit thread writes: a virtual controller in a session.
+trait Sink {
+    fn name(&self) -> &str;
+    fn emit_frame(&mut self, frame: &[(Target, i32)]) -> Result<()>;
+    fn release_all(&mut self) -> Result<()>;
+    fn answer_force_feedback(&mut self) -> Result<()>;
+}
+
+impl Sink for VirtualController {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn emit_frame(&mut self, frame: &[(Target, i32)]) -> Result<()> {
+        VirtualController::emit_frame(self, frame)
+    }
+
+    fn release_all(&mut self) -> Result<()> {
+        VirtualController::release_all(self)
+    }
+
+    fn answer_force_feedback(&mut self) -> Result<()> {
+        VirtualController::answer_force_feedback(self)
+    }
+}
+
+/// Pause before retrying an emit that failed with a transient error.
+const EMIT_RETRY_DELAY: Duration = Duration::from_millis(1);
+
+/// How often the emit thread answers force-feedback requests from games.
+const FF_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Real-time priority requested for the emit thread. Low within the
+/// SCHED_FIFO range, just enough to preempt ordinary processes.
+const EMIT_PRIORITY: libc::c_int = 10;
+
+/// Default for how long the mapping loop waits for input before rechecking
+/// whether it should stop.
+pub const DEFAULT_TICK_MS: u64 = 100;
+
+/// Signals that stop a running session unless set otherwise: SIGINT, SIGTERM
+/// and SIGHUP. See [`DeviceMapper::stop_signals`].
+pub const DEFAULT_STOP_SIGNALS: [i32; 3] = [
+    signal_hook::consts::SIGINT,
+    signal_hook::consts::SIGTERM,
+    signal_hook::consts::SIGHUP,
+];
+
+/// Session-wide behavior, as opposed to the per-controller bindings. Saved in
+/// the `global` section of a profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// How long tapped buttons stay down, in milliseconds.
+    pub pulse_ms: u64,
+    /// Longest wait for input between checks for a stop request.
+    pub tick_ms: u64,
+    /// Whether unmapped keys of a grabbed source are passed back to the
+    /// system through a forwarding keyboard, or swallowed.
+    pub forward_unmapped: bool,
+    /// Key code that opens the session menu while mapping; 0 disables it.
+    pub menu_key: u16,
+    /// Key code that ends the session; 0 disables it. It is read from the
+    /// grabbed sources like the menu key, so it works whichever window has
+    /// focus, and it takes precedence over a binding of the same key.
+    pub stop_key: u16,
+    /// What SIGHUP does to a running session.
+    pub hangup: HangupAction,
+    /// Whether the tuning hotkeys are active while mapping. See [`Hotbar`].
+    pub hotbar: bool,
+}
+
+/// What a running session does on SIGHUP. Other signals stop it if they are
+/// among [`DeviceMapper::stop_signals`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HangupAction {
+    /// Stop the session.
+    #[default]
+    Quit,
+    /// Reread the bindings from the last saved or loaded profile without
+    /// releasing the grab, as daemons conventionally do.
+    Reload,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            pulse_ms: DEFAULT_PULSE_MS,
+            tick_ms: DEFAULT_TICK_MS,
+            forward_unmapped: true,
+            menu_key: KeyCode::KEY_PAUSE.0,
+            stop_key: KeyCode::KEY_DELETE.0,
+            hangup: HangupAction::default(),
+            hotbar: false,
         }
     }
+}
 
-    pub fn add_controller(&mut self, controller: VirtualController) {
-        // Update mapped_keys set with the controller's key mappings
-        let mut mapped_keys = self.mapped_keys.write();
-        for key in controller.key_mapping.read().keys() {
-            mapped_keys.insert(*key);
+/// What the mapping thread tells the UI while a session runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    /// The menu key was pressed. Dispatch is paused until the menu closes.
+    MenuOpened(Vec<ControllerStatus>),
+    MenuClosed,
+    /// The session was stopped from the menu.
+    Stopped,
+    /// A number key from 3 up was pressed in the menu, picking the saved
+    /// profile at this index to continue with. The session keeps running
+    /// until it is stopped.
+    SwitchProfile(usize),
+    /// A bound key was pressed, `offset` after the session started.
+    Input {
+        key: KeyCode,
+        offset: Duration,
+    },
+    /// A hotbar key selected `controller`, changed its axis scale or switched
+    /// it on or off.
+    Tuning {
+        controller: usize,
+        axis_scale: f32,
+    },
+    /// A game started or stopped rumbling `controller`.
+    Rumble {
+        controller: usize,
+        strength: RumbleStrength,
+    },
+}
+
+/// Keys that select the controller the hotbar tunes, by position.
+const HOTBAR_SELECT_KEYS: [KeyCode; 4] = [
+    KeyCode::KEY_F1,
+    KeyCode::KEY_F2,
+    KeyCode::KEY_F3,
+    KeyCode::KEY_F4,
+];
+
+/// Held together with a nudge key to change the selected controller's axis
+/// scale.
+const HOTBAR_MODIFIER: KeyCode = KeyCode::KEY_RIGHTCTRL;
+
+/// Axis scale change per press of each nudge key.
+const HOTBAR_NUDGE_KEYS: [(KeyCode, f32); 2] = [(KeyCode::KEY_UP, 0.1), (KeyCode::KEY_DOWN, -0.1)];
+
+/// Held with the modifier, switches the selected controller on or off.
+const HOTBAR_TOGGLE_KEY: KeyCode = KeyCode::KEY_SPACE;
+
+/// Pressed while a Ctrl and an Alt key are held, ends the session at once.
+const PANIC_KEY: KeyCode = KeyCode::KEY_K;
+
+/// Watches for the panic combo, Ctrl+Alt+K. The keys are read straight from
+/// the grabbed sources, ahead of the menu, the hotbar and the bindings, so
+/// the combo works whatever they are bound to and whichever window, such as
+/// a fullscreen game, has focus.
+#[derive(Default)]
+struct PanicCombo {
+    ctrl: HashSet<KeyCode>,
+    alt: HashSet<KeyCode>,
+}
+
+impl PanicCombo {
+    /// Returns whether the event completes the combo.
+    fn handle(&mut self, key: KeyCode, value: i32) -> bool {
+        let held = match key {
+            KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL => &mut self.ctrl,
+            KeyCode::KEY_LEFTALT | KeyCode::KEY_RIGHTALT => &mut self.alt,
+            _ => {
+                return key == PANIC_KEY
+                    && value == 1
+                    && !self.ctrl.is_empty()
+                    && !self.alt.is_empty();
+            }
+        };
+
+        if value == 0 {
+            held.remove(&key);
+        } else {
+            held.insert(key);
         }
+        false
+    }
+}
 
-        self.controllers.push(controller);
+/// What a hotbar key asks for.
+enum HotbarAction {
+    /// Nothing, but the event is the hotbar's and goes no further.
+    Swallow,
+    /// Make `Hotbar::selected` the controller to tune.
+    Select,
+    /// Change the selected controller's axis scale by this much.
+    Nudge(f32),
+    /// Switch the selected controller on or off.
+    Toggle,
+}
+
+/// Live tuning keys handled inside the mapping loop, so they work while the
+/// keyboard is grabbed: F1-F4 select a controller, Right Ctrl + Up/Down
+/// nudge its axis scale and Right Ctrl + Space switch it on or off. A select
+/// key or the modifier that is bound to something is left to its binding,
+/// and the nudge and toggle keys only act while the modifier is held.
+struct Hotbar {
+    selected: usize,
+    modifier_held: bool,
+    /// Hotkeys that aren't bound, and so belong to the hotbar.
+    free: HashSet<KeyCode>,
+}
+
+impl Hotbar {
+    fn new(mapped_keys: &HashSet<KeyCode>) -> Self {
+        Hotbar {
+            selected: 0,
+            modifier_held: false,
+            free: HOTBAR_SELECT_KEYS
+                .into_iter()
+                .chain([HOTBAR_MODIFIER])
+                .filter(|key| !mapped_keys.contains(key))
+                .collect(),
+        }
     }
 
-    pub fn start_mapping(&mut self) -> Result<thread::JoinHandle<Result<()>>> {
-        // Check if we have any controllers
-        if self.controllers.is_empty() {
-            return Err(anyhow::anyhow!("No controllers available to map"));
+    /// Hotkeys given up to bindings, for warning the user.
+    fn shadowed(&self) -> Vec<KeyCode> {
+        HOTBAR_SELECT_KEYS
+            .into_iter()
+            .chain([HOTBAR_MODIFIER])
+            .filter(|key| !self.free.contains(key))
+            .collect()
+    }
+
+    /// Returns `None` for events the hotbar doesn't take.
+    fn handle(&mut self, key: KeyCode, value: i32, controllers: usize) -> Option<HotbarAction> {
+        if key == HOTBAR_MODIFIER && self.free.contains(&key) {
+            self.modifier_held = value != 0;
+            return Some(HotbarAction::Swallow);
+        }
+
+        if let Some(index) = HOTBAR_SELECT_KEYS.iter().position(|k| *k == key)
+            && self.free.contains(&key)
+        {
+            if value == 1 && index < controllers {
+                self.selected = index;
+                return Some(HotbarAction::Select);
+            }
+            return Some(HotbarAction::Swallow);
         }
 
-        // Store the path to the keyboard
-        let keyboard_path = self.keyboard.path.clone();
+        // Releases go through, so a key pressed before the modifier
+        // doesn't stay down
+        if self.modifier_held
+            && value != 0
+            && let Some((_, step)) = HOTBAR_NUDGE_KEYS.iter().find(|(k, _)| *k == key)
+        {
+            return Some(if value == 1 {
+                HotbarAction::Nudge(*step)
+            } else {
+                HotbarAction::Swallow
+            });
+        }
 
-        // Store the key mappings and device names we need to recreate
-        let controller_settings: Vec<_> = self
-            .controllers
+        if self.modifier_held && value != 0 && key == HOTBAR_TOGGLE_KEY {
+            return Some(if value == 1 {
+                HotbarAction::Toggle
+            } else {
+                HotbarAction::Swallow
+            });
+        }
+
+        None
+    }
+}
+
+/// A controller's name and the buttons and axes it currently holds away
+/// from rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControllerStatus {
+    pub name: String,
+    pub active: Vec<Target>,
+}
+
+/// A keyboard key event captured during a mapping session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedEvent {
+    /// Milliseconds since the session started.
+    pub offset_ms: u64,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// A controller button or axis change produced by a keyboard event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerOutput {
+    /// Index of the controller in creation order.
+    pub controller: usize,
+    pub target: Target,
+    pub value: i32,
+}
+
+/// Per-controller state the dispatch keeps between events.
+#[derive(Debug, Default)]
+pub struct ControllerState {
+    /// Whether each toggled axis is currently latched away from center.
+    axis_latches: HashMap<AbsoluteAxisCode, bool>,
+    /// Index of the value each cycling key last moved its axis to.
+    axis_cycles: HashMap<KeyCode, usize>,
+    /// How many keys bound to each button are down, so a button bound to
+    /// several keys stays pressed until the last of them is released.
+    held_keys: HashMap<KeyCode, u32>,
+    /// D-pad buttons held down, for controllers reporting a hat.
+    dpad_held: HashSet<KeyCode>,
+    /// Set by a sticky key; the next button pressed stays held.
+    sticky_armed: bool,
+    /// Buttons held by sticky mode until their next press.
+    sticky_held: HashSet<KeyCode>,
+    /// Buttons whose next release is dropped, because the press before it
+    /// latched or let go of a sticky button.
+    swallow_release: HashSet<KeyCode>,
+    /// Whether a half-press key is down.
+    half_pressed: bool,
+    /// The layer whose key is held, if any.
+    active_layer: Option<String>,
+    /// Keys pressed while a layer was active, with the layer binding they
+    /// pressed, so their release goes to the same binding.
+    layer_held: HashMap<KeyCode, Binding>,
+    /// Keys whose next release is dropped, because releasing their layer
+    /// or chord already let go of what they pressed.
+    dropped_releases: HashSet<KeyCode>,
+    /// Chord keys down and waiting for the rest of their chord, in the order
+    /// they went down.
+    chord_buffer: Vec<KeyCode>,
+    /// The device each key in `chord_buffer` came from, for the binding it
+    /// goes through to if no chord completes.
+    chord_sources: Vec<Option<PathBuf>>,
+    /// When the keys in `chord_buffer` stop waiting and go through alone.
+    chord_due: Option<Instant>,
+    /// Chords whose button is down.
+    active_chords: Vec<Chord>,
+    /// When each double-tap key last went down, while it waits for a second
+    /// press.
+    last_presses: HashMap<KeyCode, Instant>,
+    /// Where the bindings put each stick axis before diagonals were rounded.
+    stick_deflections: HashMap<AbsoluteAxisCode, i32>,
+    /// Shortcut keys whose button is down, with that button, so the key's
+    /// release goes to the shortcut whatever modifiers are held by then.
+    active_shortcuts: HashMap<KeyCode, KeyCode>,
+}
+
+impl ControllerState {
+    /// Counts a key bound to `button` going down or up. Returns whether the
+    /// button itself changes: the first key down presses it and the last
+    /// key up releases it. A release with no press counted goes through, for
+    /// keys that were already down when the session started.
+    fn count_held(&mut self, button: KeyCode, value: i32) -> bool {
+        let held = self.held_keys.entry(button).or_insert(0);
+
+        if value != 0 {
+            *held += 1;
+            return *held == 1;
+        }
+
+        *held = held.saturating_sub(1);
+        *held == 0
+    }
+
+    /// Runs a plain button change through sticky mode. Returns the value to
+    /// emit, or `None` when sticky mode absorbs the event. Sticky mode takes
+    /// precedence over the button's own press/release.
+    fn sticky_filter(&mut self, button: KeyCode, value: i32) -> Option<i32> {
+        if value == 0 {
+            return (!self.swallow_release.remove(&button)).then_some(0);
+        }
+
+        if self.sticky_held.remove(&button) {
+            // The next press lets go of a latched button
+            self.swallow_release.insert(button);
+            return Some(0);
+        }
+
+        if self.sticky_armed {
+            self.sticky_armed = false;
+            self.sticky_held.insert(button);
+            self.swallow_release.insert(button);
+        }
+
+        Some(value)
+    }
+
+    /// Records a D-pad button change and returns the hat axis it moves with
+    /// the axis' new position. Opposite directions held together cancel out.
+    fn hat_output(&mut self, button: KeyCode, pressed: bool) -> Option<(AbsoluteAxisCode, i32)> {
+        let (axis, _) = dpad_hat_direction(button)?;
+
+        if pressed {
+            self.dpad_held.insert(button);
+        } else {
+            self.dpad_held.remove(&button);
+        }
+
+        let position = self
+            .dpad_held
             .iter()
-            .map(|c| {
-                let mapping = c.key_mapping.read().clone();
-                (c.name.clone(), mapping)
-            })
-            .collect();
+            .filter_map(|held| dpad_hat_direction(*held))
+            .filter(|(held_axis, _)| *held_axis == axis)
+            .map(|(_, direction)| direction)
+            .sum();
 
-        *self.running.lock() = true;
-        let running = self.running.clone();
-        let mapped_keys_arc = self.mapped_keys.clone();
+        Some((axis, position))
+    }
+}
 
-        // Create a signal channel
-        let (signal_tx, signal_rx) = bounded(1);
+/// Resolves source key events into controller outputs, keeping each
+/// controller's state between events. The mapping loop and fixture replay
+/// both go through this, so a replay reproduces what a session emitted.
+/// Timers read the time from `C`.
+pub struct Dispatcher<C: Clock = SystemClock> {
+    clock: C,
+    controllers: Vec<DispatchSlot>,
+    /// How long a tapped button stays down.
+    pulse: Duration,
+    pending: Vec<PendingRelease>,
+    /// Turbo buttons whose key is held.
+    turbo: Vec<TurboTimer>,
+    /// Commands of `exec` bindings pressed since the last
+    /// [`Dispatcher::take_commands`], with their controller.
+    commands: Vec<(usize, String)>,
+    /// Mouse-driven sticks waiting to return to center.
+    mouse_recenters: Vec<MouseRecenter>,
+    /// Axes held by keys, in the order their keys went down.
+    ramps: Vec<AxisRamp>,
+    /// Modifier keys down on any source, for shortcuts.
+    held_modifiers: HashSet<KeyCode>,
+}
 
-        // Handle SIGINT, SIGTERM, and SIGHUP
-        let signal_running = running.clone();
-        let signal_tx_clone = signal_tx.clone();
+/// An axis held by a key, easing from rest to the binding's deflection.
+struct AxisRamp {
+    controller: usize,
+    /// The source key holding it.
+    key: KeyCode,
+    axis: AbsoluteAxisCode,
+    /// Where the axis ends up once the ramp is over.
+    value: i32,
+    start: Instant,
+    ramp: Duration,
+    /// How far along the ramp starts, in percent.
+    deadzone_pct: u32,
+    /// The value last sent, so steps that don't move the axis send nothing.
+    sent: i32,
+    /// When it moves next; `None` once it is all the way there.
+    due: Option<Instant>,
+}
 
-        // Set up signal handler
-        let _signal_thread = thread::spawn(move || {
-            let mut signals = signal_hook::iterator::Signals::new(&[
-                signal_hook::consts::SIGINT,
-                signal_hook::consts::SIGTERM,
-                signal_hook::consts::SIGHUP,
-            ])
-            .unwrap();
+/// A held turbo button, flipping between pressed and released.
+struct TurboTimer {
+    controller: usize,
+    /// The source key holding it.
+    key: KeyCode,
+    button: KeyCode,
+    pressed: bool,
+    /// When it flips next.
+    due: Instant,
+    /// Half of the turbo period: how long each press and each gap lasts.
+    half_period: Duration,
+}
 
-            for sig in signals.forever() {
-                println!("Received signal {:?}, shutting down...", sig);
-                *signal_running.lock() = false;
-                let _ = signal_tx_clone.send(());
-                break;
-            }
-        });
+/// A tapped button waiting for its release.
+struct PendingRelease {
+    due: Instant,
+    controller: usize,
+    button: KeyCode,
+}
 
-        let handle = thread::spawn(move || -> Result<()> {
-            let mut keyboard = Device::open(&keyboard_path)?;
+/// How often a ramping axis moves toward its deflection.
+const RAMP_STEP: Duration = Duration::from_millis(10);
 
-            // Get the mapped keys
-            let mapped_keys = mapped_keys_arc.read().clone();
+/// What held axis keys are limited to while a half-press key is down.
+const HALF_PRESS_FACTOR: f32 = 0.5;
 
-            // Create the controller devices
-            let mut controllers = Vec::new();
+/// A controller's mouse-driven right stick, due back at center once the
+/// mouse has been still for the stick's decay time.
+struct MouseRecenter {
+    due: Instant,
+    controller: usize,
+}
 
-            for (name, key_mapping) in controller_settings {
-                let controller = VirtualController::new(&name)?;
+/// What a key press on a controller can go to. Where their priorities tie,
+/// the order here decides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Shortcut,
+    Chord,
+    /// The active layer's binding or the key's own.
+    Binding,
+}
 
-                // Apply the key mappings
-                let mut mapping = controller.key_mapping.write();
-                for (key, target) in key_mapping {
-                    mapping.insert(key, target);
-                }
-                drop(mapping); // Explicitly release the write lock
+struct DispatchSlot {
+    mapping: Mapping,
+    /// Only events from these devices reach the controller; empty accepts all.
+    sources: Vec<PathBuf>,
+    dpad_mode: DpadMode,
+    axis_scale: f32,
+    normalize_diagonals: bool,
+    /// Where each declared axis returns to on release.
+    axis_rests: HashMap<AbsoluteAxisCode, i32>,
+    /// The lowest and highest value of each declared axis.
+    axis_ranges: HashMap<AbsoluteAxisCode, (i32, i32)>,
+    mouse_stick: Option<MouseStick>,
+    chords: Vec<Chord>,
+    chord_window: Duration,
+    double_taps: Vec<DoubleTap>,
+    shortcuts: Vec<Shortcut>,
+    state: ControllerState,
+}
 
-                controllers.push(controller);
+impl DispatchSlot {
+    /// Whether events from `source` reach this controller. `None` (as in
+    /// fixture replay) reaches every controller.
+    fn accepts(&self, source: Option<&Path>) -> bool {
+        match source {
+            Some(source) => {
+                self.sources.is_empty() || self.sources.iter().any(|wanted| wanted == source)
             }
+            None => true,
+        }
+    }
 
-            // Grab the keyboard exclusively
-            match keyboard.grab() {
-                Ok(_) => {
-                    println!("Keyboard grabbed successfully");
+    /// The binding `key` from `source` going to the raw `value` acts on: the
+    /// active layer's if it binds the key and doesn't rank below the key's
+    /// own, otherwise the key's own. A release goes to whatever the press
+    /// went to, or nowhere if releasing the layer already let go of it.
+    fn resolve(&mut self, source: Option<&Path>, key: KeyCode, value: i32) -> Option<Binding> {
+        if value == 0 {
+            if self.state.dropped_releases.remove(&key) {
+                return None;
+            }
+            if let Some(binding) = self.state.layer_held.remove(&key) {
+                return Some(binding);
+            }
+        } else if let Some((_, binding)) = self.layer_binding(source, key) {
+            let binding = binding.clone();
+            if value == 1 {
+                self.state.layer_held.insert(key, binding.clone());
+            }
+            return Some(binding);
+        }
 
-                    // Create virtual keyboard for passing through non-mapped keys
-                    let mut virtual_kbd = {
-                        let supported_keys = keyboard.supported_keys().unwrap_or_default();
-                        evdev::uinput::VirtualDevice::builder()?
-                            .name("Forwarded Keyboard")
-                            .with_keys(&supported_keys)?
-                            .build()?
-                    };
+        self.own_binding(source, key).cloned()
+    }
 
-                    // Create a ticker for periodic checking (100ms)
-                    let ticker = tick(Duration::from_millis(100));
-
-                    // Main processing loop
-                    while *running.lock() {
-                        // Use crossbeam's select for efficient waiting
-                        select! {
-                            recv(signal_rx) -> _ => {
-                                // Signal received, exit loop
-                                println!("Signal received, exiting keyboard mapping");
-                                break;
-                            },
-                            recv(ticker) -> _ => {
-                                // Periodic check if we should keep running
-                                if !*running.lock() {
-                                    break;
-                                }
-                            },
-                            default => {
-                                // Process keyboard events
-                                for ev in keyboard.fetch_events()? {
-                                    if ev.event_type() == EventType::KEY {
-                                        let key_code = KeyCode::new(ev.code());
-                                        let value = ev.value();
-
-                                        if mapped_keys.contains(&key_code) {
-                                            for controller in &mut controllers {
-                                                let target_key = {
-                                                    let mapping = controller.key_mapping.read();
-                                                    mapping.get(&key_code).copied()
-                                                }; // Read lock is dropped here
-
-                                                if let Some(target_key) = target_key {
-                                                    controller.handle_key_event(target_key, value)?;
-                                                }
-                                            }
-                                        } else {
-                                            // Forward to virtual keyboard
-                                            let events =
-                                                [InputEvent::new(EventType::KEY.0, key_code.0, value)];
-                                            virtual_kbd.emit(&events)?;
-                                        }
-                                    } else {
-                                        // Forward non-key events
-                                        let events = [ev];
-                                        virtual_kbd.emit(&events)?;
-                                    }
-                                }
-                            }
-                        }
-                    }
+    /// The binding `key` from `source` has outside layers: the one for keys
+    /// of that device, otherwise the usual one.
+    fn own_binding(&self, source: Option<&Path>, key: KeyCode) -> Option<&Binding> {
+        source
+            .and_then(|source| self.mapping.device_binding(source, &key))
+            .or_else(|| self.mapping.get(&key))
+    }
 
-                    // Always ungrab the keyboard before exiting
-                    match keyboard.ungrab() {
-                        Ok(_) => println!("Keyboard released successfully"),
-                        Err(e) => eprintln!("Error releasing keyboard: {}", e),
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Failed to grab keyboard: {}", e);
-                    return Err(anyhow::anyhow!("Failed to grab keyboard: {}", e));
-                }
-            }
+    /// The active layer's binding of `key` with the layer's priority, unless
+    /// the key's own binding from `source` ranks above it.
+    fn layer_binding(&self, source: Option<&Path>, key: KeyCode) -> Option<(i32, &Binding)> {
+        let layer = self.state.active_layer.as_deref()?;
+        let binding = self.mapping.layer_binding(layer, &key)?;
+        let priority = self.mapping.layer_priority(layer);
+        if self.own_binding(source, key).is_some() && self.mapping.priority(&key) > priority {
+            return None;
+        }
 
-            Ok(())
-        });
+        Some((priority, binding))
+    }
 
-        Ok(handle)
+    /// Priority of the binding a press of `key` from `source` goes to past
+    /// any chord or shortcut, if the key has one.
+    fn binding_priority(&self, source: Option<&Path>, key: KeyCode) -> Option<i32> {
+        match self.layer_binding(source, key) {
+            Some((priority, _)) => Some(priority),
+            None => self
+                .own_binding(source, key)
+                .map(|_| self.mapping.priority(&key)),
+        }
     }
 
-    pub fn stop_mapping(&self) {
-        *self.running.lock() = false;
-        thread::sleep(Duration::from_millis(200));
+    /// Whether `chord` may take its keys from `source`: it ranks no lower
+    /// than the binding of any of them.
+    fn chord_takes_keys(&self, chord: &Chord, source: Option<&Path>) -> bool {
+        chord.keys.iter().all(|key| {
+            self.binding_priority(source, *key)
+                .is_none_or(|priority| chord.priority >= priority)
+        })
     }
 
-    pub fn capture_key(&mut self) -> Result<KeyCode> {
-        // Wait for a key press from the keyboard
-        println!("Press a key to capture mapping...");
+    /// The rules a press of `key` from `source` can go to while `held`
+    /// modifiers are down, highest priority first.
+    fn press_rules(&self, source: Option<&Path>, key: KeyCode, held: &[Modifier]) -> Vec<Rule> {
+        let shortcut = self
+            .shortcuts
+            .iter()
+            .find(|shortcut| shortcut.key == key && shortcut.matches(held))
+            .map(|shortcut| shortcut.priority);
+        let chord = self
+            .chords
+            .iter()
+            .filter(|chord| chord.keys.contains(&key) && self.chord_takes_keys(chord, source))
+            .map(|chord| chord.priority)
+            .max();
+        let binding = self.binding_priority(source, key);
 
-        // Create a copy of the path for the capture thread
-        let keyboard_path = self.keyboard.path.clone();
+        let mut rules: Vec<(i32, Rule)> = [
+            (shortcut, Rule::Shortcut),
+            (chord, Rule::Chord),
+            (binding, Rule::Binding),
+        ]
+        .into_iter()
+        .filter_map(|(priority, rule)| Some((priority?, rule)))
+        .collect();
+        // Stable, so ties keep the order of `Rule`
+        rules.sort_by_key(|(priority, _)| Reverse(*priority));
 
-        // Use a channel to communicate between threads
-        let (tx, rx) = crossbeam_channel::bounded(1);
+        rules.into_iter().map(|(_, rule)| rule).collect()
+    }
 
-        // Spawn a thread to capture the key
-        let handle = thread::spawn(move || -> Result<()> {
-            let mut keyboard = Device::open(&keyboard_path)?;
+    /// The value `target` takes when let go: 0 for buttons and the axis'
+    /// rest value for axes.
+    fn rest(&self, target: Target) -> i32 {
+        match target {
+            Target::Button(_) => 0,
+            Target::Axis(axis) => self.axis_rests.get(&axis).copied().unwrap_or(0),
+        }
+    }
 
-            loop {
-                for event in keyboard.fetch_events()? {
-                    if event.event_type() == EventType::KEY && event.value() == 1 {
-                        // Key press (not release)
-                        let key_code = KeyCode::new(event.code());
-                        tx.send(key_code).unwrap();
-                        return Ok(());
-                    }
+    /// Where a held axis is at `now`, with the controller's axis scale and
+    /// half-press applied.
+    fn ramp_position(&self, ramp: &AxisRamp, now: Instant) -> i32 {
+        let rest = self.rest(Target::Axis(ramp.axis));
+        let from =
+            rest + (i64::from(ramp.value - rest) * i64::from(ramp.deadzone_pct) / 100) as i32;
+        let eased = ramp_value(from, ramp.value, now.duration_since(ramp.start), ramp.ramp);
+
+        let mut scale = self.axis_scale;
+        if self.state.half_pressed {
+            scale *= HALF_PRESS_FACTOR;
+        }
+        self.scale_axis(ramp.axis, eased, scale)
+    }
+
+    /// Applies `scale` to how far `value` is from the axis' rest, keeping
+    /// the result within the axis' range. Axes the controller doesn't
+    /// declare are kept within the stick range.
+    fn scale_axis(&self, axis: AbsoluteAxisCode, value: i32, scale: f32) -> i32 {
+        let rest = self.rest(Target::Axis(axis));
+        let (min, max) = self
+            .axis_ranges
+            .get(&axis)
+            .copied()
+            .unwrap_or((STICK_MIN, STICK_MAX));
+
+        (rest as f32 + (value - rest) as f32 * scale)
+            .round()
+            .clamp(min as f32, max as f32) as i32
+    }
+
+    /// Presses or releases `button`. Each controller reports the D-pad its
+    /// own way, so the same key can press a button on one controller and move
+    /// a hat on another. Triggers also move their analog axis all the way.
+    fn button_output(
+        &mut self,
+        controller: usize,
+        button: KeyCode,
+        value: i32,
+    ) -> Vec<ControllerOutput> {
+        if self.dpad_mode == DpadMode::Hat
+            && let Some((axis, position)) = self.state.hat_output(button, value != 0)
+        {
+            return vec![ControllerOutput {
+                controller,
+                target: Target::Axis(axis),
+                value: position,
+            }];
+        }
+
+        let mut outputs = vec![ControllerOutput {
+            controller,
+            target: Target::Button(button),
+            value,
+        }];
+
+        if let Some(axis) = analog_trigger(button) {
+            let target = Target::Axis(axis);
+            outputs.push(ControllerOutput {
+                controller,
+                target,
+                value: if value != 0 {
+                    TRIGGER_MAX
+                } else {
+                    self.rest(target)
+                },
+            });
+        }
+
+        outputs
+    }
+}
+
+impl Dispatcher {
+    pub fn new(controllers: &[ControllerConfig], pulse: Duration) -> Self {
+        Self::with_clock(controllers, pulse, SystemClock)
+    }
+}
+
+impl<C: Clock> Dispatcher<C> {
+    pub fn with_clock(controllers: &[ControllerConfig], pulse: Duration, clock: C) -> Self {
+        Dispatcher {
+            clock,
+            controllers: controllers
+                .iter()
+                .map(|config| DispatchSlot {
+                    mapping: config.mapping.clone(),
+                    sources: config.sources.clone(),
+                    dpad_mode: config.dpad_mode,
+                    axis_scale: config.axis_scale,
+                    normalize_diagonals: config.normalize_diagonals,
+                    // Rests set for axes the kind doesn't declare still
+                    // apply, as fixtures don't record the kind
+                    axis_rests: config
+                        .capabilities()
+                        .axes
+                        .iter()
+                        .map(|capability| (capability.axis, capability.rest))
+                        .chain(config.axis_rests.clone())
+                        .collect(),
+                    axis_ranges: config
+                        .capabilities()
+                        .axes
+                        .iter()
+                        .map(|capability| (capability.axis, (capability.min, capability.max)))
+                        .collect(),
+                    mouse_stick: config.mouse_stick,
+                    chords: config.chords.clone(),
+                    chord_window: Duration::from_millis(config.chord_window_ms),
+                    double_taps: config.double_taps.clone(),
+                    shortcuts: config.shortcuts.clone(),
+                    state: ControllerState::default(),
+                })
+                .collect(),
+            pulse,
+            pending: Vec::new(),
+            turbo: Vec::new(),
+            commands: Vec::new(),
+            mouse_recenters: Vec::new(),
+            ramps: Vec::new(),
+            held_modifiers: HashSet::new(),
+        }
+    }
+
+    /// The value `target` takes on `controller` when nothing holds it.
+    pub fn rest(&self, controller: usize, target: Target) -> i32 {
+        self.controllers[controller].rest(target)
+    }
+
+    pub fn axis_scale(&self, controller: usize) -> f32 {
+        self.controllers[controller].axis_scale
+    }
+
+    /// Changes a controller's axis scale from the next event on. Axes
+    /// already deflected keep their value until they move again.
+    pub fn set_axis_scale(&mut self, controller: usize, scale: f32) {
+        self.controllers[controller].axis_scale = scale;
+    }
+
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Commands whose `exec` binding was pressed, in order, with the
+    /// controller that has the binding. The dispatcher only collects them,
+    /// so replaying a fixture never runs anything.
+    pub fn take_commands(&mut self) -> Vec<(usize, String)> {
+        std::mem::take(&mut self.commands)
+    }
+
+    /// When the next tapped button is due for release, turbo button due to
+    /// flip, held axis due to move, mouse-driven stick due back at center or
+    /// chord key due to stop waiting, if any.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .map(|p| p.due)
+            .chain(self.turbo.iter().map(|t| t.due))
+            .chain(self.ramps.iter().filter_map(|r| r.due))
+            .chain(self.mouse_recenters.iter().map(|r| r.due))
+            .chain(self.chord_deadlines())
+            .min()
+    }
+
+    /// When the next tapped button is due for release or chord key due to
+    /// stop waiting, if any. Unlike [`Dispatcher::next_deadline`] this runs
+    /// out, as turbo buttons flip for as long as their key is held.
+    pub fn next_release_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .map(|p| p.due)
+            .chain(self.chord_deadlines())
+            .min()
+    }
+
+    fn chord_deadlines(&self) -> impl Iterator<Item = Instant> + '_ {
+        self.controllers
+            .iter()
+            .filter_map(|slot| slot.state.chord_due)
+    }
+
+    /// Releases the tapped buttons whose pulse has elapsed and flips the
+    /// turbo buttons that are due, oldest first, then moves the ramping axes
+    /// and centers the mouse-driven sticks whose mouse has gone still.
+    pub fn fire_due(&mut self) -> Vec<ControllerOutput> {
+        let now = self.clock.now();
+        let (due_releases, pending): (Vec<_>, Vec<_>) =
+            self.pending.drain(..).partition(|p| p.due <= now);
+        self.pending = pending;
+
+        let mut due: Vec<(Instant, usize, KeyCode, i32)> = due_releases
+            .into_iter()
+            .map(|p| (p.due, p.controller, p.button, 0))
+            .collect();
+
+        // A turbo button may be due several times since the last call. One
+        // another key holds as well stays down rather than flipping up.
+        for timer in &mut self.turbo {
+            while timer.due <= now {
+                let shared = self.controllers[timer.controller]
+                    .state
+                    .held_keys
+                    .get(&timer.button)
+                    .is_some_and(|held| *held > 1);
+                if !(timer.pressed && shared) {
+                    timer.pressed = !timer.pressed;
+                    due.push((
+                        timer.due,
+                        timer.controller,
+                        timer.button,
+                        timer.pressed as i32,
+                    ));
                 }
-                thread::sleep(Duration::from_millis(10));
+                timer.due += timer.half_period;
             }
-        });
+        }
+        due.sort_by_key(|(due, ..)| *due);
+
+        let mut outputs: Vec<ControllerOutput> = due
+            .into_iter()
+            .flat_map(|(_, controller, button, value)| {
+                self.controllers[controller].button_output(controller, button, value)
+            })
+            .collect();
+
+        for ramp in &mut self.ramps {
+            if ramp.due.is_none_or(|due| due > now) {
+                continue;
+            }
+
+            let value = self.controllers[ramp.controller].ramp_position(ramp, now);
+            if value != ramp.sent {
+                outputs.push(ControllerOutput {
+                    controller: ramp.controller,
+                    target: Target::Axis(ramp.axis),
+                    value,
+                });
+                ramp.sent = value;
+            }
+            ramp.due = (now < ramp.start + ramp.ramp).then_some(now + RAMP_STEP);
+        }
+
+        let (recentered, waiting): (Vec<_>, Vec<_>) =
+            self.mouse_recenters.drain(..).partition(|r| r.due <= now);
+        self.mouse_recenters = waiting;
+        for recenter in recentered {
+            for axis in [AbsoluteAxisCode::ABS_RX, AbsoluteAxisCode::ABS_RY] {
+                outputs.push(ControllerOutput {
+                    controller: recenter.controller,
+                    target: Target::Axis(axis),
+                    value: self.rest(recenter.controller, Target::Axis(axis)),
+                });
+            }
+        }
+
+        for controller in 0..self.controllers.len() {
+            if self.controllers[controller]
+                .state
+                .chord_due
+                .is_some_and(|due| due <= now)
+            {
+                self.flush_chord(controller, now, &mut outputs);
+            }
+        }
+
+        self.normalize_diagonals(outputs)
+    }
+
+    /// Whether motion from `source` drives a controller's stick. Such motion
+    /// is taken by the controllers and not forwarded.
+    pub fn takes_motion(&self, source: &Path) -> bool {
+        self.controllers
+            .iter()
+            .any(|slot| slot.mouse_stick.is_some() && slot.accepts(Some(source)))
+    }
+
+    /// Every key bound by a controller reading `source`, routing by device
+    /// and code. Only these keys of the source go to the controllers; the
+    /// rest are forwarded, even when a controller reading another device
+    /// binds them.
+    pub fn keys_bound_for(&self, source: &Path) -> HashSet<KeyCode> {
+        self.controllers
+            .iter()
+            .filter(|slot| slot.accepts(Some(source)))
+            .flat_map(|slot| {
+                slot.mapping
+                    .keys_for_device(source)
+                    .chain(
+                        slot.chords
+                            .iter()
+                            .flat_map(|chord| chord.keys.iter().copied()),
+                    )
+                    .chain(slot.double_taps.iter().map(|double_tap| double_tap.key))
+            })
+            .collect()
+    }
+
+    /// Deflects the right stick of each mouse-driven controller by one frame
+    /// of motion, and schedules it back to rest for when the mouse stops.
+    pub fn handle_motion(
+        &mut self,
+        source: Option<&Path>,
+        dx: i32,
+        dy: i32,
+    ) -> Vec<ControllerOutput> {
+        let now = self.clock.now();
+        let mut outputs = Vec::new();
+
+        for (controller, slot) in self.controllers.iter().enumerate() {
+            let Some(mouse) = slot.mouse_stick else {
+                continue;
+            };
+            if !slot.accepts(source) {
+                continue;
+            }
+
+            // Deflected from the stick's rest, and scaled like key-driven axes
+            let (x, y) = mouse_to_axis(dx, dy, mouse.sensitivity);
+            for (axis, deflection) in [(AbsoluteAxisCode::ABS_RX, x), (AbsoluteAxisCode::ABS_RY, y)]
+            {
+                let rest = slot.rest(Target::Axis(axis));
+                outputs.push(ControllerOutput {
+                    controller,
+                    target: Target::Axis(axis),
+                    value: slot.scale_axis(axis, rest + deflection, slot.axis_scale),
+                });
+            }
+
+            // Further motion pushes the return back
+            self.mouse_recenters.retain(|r| r.controller != controller);
+            self.mouse_recenters.push(MouseRecenter {
+                due: now + Duration::from_millis(mouse.decay_ms),
+                controller,
+            });
+        }
+
+        self.normalize_diagonals(outputs)
+    }
+
+    /// Passes a source's own axis through to each controller declaring it,
+    /// so a gamepad remapped as a source keeps its sticks and triggers. The
+    /// value is rescaled from `range`, the source's, to the controller's.
+    pub fn handle_axis(
+        &mut self,
+        source: Option<&Path>,
+        axis: AbsoluteAxisCode,
+        value: i32,
+        range: (i32, i32),
+    ) -> Vec<ControllerOutput> {
+        self.controllers
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.accepts(source))
+            .filter_map(|(controller, slot)| {
+                let &target_range = slot.axis_ranges.get(&axis)?;
+                Some(ControllerOutput {
+                    controller,
+                    target: Target::Axis(axis),
+                    value: rescale_axis(value, range, target_range),
+                })
+            })
+            .collect()
+    }
+
+    /// Resolves one key event, in controller order. `source` is the device it
+    /// came from; `None` (as in fixture replay) reaches every controller.
+    /// Releases of tapped buttons are scheduled on the clock and come out of
+    /// [`Dispatcher::fire_due`].
+    ///
+    /// Where a key press has more than one rule on a controller, the rules
+    /// are sorted by priority and the highest takes it: a shortcut with its
+    /// modifiers held, a chord, the held layer's binding or the key's own
+    /// (for `source` only, or the usual one). A chord only takes its keys if
+    /// it ranks no lower than each key's binding. Ties go in that order,
+    /// which is also the order of the default priorities. Repeats and
+    /// releases go where the press went. Double-taps watch alongside
+    /// whichever wins.
+    pub fn handle_key(
+        &mut self,
+        source: Option<&Path>,
+        key: KeyCode,
+        value: i32,
+    ) -> Vec<ControllerOutput> {
+        let now = self.clock.now();
+        let mut outputs = Vec::new();
+        self.track_modifier(key, value);
+        let held = self.modifiers();
+
+        for controller in 0..self.controllers.len() {
+            if !self.controllers[controller].accepts(source) {
+                continue;
+            }
+
+            let rules = if KeyState::from_value(value) == Some(KeyState::Press) {
+                let rules = self.controllers[controller].press_rules(source, key, &held);
+                // Anything but a chord key going down ends the wait
+                if rules.first() != Some(&Rule::Chord) {
+                    self.flush_chord(controller, now, &mut outputs);
+                }
+                rules
+            } else {
+                vec![Rule::Shortcut, Rule::Chord, Rule::Binding]
+            };
+
+            let mut shortcut = false;
+            for rule in rules {
+                let taken = match rule {
+                    Rule::Shortcut => {
+                        shortcut = self.shortcut_key(controller, key, value, now, &mut outputs);
+                        shortcut
+                    }
+                    Rule::Chord => {
+                        self.chord_key(controller, source, key, value, now, &mut outputs)
+                    }
+                    Rule::Binding => {
+                        self.dispatch(controller, source, key, value, now, &mut outputs);
+                        true
+                    }
+                };
+                if taken {
+                    break;
+                }
+            }
+            if !shortcut {
+                self.double_tap(controller, key, value, now, &mut outputs);
+            }
+        }
+
+        self.normalize_diagonals(outputs)
+    }
+
+    /// Notes a modifier key going down or up. [`Dispatcher::handle_key`] does
+    /// this itself; the session passes unbound keys here instead, so
+    /// shortcuts see the modifiers that go on to the system.
+    pub fn track_modifier(&mut self, key: KeyCode, value: i32) {
+        if Modifier::of(key).is_none() {
+            return;
+        }
+
+        if value == 0 {
+            self.held_modifiers.remove(&key);
+        } else {
+            self.held_modifiers.insert(key);
+        }
+    }
+
+    /// The modifiers held now, sorted and without duplicates.
+    fn modifiers(&self) -> Vec<Modifier> {
+        let held: BTreeSet<Modifier> = self
+            .held_modifiers
+            .iter()
+            .filter_map(|key| Modifier::of(*key))
+            .collect();
+
+        held.into_iter().collect()
+    }
+
+    /// Whether `key` going to `value` from `source` belongs to a shortcut of
+    /// some controller: a press with exactly its modifiers held, or a repeat
+    /// or release of a shortcut key that is down. The session hands these to
+    /// [`Dispatcher::handle_key`] even when the key is unbound, and doesn't
+    /// forward them.
+    pub fn takes_shortcut(&self, source: Option<&Path>, key: KeyCode, value: i32) -> bool {
+        let held = self.modifiers();
+        self.controllers
+            .iter()
+            .filter(|slot| slot.accepts(source))
+            .any(|slot| match KeyState::from_value(value) {
+                Some(KeyState::Press) => slot
+                    .shortcuts
+                    .iter()
+                    .any(|shortcut| shortcut.key == key && shortcut.matches(&held)),
+                Some(KeyState::Release | KeyState::Repeat) => {
+                    slot.state.active_shortcuts.contains_key(&key)
+                }
+                None => false,
+            })
+    }
+
+    /// Handles `key` as the key of a shortcut on `controller`. Returns
+    /// whether a shortcut took the event, or whether it still goes to the
+    /// key's own binding. Releasing the key releases the shortcut's button,
+    /// even if its modifiers were let go first.
+    fn shortcut_key(
+        &mut self,
+        controller: usize,
+        key: KeyCode,
+        value: i32,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) -> bool {
+        let held = self.modifiers();
+        let slot = &mut self.controllers[controller];
+        let button = match KeyState::from_value(value) {
+            Some(KeyState::Press) => {
+                let Some(shortcut) = slot
+                    .shortcuts
+                    .iter()
+                    .find(|shortcut| shortcut.key == key && shortcut.matches(&held))
+                else {
+                    return false;
+                };
+                slot.state.active_shortcuts.insert(key, shortcut.button);
+                shortcut.button
+            }
+            Some(KeyState::Release) => {
+                let Some(button) = slot.state.active_shortcuts.remove(&key) else {
+                    return false;
+                };
+                button
+            }
+            Some(KeyState::Repeat) => return slot.state.active_shortcuts.contains_key(&key),
+            None => return false,
+        };
+
+        self.apply(
+            controller,
+            key,
+            &Binding::Button(button),
+            value,
+            now,
+            outputs,
+        );
+        true
+    }
+
+    /// Rounds the stick diagonals of the controllers that ask for it. Each
+    /// stick output is recorded as the bindings meant it, and replaced by
+    /// both of the stick's axes pulled in to its circle, so letting go of one
+    /// of two keys restores the other axis in full.
+    fn normalize_diagonals(&mut self, outputs: Vec<ControllerOutput>) -> Vec<ControllerOutput> {
+        let mut normalized = Vec::with_capacity(outputs.len());
+        for output in outputs {
+            let slot = &mut self.controllers[output.controller];
+            let axis = match output.target {
+                Target::Axis(axis) if slot.normalize_diagonals => axis,
+                _ => {
+                    normalized.push(output);
+                    continue;
+                }
+            };
+            let Some((x_axis, y_axis)) = STICK_AXIS_PAIRS
+                .into_iter()
+                .find(|(x, y)| *x == axis || *y == axis)
+            else {
+                normalized.push(output);
+                continue;
+            };
+
+            slot.state.stick_deflections.insert(axis, output.value);
+            let rest_x = slot.rest(Target::Axis(x_axis));
+            let rest_y = slot.rest(Target::Axis(y_axis));
+            let deflection = |axis, rest| {
+                slot.state
+                    .stick_deflections
+                    .get(&axis)
+                    .map_or(0, |v| v - rest)
+            };
+            let (x, y) = normalize_axis_pair(
+                deflection(x_axis, rest_x),
+                deflection(y_axis, rest_y),
+                STICK_MAX,
+            );
+
+            for (axis, value) in [(x_axis, rest_x + x), (y_axis, rest_y + y)] {
+                normalized.push(ControllerOutput {
+                    controller: output.controller,
+                    target: Target::Axis(axis),
+                    value,
+                });
+            }
+        }
+
+        normalized
+    }
+
+    /// Taps the button of `key`'s double-tap on `controller` if this press
+    /// comes within the window of the one before. The press completing a
+    /// double-tap doesn't start another, so a third quick press starts over.
+    fn double_tap(
+        &mut self,
+        controller: usize,
+        key: KeyCode,
+        value: i32,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) {
+        if KeyState::from_value(value) != Some(KeyState::Press) {
+            return;
+        }
+
+        let slot = &mut self.controllers[controller];
+        let Some(double_tap) = slot.double_taps.iter().find(|d| d.key == key).cloned() else {
+            return;
+        };
+
+        let previous = slot.state.last_presses.remove(&key);
+        if !previous.is_some_and(|previous| double_tap.completes(now.duration_since(previous))) {
+            slot.state.last_presses.insert(key, now);
+            return;
+        }
+
+        let button = double_tap.button;
+        outputs.extend(slot.button_output(controller, button, 1));
+        self.pending
+            .retain(|p| p.controller != controller || p.button != button);
+        self.pending.push(PendingRelease {
+            due: now + Duration::from_millis(double_tap.pulse_ms),
+            controller,
+            button,
+        });
+    }
+
+    /// Runs `key` from `source` going to the raw `value` through the binding
+    /// it has on `controller`, past any chords.
+    fn dispatch(
+        &mut self,
+        controller: usize,
+        source: Option<&Path>,
+        key: KeyCode,
+        value: i32,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) {
+        let Some(binding) = self.controllers[controller].resolve(source, key, value) else {
+            return;
+        };
+        let Some(value) = normalize_value(&binding, value) else {
+            return;
+        };
+
+        self.apply(controller, key, &binding, value, now, outputs);
+    }
+
+    /// Handles `key` as part of a chord on `controller`. Returns whether the
+    /// chord took the event, or whether it still goes to the key's own
+    /// binding.
+    ///
+    /// A chord key going down waits up to the chord window for the rest of
+    /// its chord. If they all arrive the chord's button is pressed and the
+    /// keys' own bindings never see them; otherwise the waiting keys go
+    /// through late, in order. Releasing any key of a pressed chord
+    /// releases its button, and the other keys' releases are dropped.
+    fn chord_key(
+        &mut self,
+        controller: usize,
+        source: Option<&Path>,
+        key: KeyCode,
+        value: i32,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) -> bool {
+        let slot = &mut self.controllers[controller];
+        if slot.chords.is_empty() {
+            return false;
+        }
+
+        let chords: Vec<Chord> = slot
+            .chords
+            .iter()
+            .filter(|chord| slot.chord_takes_keys(chord, source))
+            .cloned()
+            .collect();
+        let state = &mut slot.state;
+        match KeyState::from_value(value) {
+            Some(KeyState::Press) => {
+                if !chords.iter().any(|chord| chord.keys.contains(&key)) {
+                    // Anything else going down ends the wait
+                    self.flush_chord(controller, now, outputs);
+                    return false;
+                }
+
+                state.chord_buffer.push(key);
+                state.chord_sources.push(source.map(Path::to_path_buf));
+                if let Some(chord) = chords.iter().find(|c| c.matches(&state.chord_buffer)) {
+                    state.chord_buffer.clear();
+                    state.chord_sources.clear();
+                    state.chord_due = None;
+                    state.active_chords.push(chord.clone());
+
+                    let button = Binding::Button(chord.button);
+                    self.apply(controller, key, &button, 1, now, outputs);
+                    return true;
+                }
+
+                if !chords
+                    .iter()
+                    .any(|chord| chord.could_match(&state.chord_buffer))
+                {
+                    // The keys so far can't make a chord with this one, but it
+                    // may start a chord of its own
+                    state.chord_buffer.pop();
+                    state.chord_sources.pop();
+                    self.flush_chord(controller, now, outputs);
+                    let state = &mut self.controllers[controller].state;
+                    state.chord_buffer.push(key);
+                    state.chord_sources.push(source.map(Path::to_path_buf));
+                }
+
+                let slot = &mut self.controllers[controller];
+                slot.state.chord_due.get_or_insert(now + slot.chord_window);
+                true
+            }
+            Some(KeyState::Release) => {
+                if let Some(index) = state
+                    .active_chords
+                    .iter()
+                    .position(|chord| chord.keys.contains(&key))
+                {
+                    let chord = state.active_chords.swap_remove(index);
+                    state
+                        .dropped_releases
+                        .extend(chord.keys.iter().filter(|other| **other != key));
+
+                    let button = Binding::Button(chord.button);
+                    self.apply(controller, key, &button, 0, now, outputs);
+                    return true;
+                }
+
+                // A waiting key let go early was a plain press after all
+                if state.chord_buffer.contains(&key) {
+                    self.flush_chord(controller, now, outputs);
+                }
+                false
+            }
+            Some(KeyState::Repeat) => {
+                state.chord_buffer.contains(&key)
+                    || state
+                        .active_chords
+                        .iter()
+                        .any(|chord| chord.keys.contains(&key))
+            }
+            None => false,
+        }
+    }
+
+    /// Lets the chord keys waiting on `controller` go through to their own
+    /// bindings.
+    fn flush_chord(
+        &mut self,
+        controller: usize,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) {
+        let state = &mut self.controllers[controller].state;
+        state.chord_due = None;
+
+        let sources = std::mem::take(&mut state.chord_sources);
+        for (key, source) in std::mem::take(&mut state.chord_buffer)
+            .into_iter()
+            .zip(sources)
+        {
+            self.dispatch(controller, source.as_deref(), key, 1, now, outputs);
+        }
+    }
+
+    /// Acts on `key` going to `value` on `controller`, where the key does
+    /// `binding` and `value` has already been through [`normalize_value`].
+    fn apply(
+        &mut self,
+        controller: usize,
+        key: KeyCode,
+        binding: &Binding,
+        value: i32,
+        now: Instant,
+        outputs: &mut Vec<ControllerOutput>,
+    ) {
+        let slot = &mut self.controllers[controller];
+
+        match *binding {
+            Binding::Button(button) => {
+                if !slot.state.count_held(button, value) {
+                    if value != 0 && press_turbo_gap(&mut self.turbo, controller, button) {
+                        outputs.extend(slot.button_output(controller, button, 1));
+                    }
+                    return;
+                }
+                if let Some(value) = slot.state.sticky_filter(button, value) {
+                    outputs.extend(slot.button_output(controller, button, value));
+                }
+            }
+            Binding::Combo(ref buttons) => {
+                // Each button goes through the same counting and sticky
+                // mode as a plain one. Sticky mode latches them all.
+                let armed = slot.state.sticky_armed;
+                for button in buttons.clone() {
+                    if !slot.state.count_held(button, value) {
+                        if value != 0 && press_turbo_gap(&mut self.turbo, controller, button) {
+                            outputs.extend(slot.button_output(controller, button, 1));
+                        }
+                        continue;
+                    }
+                    slot.state.sticky_armed = armed;
+                    if let Some(value) = slot.state.sticky_filter(button, value) {
+                        outputs.extend(slot.button_output(controller, button, value));
+                    }
+                }
+            }
+            Binding::Sticky => slot.state.sticky_armed = !slot.state.sticky_armed,
+            Binding::Tap(button) => {
+                outputs.extend(slot.button_output(controller, button, 1));
+
+                // Tapping again while still down extends the pulse
+                self.pending
+                    .retain(|p| p.controller != controller || p.button != button);
+                self.pending.push(PendingRelease {
+                    due: now + self.pulse,
+                    controller,
+                    button,
+                });
+            }
+            Binding::Turbo { button, hz } => {
+                // Counted and run through sticky mode like a plain button,
+                // so a latch holds it steady and a button another key holds
+                // isn't let go by the flipping
+                let held = self
+                    .turbo
+                    .iter()
+                    .position(|t| t.controller == controller && t.key == key);
+
+                match (value, held) {
+                    (0, Some(index)) => {
+                        let timer = self.turbo.swap_remove(index);
+                        if slot.state.count_held(button, 0) {
+                            if timer.pressed && slot.state.sticky_filter(button, 0).is_some() {
+                                outputs.extend(slot.button_output(controller, button, 0));
+                            }
+                        } else if !timer.pressed {
+                            // Another key still holds it
+                            outputs.extend(slot.button_output(controller, button, 1));
+                        }
+                    }
+                    (0, None) => {
+                        // Latched or let go by sticky mode on its press
+                        if slot.state.count_held(button, 0)
+                            && let Some(value) = slot.state.sticky_filter(button, 0)
+                        {
+                            outputs.extend(slot.button_output(controller, button, value));
+                        }
+                    }
+                    (1, None) => {
+                        let flips = if slot.state.count_held(button, 1) {
+                            let value = slot.state.sticky_filter(button, 1);
+                            if let Some(value) = value {
+                                outputs.extend(slot.button_output(controller, button, value));
+                            }
+                            value == Some(1) && !slot.state.sticky_held.contains(&button)
+                        } else {
+                            // Already down for another key
+                            true
+                        };
+
+                        if flips {
+                            let half_period =
+                                Duration::from_secs(1) / (2 * hz.clamp(1, MAX_TURBO_HZ));
+                            self.turbo.push(TurboTimer {
+                                controller,
+                                key,
+                                button,
+                                pressed: true,
+                                due: now + half_period,
+                                half_period,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Binding::AxisToggle {
+                axis,
+                value: deflection,
+            } => {
+                let latched = slot.state.axis_latches.entry(axis).or_insert(false);
+                *latched = !*latched;
+
+                let value = if *latched {
+                    slot.scale_axis(axis, deflection, slot.axis_scale)
+                } else {
+                    slot.rest(Target::Axis(axis))
+                };
+                outputs.push(ControllerOutput {
+                    controller,
+                    target: Target::Axis(axis),
+                    value,
+                });
+            }
+            Binding::AxisCycle { axis, ref values } => {
+                // The first press goes to the first value
+                let index = slot
+                    .state
+                    .axis_cycles
+                    .entry(key)
+                    .and_modify(|index| *index = (*index + 1) % values.len())
+                    .or_insert(0);
+                let position = values[*index];
+
+                outputs.push(ControllerOutput {
+                    controller,
+                    target: Target::Axis(axis),
+                    value: slot.scale_axis(axis, position, slot.axis_scale),
+                });
+            }
+            Binding::Axis {
+                axis,
+                value: deflection,
+                ramp_ms,
+                deadzone_pct,
+            } => {
+                self.ramps
+                    .retain(|r| r.controller != controller || r.key != key);
+
+                if value != 0 {
+                    let mut ramp = AxisRamp {
+                        controller,
+                        key,
+                        axis,
+                        value: deflection,
+                        start: now,
+                        ramp: Duration::from_millis(ramp_ms),
+                        deadzone_pct,
+                        sent: 0,
+                        due: None,
+                    };
+                    ramp.sent = slot.ramp_position(&ramp, now);
+                    ramp.due = (ramp_ms > 0).then_some(now + RAMP_STEP);
+                    outputs.push(ControllerOutput {
+                        controller,
+                        target: Target::Axis(axis),
+                        value: ramp.sent,
+                    });
+                    self.ramps.push(ramp);
+                    return;
+                }
+
+                // Another key still holding the axis takes it back over
+                let value = match self
+                    .ramps
+                    .iter_mut()
+                    .rfind(|r| r.controller == controller && r.axis == axis)
+                {
+                    Some(other) => {
+                        other.sent = slot.ramp_position(other, now);
+                        other.sent
+                    }
+                    None => slot.rest(Target::Axis(axis)),
+                };
+                outputs.push(ControllerOutput {
+                    controller,
+                    target: Target::Axis(axis),
+                    value,
+                });
+            }
+            Binding::HalfPress => {
+                slot.state.half_pressed = value != 0;
+
+                // Held axes move to their new limit straight away
+                for ramp in self.ramps.iter_mut().filter(|r| r.controller == controller) {
+                    ramp.sent = slot.ramp_position(ramp, now);
+                    outputs.push(ControllerOutput {
+                        controller,
+                        target: Target::Axis(ramp.axis),
+                        value: ramp.sent,
+                    });
+                }
+            }
+            Binding::Layer(ref name) => {
+                if value != 0 {
+                    slot.state.active_layer = Some(name.clone());
+                    return;
+                }
+                if slot.state.active_layer.as_ref() != Some(name) {
+                    return;
+                }
+                slot.state.active_layer = None;
+
+                // Keys still down let go of what they pressed under the
+                // layer, and their own release is dropped later
+                let mut held: Vec<_> = slot.state.layer_held.drain().collect();
+                held.sort_by_key(|(key, _)| key.0);
+                for (key, binding) in held {
+                    self.controllers[controller]
+                        .state
+                        .dropped_releases
+                        .insert(key);
+                    if let Some(value) = normalize_value(&binding, 0) {
+                        self.apply(controller, key, &binding, value, now, outputs);
+                    }
+                }
+            }
+            Binding::Exec(ref command) => self.commands.push((controller, command.clone())),
+        }
+    }
+}
+
+/// Presses `button` back down for a key that holds it while a turbo on
+/// `controller` flips it, if the turbo has it up. Returns whether it did.
+fn press_turbo_gap(turbo: &mut [TurboTimer], controller: usize, button: KeyCode) -> bool {
+    match turbo
+        .iter_mut()
+        .find(|t| t.controller == controller && t.button == button && !t.pressed)
+    {
+        Some(timer) => {
+            timer.pressed = true;
+            true
+        }
+        None => false,
+    }
+}
+
+/// How far a held axis has eased from `from` toward `value` after `elapsed`
+/// of a `ramp` long ease-in. It moves linearly and gets to `value` at the
+/// end of the ramp; a zero ramp goes there at once.
+fn ramp_value(from: i32, value: i32, elapsed: Duration, ramp: Duration) -> i32 {
+    if elapsed >= ramp {
+        return value;
+    }
+
+    let progress = elapsed.as_secs_f64() / ramp.as_secs_f64();
+    from + ((value - from) as f64 * progress).round() as i32
+}
+
+/// A recorded session together with the mappings that were active and the
+/// controller outputs they produced. See [`export_fixture`] for the format.
+#[derive(Debug, Default, PartialEq)]
+pub struct Fixture {
+    pub controllers: Vec<ControllerConfig>,
+    pub pulse_ms: u64,
+    pub input: Vec<RecordedEvent>,
+    pub expected: Vec<ControllerOutput>,
+}
+
+impl Fixture {
+    pub fn new(controllers: &[VirtualController], pulse_ms: u64, input: &[RecordedEvent]) -> Self {
+        let controllers: Vec<_> = controllers.iter().map(|c| c.config()).collect();
+
+        let mut fixture = Fixture {
+            controllers,
+            pulse_ms,
+            input: input.to_vec(),
+            expected: Vec::new(),
+        };
+        fixture.expected = fixture.replay();
+
+        fixture
+    }
+
+    /// Feeds the recorded input through a fresh [`Dispatcher`] and returns
+    /// the outputs the current code produces. A regression test compares this
+    /// against `expected`.
+    pub fn replay(&self) -> Vec<ControllerOutput> {
+        // Replay has no devices; every event reaches every controller
+        let controllers: Vec<_> = self
+            .controllers
+            .iter()
+            .map(|c| ControllerConfig {
+                sources: Vec::new(),
+                ..c.clone()
+            })
+            .collect();
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut dispatcher =
+            Dispatcher::with_clock(&controllers, Duration::from_millis(self.pulse_ms), clock);
+
+        // Recorded offsets drive the clock, so pending releases land between
+        // the same events they did in the session
+        let mut outputs = Vec::new();
+
+        for ev in &self.input {
+            let now = start + Duration::from_millis(ev.offset_ms);
+            dispatcher.clock_mut().advance_to(now);
+            outputs.extend(dispatcher.fire_due());
+            outputs.extend(dispatcher.handle_key(None, KeyCode::new(ev.code), ev.value));
+        }
+
+        while let Some(due) = dispatcher.next_release_deadline() {
+            dispatcher.clock_mut().advance_to(due);
+            outputs.extend(dispatcher.fire_due());
+        }
+
+        outputs
+    }
+}
+
+/// Writes a session recording and the active mappings as a fixture file.
+///
+/// The format is line based; `#` starts a comment and blank lines are ignored.
+/// In an `exec` binding or a controller name, only `  #` does.
+/// Codes are raw evdev codes, with the symbolic names added as comments:
+///
+/// ```text
+/// pulse <ms>                              tap duration, 16 if absent
+/// controller <name>                       starts a controller section
+/// dpad <buttons|hat>                      D-pad mode of the current controller
+/// axis-scale <factor>                     its axis scale, 1 if absent
+/// normalize-diagonals                     rounds its stick diagonals
+/// axis-rest <axis code> <value>           where an axis returns to on release
+/// map <key code> <binding>                a binding of the current controller
+/// priority <key code> <n>                 the priority of a key's binding, 0 if absent
+/// layer-map <layer> <key code> <binding>  a binding in one of its layers
+/// layer-priority <layer> <n>              the priority of a layer, 10 if absent
+/// chord <button code> <key code>...       a chord of the current controller
+/// chord-priority <n>                      the priority of the chord above, 20 if absent
+/// chord-window <ms>                       how long its chords wait, 20 if absent
+/// double-tap <key> <button> <ms> <ms>     a double-tap, its window and pulse
+/// shortcut <button> <key> <modifier>...   a shortcut, e.g. `... 2 Shift` for Shift+1
+/// shortcut-priority <n>                   the priority of the shortcut above, 30 if absent
+/// input <offset ms> <key code> <value>
+/// expect <controller index> <value> <target>
+/// ```
+///
+/// `<binding>` is `button <code>`, `combo <code>...`, `tap <code>`,
+/// `turbo <code> <hz>`, `sticky`, `axis <axis code> <value> <ramp ms>`,
+/// `half-press`, `layer <layer>`, `axis-toggle <axis code> <value>`,
+/// `axis-cycle <axis code> <value>...` or `exec <command>`, and `<target>` is `button <code>` or `axis <code>`.
+/// Bindings for one device only are left out, as the input has no device.
+pub fn export_fixture(
+    path: &Path,
+    controllers: &[VirtualController],
+    pulse_ms: u64,
+    recording: &[RecordedEvent],
+) -> Result<()> {
+    let fixture = Fixture::new(controllers, pulse_ms, recording);
+    let mut out = String::from("# inputmaster session fixture v1\n");
+    writeln!(out, "pulse {}", fixture.pulse_ms)?;
+
+    for controller in &fixture.controllers {
+        let mut bindings: Vec<_> = controller.mapping.iter().collect();
+        bindings.sort_by_key(|(key, _)| key.0);
+
+        writeln!(out, "\ncontroller {}", controller.name)?;
+        writeln!(out, "dpad {}", controller.dpad_mode)?;
+        writeln!(out, "axis-scale {}", controller.axis_scale)?;
+        if controller.normalize_diagonals {
+            writeln!(out, "normalize-diagonals")?;
+        }
+        let mut rests: Vec<_> = controller.axis_rests.iter().collect();
+        rests.sort_by_key(|(axis, _)| axis.0);
+        for (axis, rest) in rests {
+            writeln!(out, "axis-rest {} {}  # {}", axis.0, rest, axis_name(*axis))?;
+        }
+        for (key, binding) in bindings {
+            writeln!(out, "map {} {}  # {}", key.0, binding, key_name(*key))?;
+        }
+        let mut priorities: Vec<_> = controller.mapping.priorities().collect();
+        priorities.sort_by_key(|(key, _)| key.0);
+        for (key, priority) in priorities {
+            writeln!(out, "priority {} {}  # {}", key.0, priority, key_name(key))?;
+        }
+        if !controller.chords.is_empty() {
+            writeln!(out, "chord-window {}", controller.chord_window_ms)?;
+        }
+        for chord in &controller.chords {
+            write!(out, "chord {}", chord.button.0)?;
+            for key in &chord.keys {
+                write!(out, " {}", key.0)?;
+            }
+            writeln!(out, "  # {}", key_name(chord.button))?;
+            if chord.priority != DEFAULT_CHORD_PRIORITY {
+                writeln!(out, "chord-priority {}", chord.priority)?;
+            }
+        }
+        for double_tap in &controller.double_taps {
+            writeln!(
+                out,
+                "double-tap {} {} {} {}  # {}",
+                double_tap.key.0,
+                double_tap.button.0,
+                double_tap.window_ms,
+                double_tap.pulse_ms,
+                key_name(double_tap.key)
+            )?;
+        }
+        for shortcut in &controller.shortcuts {
+            write!(out, "shortcut {} {}", shortcut.button.0, shortcut.key.0)?;
+            for modifier in &shortcut.modifiers {
+                write!(out, " {}", modifier)?;
+            }
+            writeln!(out, "  # {}", shortcut)?;
+            if shortcut.priority != DEFAULT_SHORTCUT_PRIORITY {
+                writeln!(out, "shortcut-priority {}", shortcut.priority)?;
+            }
+        }
+        for (name, layer) in controller.mapping.layers() {
+            let priority = controller.mapping.layer_priority(name);
+            if priority != DEFAULT_LAYER_PRIORITY {
+                writeln!(out, "layer-priority {} {}", name, priority)?;
+            }
+            let mut bindings: Vec<_> = layer.iter().collect();
+            bindings.sort_by_key(|(key, _)| key.0);
+            for (key, binding) in bindings {
+                writeln!(
+                    out,
+                    "layer-map {} {} {}  # {}",
+                    name,
+                    key.0,
+                    binding,
+                    key_name(*key)
+                )?;
+            }
+        }
+    }
+
+    out.push('\n');
+    for ev in &fixture.input {
+        writeln!(
+            out,
+            "input {} {} {}  # {}",
+            ev.offset_ms,
+            ev.code,
+            ev.value,
+            key_name(KeyCode::new(ev.code))
+        )?;
+    }
+
+    out.push('\n');
+    for output in &fixture.expected {
+        writeln!(
+            out,
+            "expect {} {} {}",
+            output.controller, output.value, output.target
+        )?;
+    }
+
+    fs::write(path, out)?;
+
+    Ok(())
+}
+
+/// `line` of a fixture without its comment. A command or a controller name
+/// runs to the end of the line and may hold a `#` of its own, so there only
+/// `  #`, as in the note [`export_fixture`] adds, ends it.
+fn strip_fixture_comment(line: &str) -> &str {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return "";
+    }
+
+    let end = if line.starts_with("controller ") {
+        line.find("  #")
+    } else {
+        match line.find(" exec ") {
+            Some(exec) => line[exec..].rfind("  #").map(|i| exec + i),
+            None => line.find('#'),
+        }
+    };
+    line[..end.unwrap_or(line.len())].trim()
+}
+
+/// Parses a fixture written by [`export_fixture`].
+pub fn load_fixture(path: &Path) -> Result<Fixture> {
+    let contents = fs::read_to_string(path)?;
+    let mut fixture = Fixture {
+        pulse_ms: DEFAULT_PULSE_MS,
+        ..Fixture::default()
+    };
+
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = strip_fixture_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let bad_line = || anyhow::anyhow!("Invalid fixture line {}: {}", line_no + 1, line);
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        match kind {
+            "pulse" => fixture.pulse_ms = rest.trim().parse().map_err(|_| bad_line())?,
+            "controller" => fixture.controllers.push(ControllerConfig {
+                name: rest.trim().to_string(),
+                ..ControllerConfig::default()
+            }),
+            "dpad" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.dpad_mode = DpadMode::parse(rest.trim()).ok_or_else(bad_line)?;
+            }
+            "axis-scale" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.axis_scale = rest.trim().parse().map_err(|_| bad_line())?;
+            }
+            "normalize-diagonals" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.normalize_diagonals = true;
+            }
+            "axis-rest" => {
+                let (axis, rest) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let axis = axis.parse().map_err(|_| bad_line())?;
+                let rest = rest.trim().parse().map_err(|_| bad_line())?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.axis_rests.insert(AbsoluteAxisCode(axis), rest);
+            }
+            "map" => {
+                let (key, binding) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let key = key.parse().map_err(|_| bad_line())?;
+                let binding = Binding::parse(binding).ok_or_else(bad_line)?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.mapping.insert(KeyCode::new(key), binding);
+            }
+            "priority" => {
+                let (key, priority) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let key = key.parse().map_err(|_| bad_line())?;
+                let priority = priority.trim().parse().map_err(|_| bad_line())?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.mapping.set_priority(KeyCode::new(key), priority);
+            }
+            "chord-window" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.chord_window_ms = rest.trim().parse().map_err(|_| bad_line())?;
+            }
+            "chord" => {
+                let codes: Vec<u16> = rest
+                    .split_whitespace()
+                    .map(|code| code.parse().map_err(|_| bad_line()))
+                    .collect::<Result<_>>()?;
+                let [button, keys @ ..] = codes.as_slice() else {
+                    return Err(bad_line());
+                };
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.chords.push(Chord {
+                    keys: keys.iter().copied().map(KeyCode::new).collect(),
+                    button: KeyCode::new(*button),
+                    priority: DEFAULT_CHORD_PRIORITY,
+                });
+            }
+            "chord-priority" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                let chord = controller.chords.last_mut().ok_or_else(bad_line)?;
+                chord.priority = rest.trim().parse().map_err(|_| bad_line())?;
+            }
+            "double-tap" => {
+                let fields: Vec<u64> = rest
+                    .split_whitespace()
+                    .map(|field| field.parse().map_err(|_| bad_line()))
+                    .collect::<Result<_>>()?;
+                let [key, button, window_ms, pulse_ms] = fields.as_slice() else {
+                    return Err(bad_line());
+                };
+                let (Ok(key), Ok(button)) = (u16::try_from(*key), u16::try_from(*button)) else {
+                    return Err(bad_line());
+                };
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.double_taps.push(DoubleTap {
+                    key: KeyCode::new(key),
+                    button: KeyCode::new(button),
+                    window_ms: *window_ms,
+                    pulse_ms: *pulse_ms,
+                });
+            }
+            "shortcut" => {
+                let mut fields = rest.split_whitespace();
+                let (Some(Ok(button)), Some(Ok(key))) = (
+                    fields.next().map(str::parse::<u16>),
+                    fields.next().map(str::parse::<u16>),
+                ) else {
+                    return Err(bad_line());
+                };
+                let modifiers = parse_modifiers(fields)
+                    .filter(|modifiers| !modifiers.is_empty())
+                    .ok_or_else(bad_line)?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.shortcuts.push(Shortcut {
+                    modifiers,
+                    key: KeyCode::new(key),
+                    button: KeyCode::new(button),
+                    priority: DEFAULT_SHORTCUT_PRIORITY,
+                });
+            }
+            "shortcut-priority" => {
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                let shortcut = controller.shortcuts.last_mut().ok_or_else(bad_line)?;
+                shortcut.priority = rest.trim().parse().map_err(|_| bad_line())?;
+            }
+            "layer-map" => {
+                let (name, rest) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let (key, binding) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let key = key.parse().map_err(|_| bad_line())?;
+                let binding = Binding::parse(binding).ok_or_else(bad_line)?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller
+                    .mapping
+                    .insert_layer_binding(name, KeyCode::new(key), binding);
+            }
+            "layer-priority" => {
+                let (name, priority) = rest.trim().split_once(' ').ok_or_else(bad_line)?;
+                let priority = priority.trim().parse().map_err(|_| bad_line())?;
+
+                let controller = fixture.controllers.last_mut().ok_or_else(bad_line)?;
+                controller.mapping.set_layer_priority(name, priority);
+            }
+            "input" => {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                let [offset_ms, code, value] = fields.as_slice() else {
+                    return Err(bad_line());
+                };
+
+                fixture.input.push(RecordedEvent {
+                    offset_ms: offset_ms.parse().map_err(|_| bad_line())?,
+                    code: code.parse().map_err(|_| bad_line())?,
+                    value: value.parse().map_err(|_| bad_line())?,
+                });
+            }
+            "expect" => {
+                let mut fields = rest.trim().splitn(3, ' ');
+                let (Some(controller), Some(value), Some(target)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return Err(bad_line());
+                };
+
+                fixture.expected.push(ControllerOutput {
+                    controller: controller.parse().map_err(|_| bad_line())?,
+                    value: value.parse().map_err(|_| bad_line())?,
+                    target: Target::parse(target).ok_or_else(bad_line)?,
+                });
+            }
+            _ => return Err(bad_line()),
+        }
+    }
+
+    for controller in &fixture.controllers {
+        profile::validate_controller(controller)?;
+    }
+
+    Ok(fixture)
+}
+
+// Layout of the shareable JSON mappings. Keys, buttons and axes go by their
+// evdev names so the files can be read and edited by hand; source devices
+// are left out, as they only mean something on the machine they came from.
+
+#[derive(Serialize, Deserialize)]
+struct SharedMappings {
+    controllers: Vec<SharedController>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedController {
+    name: String,
+    #[serde(default)]
+    kind: ControllerKind,
+    #[serde(default)]
+    layout: ControllerLayout,
+    #[serde(default)]
+    dpad_mode: DpadMode,
+    #[serde(default = "profile::neutral_axis_scale")]
+    axis_scale: f32,
+    #[serde(default)]
+    normalize_diagonals: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    axis_rests: BTreeMap<String, i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mouse_stick: Option<MouseStick>,
+    /// Binding text by key name, e.g. `"KEY_W": "BTN_DPAD_UP"`.
+    #[serde(default)]
+    bindings: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    layers: BTreeMap<String, BTreeMap<String, String>>,
+    /// Priority by key name, for keys whose binding has a priority other
+    /// than the default.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    priorities: BTreeMap<String, i32>,
+    /// Priority by layer name, for layers with a priority other than the
+    /// default.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    layer_priorities: BTreeMap<String, i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chords: Vec<SharedChord>,
+    #[serde(default = "profile::default_chord_window")]
+    chord_window_ms: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    double_taps: Vec<SharedDoubleTap>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shortcuts: Vec<SharedShortcut>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedChord {
+    keys: Vec<String>,
+    button: String,
+    #[serde(default = "profile::default_chord_priority")]
+    priority: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedDoubleTap {
+    key: String,
+    button: String,
+    #[serde(default = "profile::default_double_tap_window")]
+    window_ms: u64,
+    #[serde(default = "profile::default_double_tap_pulse")]
+    pulse_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SharedShortcut {
+    modifiers: Vec<String>,
+    key: String,
+    button: String,
+    #[serde(default = "profile::default_shortcut_priority")]
+    priority: i32,
+}
+
+fn named_bindings<'a>(
+    bindings: impl Iterator<Item = (&'a KeyCode, &'a Binding)>,
+) -> BTreeMap<String, String> {
+    bindings
+        .map(|(key, binding)| (key_name(*key).to_string(), binding.to_named_string()))
+        .collect()
+}
+
+/// Writes the controllers' bindings and settings as pretty-printed JSON for
+/// sharing, e.g. in a forum post. [`import_json`] reads it back. Like the
+/// sources, bindings for one device only stay out, as device paths differ
+/// between machines.
+pub fn export_json(controllers: &[ControllerConfig]) -> String {
+    let shared = SharedMappings {
+        controllers: controllers
+            .iter()
+            .map(|config| SharedController {
+                name: config.name.clone(),
+                kind: config.kind,
+                layout: config.layout,
+                dpad_mode: config.dpad_mode,
+                axis_scale: config.axis_scale,
+                normalize_diagonals: config.normalize_diagonals,
+                axis_rests: config
+                    .axis_rests
+                    .iter()
+                    .map(|(axis, rest)| (axis_name(*axis).to_string(), *rest))
+                    .collect(),
+                mouse_stick: config.mouse_stick,
+                bindings: named_bindings(config.mapping.iter()),
+                layers: config
+                    .mapping
+                    .layers()
+                    .map(|(name, bindings)| (name.to_string(), named_bindings(bindings.iter())))
+                    .collect(),
+                priorities: config
+                    .mapping
+                    .priorities()
+                    .map(|(key, priority)| (key_name(key).to_string(), priority))
+                    .collect(),
+                layer_priorities: config
+                    .mapping
+                    .layers()
+                    .map(|(name, _)| (name.to_string(), config.mapping.layer_priority(name)))
+                    .filter(|(_, priority)| *priority != DEFAULT_LAYER_PRIORITY)
+                    .collect(),
+                chords: config
+                    .chords
+                    .iter()
+                    .map(|chord| SharedChord {
+                        keys: chord
+                            .keys
+                            .iter()
+                            .map(|key| key_name(*key).to_string())
+                            .collect(),
+                        button: key_name(chord.button).to_string(),
+                        priority: chord.priority,
+                    })
+                    .collect(),
+                chord_window_ms: config.chord_window_ms,
+                double_taps: config
+                    .double_taps
+                    .iter()
+                    .map(|double_tap| SharedDoubleTap {
+                        key: key_name(double_tap.key).to_string(),
+                        button: key_name(double_tap.button).to_string(),
+                        window_ms: double_tap.window_ms,
+                        pulse_ms: double_tap.pulse_ms,
+                    })
+                    .collect(),
+                shortcuts: config
+                    .shortcuts
+                    .iter()
+                    .map(|shortcut| SharedShortcut {
+                        modifiers: shortcut.modifiers.iter().map(Modifier::to_string).collect(),
+                        key: key_name(shortcut.key).to_string(),
+                        button: key_name(shortcut.button).to_string(),
+                        priority: shortcut.priority,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+
+    // Every map is keyed by strings, which is all serde_json can fail on
+    serde_json::to_string_pretty(&shared).expect("shared mappings serialize to JSON")
+}
+
+/// Reads mappings written by [`export_json`], or by hand in the same
+/// layout. An unknown key, button or axis name or an invalid binding fails
+/// with the entry it was found in. The controllers have no source devices.
+///
+/// Shared mappings come from strangers, so `exec` bindings are refused
+/// outright, in layers too: with commands allowed they would run as root.
+pub fn import_json(json: &str) -> Result<Vec<ControllerConfig>> {
+    let shared: SharedMappings = serde_json::from_str(json).context("Invalid mappings JSON")?;
+
+    shared
+        .controllers
+        .into_iter()
+        .map(|controller| {
+            let name = &controller.name;
+            let key = |key_name: &str| {
+                key_from_name(key_name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown key \"{}\" in {}", key_name, name))
+            };
+            let binding = |key_name: &str, text: &str| match Binding::parse_named(text) {
+                Some(Binding::Exec(_)) => Err(anyhow::anyhow!(
+                    "Refusing to import the command bound to {} in {}",
+                    key_name,
+                    name
+                )),
+                Some(binding) => Ok(binding),
+                None => Err(anyhow::anyhow!(
+                    "Invalid binding \"{}\" for {} in {}",
+                    text,
+                    key_name,
+                    name
+                )),
+            };
+
+            let mut mapping = Mapping::new();
+            for (key_name, text) in &controller.bindings {
+                mapping.insert(key(key_name)?, binding(key_name, text)?);
+            }
+            for (layer, bindings) in &controller.layers {
+                for (key_name, text) in bindings {
+                    mapping.insert_layer_binding(layer, key(key_name)?, binding(key_name, text)?);
+                }
+            }
+            for (key_name, priority) in &controller.priorities {
+                mapping.set_priority(key(key_name)?, *priority);
+            }
+            for (layer, priority) in &controller.layer_priorities {
+                mapping.set_layer_priority(layer, *priority);
+            }
+
+            let mut axis_rests = HashMap::new();
+            for (axis, rest) in &controller.axis_rests {
+                let axis = axis_from_name(axis)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown axis \"{}\" in {}", axis, name))?;
+                axis_rests.insert(axis, *rest);
+            }
+
+            let mut chords = Vec::new();
+            for chord in &controller.chords {
+                chords.push(Chord {
+                    keys: chord
+                        .keys
+                        .iter()
+                        .map(|key_name| key(key_name))
+                        .collect::<Result<_>>()?,
+                    button: key(&chord.button)?,
+                    priority: chord.priority,
+                });
+            }
+
+            let mut double_taps = Vec::new();
+            for double_tap in &controller.double_taps {
+                double_taps.push(DoubleTap {
+                    key: key(&double_tap.key)?,
+                    button: key(&double_tap.button)?,
+                    window_ms: double_tap.window_ms,
+                    pulse_ms: double_tap.pulse_ms,
+                });
+            }
+
+            let mut shortcuts = Vec::new();
+            for shortcut in &controller.shortcuts {
+                let modifiers = parse_modifiers(shortcut.modifiers.iter().map(String::as_str))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Invalid modifiers \"{}\" in {}",
+                            shortcut.modifiers.join("+"),
+                            name
+                        )
+                    })?;
+                shortcuts.push(Shortcut {
+                    modifiers,
+                    key: key(&shortcut.key)?,
+                    button: key(&shortcut.button)?,
+                    priority: shortcut.priority,
+                });
+            }
+
+            let config = ControllerConfig {
+                name: controller.name.clone(),
+                mapping,
+                sources: Vec::new(),
+                kind: controller.kind,
+                layout: controller.layout,
+                dpad_mode: controller.dpad_mode,
+                axis_scale: controller.axis_scale,
+                normalize_diagonals: controller.normalize_diagonals,
+                axis_rests,
+                mouse_stick: controller.mouse_stick,
+                chords,
+                chord_window_ms: controller.chord_window_ms,
+                double_taps,
+                shortcuts,
+            };
+            profile::validate_controller(&config)?;
+
+            Ok(config)
+        })
+        .collect()
+}
+
+/// Rereads the profile at `path` for a running session. Only bindings and
+/// source devices can change without rebuilding the controllers, so the
+/// profile must have the same controllers in the same order; their other
+/// settings are kept as they are.
+fn reload_bindings(
+    path: Option<&Path>,
+    running: &[ControllerConfig],
+) -> Result<Vec<ControllerConfig>> {
+    let path = path.ok_or_else(|| anyhow::anyhow!("no profile has been saved or loaded"))?;
+    let profile = profile::load_profile(path)?;
+
+    if profile.controllers.len() != running.len() {
+        return Err(anyhow::anyhow!(
+            "{} has {} controller(s), the session has {}",
+            path.display(),
+            profile.controllers.len(),
+            running.len()
+        ));
+    }
+
+    Ok(running
+        .iter()
+        .zip(profile.controllers)
+        .map(|(current, loaded)| ControllerConfig {
+            mapping: loaded.mapping,
+            sources: loaded.sources,
+            ..current.clone()
+        })
+        .collect())
+}
+
+/// Emits one frame, riding out transient failures. A write that fails with
+/// EAGAIN or EINTR is retried once and then dropped with a warning, so a
+/// momentary hiccup doesn't end a long session. Any other error, such as
+/// ENODEV after the device disappeared, is returned.
+fn emit_with_retry(controller: &mut impl Sink, frame: &[(Target, i32)]) -> Result<()> {
+    let result = match controller.emit_frame(frame) {
+        Err(e) if is_transient(&e) => {
+            thread::sleep(EMIT_RETRY_DELAY);
+            controller.emit_frame(frame)
+        }
+        result => return result,
+    };
+
+    match result {
+        Err(e) if is_transient(&e) => {
+            log::warn!(
+                "Dropped {} change(s) on {}: {}",
+                frame.len(),
+                controller.name(),
+                e
+            );
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Maps `value` from one axis range onto another, end to end.
+fn rescale_axis(value: i32, (from_min, from_max): (i32, i32), (to_min, to_max): (i32, i32)) -> i32 {
+    if from_max <= from_min {
+        return 0.clamp(to_min, to_max);
+    }
+
+    let offset = i64::from(value.clamp(from_min, from_max)) - i64::from(from_min);
+    let scaled = offset * (i64::from(to_max) - i64::from(to_min))
+        / (i64::from(from_max) - i64::from(from_min));
+    (i64::from(to_min) + scaled) as i32
+}
+
+/// The emit thread's loop: sends what the reader resolves to `controllers`
+/// and answers force feedback between, until the reader hangs up.
+fn run_emitter(emit_rx: &Receiver<Emit>, controllers: &mut [impl Sink]) -> Result<()> {
+    let mut last_ff_poll = Instant::now();
+    let mut latency = EmitLatency::default();
+    loop {
+        match emit_rx.recv_timeout(FF_POLL_INTERVAL) {
+            Ok(Emit::Outputs(outputs, queued)) => {
+                // One write per controller and frame rather than per change
+                let frames = frames(&outputs, controllers.len());
+                for (controller, runs) in controllers.iter_mut().zip(frames) {
+                    for frame in runs.iter().filter(|f| !f.is_empty()) {
+                        emit_with_retry(controller, frame)?;
+                    }
+                }
+                latency.record(queued.elapsed());
+            }
+            Ok(Emit::ReleaseAll(done)) => {
+                for controller in controllers.iter_mut() {
+                    controller.release_all()?;
+                }
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_ff_poll.elapsed() >= FF_POLL_INTERVAL {
+            for controller in controllers.iter_mut() {
+                controller.answer_force_feedback()?;
+            }
+            last_ff_poll = Instant::now();
+        }
+    }
+
+    if let Some(mean) = latency.mean() {
+        log::info!(
+            "Emit latency over {} batches: mean {:?}, max {:?}",
+            latency.batches,
+            mean,
+            latency.max
+        );
+    }
+
+    Ok(())
+}
+
+/// Has the emit thread release everything and waits until it has, so the
+/// releases are written before the caller ungrabs the keyboards. Returns at
+/// once if the emit thread has already stopped.
+fn release_all_and_wait(emit_tx: &Sender<Emit>) {
+    let (done_tx, done_rx) = bounded(1);
+    if emit_tx.send(Emit::ReleaseAll(done_tx)).is_ok() {
+        let _ = done_rx.recv();
+    }
+}
+
+/// Splits `outputs` into one run of frames per controller, keeping their
+/// order. A target that changes twice starts a new frame, so a press and
+/// release read in the same batch still reach the game as a tap rather than
+/// cancelling out within one frame.
+fn frames(outputs: &[ControllerOutput], controllers: usize) -> Vec<Vec<Vec<(Target, i32)>>> {
+    let mut frames: Vec<Vec<Vec<(Target, i32)>>> = vec![vec![Vec::new()]; controllers];
+    for output in outputs {
+        let runs = &mut frames[output.controller];
+        let current = runs
+            .last_mut()
+            .expect("every controller starts with a frame");
+        if current.iter().any(|(target, _)| *target == output.target) {
+            runs.push(vec![(output.target, output.value)]);
+        } else {
+            current.push((output.target, output.value));
+        }
+    }
+
+    frames
+}
+
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(is_transient_io)
+}
+
+/// Whether a failed read or write is worth trying again: the device had
+/// nothing ready (EAGAIN), or a signal interrupted the call (EINTR).
+fn is_transient_io(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+    )
+}
+
+/// Keeps `active` in step with the outputs sent to the controllers. Anything
+/// away from its rest value counts as held.
+fn track_active<C: Clock>(
+    active: &mut HashSet<(usize, Target)>,
+    dispatcher: &Dispatcher<C>,
+    outputs: &[ControllerOutput],
+) {
+    for output in outputs {
+        if output.value != dispatcher.rest(output.controller, output.target) {
+            active.insert((output.controller, output.target));
+        } else {
+            active.remove(&(output.controller, output.target));
+        }
+    }
+}
+
+/// Runs `command` with `sh -c` on its own thread, which waits for it so the
+/// mapping loop doesn't and no zombie is left behind.
+fn spawn_command(command: String) {
+    thread::spawn(move || {
+        let status = process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(process::Stdio::null())
+            .status();
+        match status {
+            Ok(status) if !status.success() => log::warn!("\"{}\" exited with {}", command, status),
+            Ok(_) => {}
+            Err(e) => log::error!("Failed to run \"{}\": {}", command, e),
+        }
+    });
+}
+
+/// Returns the buttons and axes `controller` holds to rest, taking them out
+/// of `active`.
+fn release_controller<C: Clock>(
+    active: &mut HashSet<(usize, Target)>,
+    dispatcher: &Dispatcher<C>,
+    controller: usize,
+) -> Vec<ControllerOutput> {
+    let mut released = Vec::new();
+    active.retain(|&(index, target)| {
+        if index != controller {
+            return true;
+        }
+        released.push(ControllerOutput {
+            controller,
+            target,
+            value: dispatcher.rest(controller, target),
+        });
+        false
+    });
+    released
+}
+
+/// Drops the outputs of controllers that are switched off. The dispatch
+/// still runs for them, so a key held across the switch releases cleanly.
+fn drop_disabled(outputs: &mut Vec<ControllerOutput>, enabled: &[Arc<AtomicBool>]) {
+    outputs.retain(|output| enabled[output.controller].load(Ordering::Relaxed));
+}
+
+/// Returns every held button and axis to rest, emptying `active`.
+fn release_active<C: Clock>(
+    active: &mut HashSet<(usize, Target)>,
+    dispatcher: &Dispatcher<C>,
+) -> Vec<ControllerOutput> {
+    active
+        .drain()
+        .map(|(controller, target)| ControllerOutput {
+            controller,
+            target,
+            value: dispatcher.rest(controller, target),
+        })
+        .collect()
+}
+
+fn controller_status(names: &[String], active: &HashSet<(usize, Target)>) -> Vec<ControllerStatus> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| ControllerStatus {
+            name: name.clone(),
+            active: active
+                .iter()
+                .filter(|(controller, _)| *controller == index)
+                .map(|(_, target)| *target)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Asks for SCHED_FIFO scheduling for the calling thread, so emitting isn't
+/// held up by ordinary load. This needs CAP_SYS_NICE or an rtprio limit;
+/// without either it fails and the thread keeps its normal priority.
+fn request_realtime_priority() -> io::Result<()> {
+    let param = libc::sched_param {
+        sched_priority: EMIT_PRIORITY,
+    };
+
+    // SAFETY: `param` is a valid sched_param for the duration of the call,
+    // and pid 0 refers to the calling thread.
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// A source device opened by the mapping thread.
+struct Source {
+    path: PathBuf,
+    device: Device,
+    grab: bool,
+    /// Passes unmapped input of a grabbed device back to the system.
+    forward: Option<VirtualDevice>,
+    /// Input waiting to be forwarded at the source's next `SYN_REPORT`, so
+    /// e.g. a mouse's X and Y movement stay in one frame.
+    forward_frame: Vec<InputEvent>,
+    /// Drives the lock LEDs of a grabbed, forwarded keyboard.
+    lock_leds: Option<device::LockLeds>,
+    /// Whether the device's motion drives a controller's stick.
+    takes_motion: bool,
+    /// The keys of the device the controllers take, see
+    /// [`Dispatcher::keys_bound_for`].
+    bound_keys: HashSet<KeyCode>,
+    /// Motion summed over the current frame, sent at its `SYN_REPORT`.
+    motion: (i32, i32),
+    /// The range of each absolute axis the device has, passed through to
+    /// the controllers, as a gamepad source has sticks and triggers.
+    abs_ranges: HashMap<AbsoluteAxisCode, (i32, i32)>,
+}
+
+/// Reads key events from source devices without grabbing them, so bindings
+/// can be tried out while the keys still type as usual.
+pub struct KeyWatcher {
+    sources: Vec<(PathBuf, Device)>,
+    fds: Vec<RawFd>,
+}
+
+impl KeyWatcher {
+    /// Key presses, releases and repeats that arrive within `timeout`, with
+    /// the device each came from.
+    pub fn read(&mut self, timeout: Duration) -> Result<Vec<(PathBuf, KeyCode, i32)>> {
+        let mut keys = Vec::new();
+
+        for idx in device::wait_readable(&self.fds, timeout)? {
+            let (path, device) = &mut self.sources[idx];
+            let events = match device.fetch_events() {
+                Ok(events) => events,
+                Err(e) if is_transient_io(&e) => continue,
+                Err(e) => return Err(device::read_error(path, e)),
+            };
+
+            keys.extend(
+                events
+                    .filter(|event| event.event_type() == EventType::KEY)
+                    .map(|event| (path.clone(), KeyCode::new(event.code()), event.value())),
+            );
+        }
+
+        Ok(keys)
+    }
+}
+
+/// What the user pressed when asked for a key while walking through a
+/// controller's buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureResult {
+    Key(KeyCode),
+    /// Escape: leave the button unbound.
+    Skip,
+    /// Backspace: redo the previous button.
+    Back,
+    /// Delete: abandon the whole walk-through.
+    Cancel,
+}
+
+/// A keyboard key bound on several controllers that read the same source,
+/// so one press drives all of them. Fine for co-op mirroring, a mistake
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub key: KeyCode,
+    /// Indices of the controllers binding `key`, in order.
+    pub controllers: Vec<usize>,
+}
+
+/// Keys bound on more than one of `controllers`, sorted by key. Controllers
+/// reading from different devices don't conflict, as a key only reaches
+/// the ones reading the device it was pressed on.
+pub fn find_conflicts(controllers: &[ControllerConfig]) -> Vec<Conflict> {
+    let mut binders: BTreeMap<u16, Vec<usize>> = BTreeMap::new();
+    for (index, config) in controllers.iter().enumerate() {
+        let keys: HashSet<KeyCode> = config.bound_keys().collect();
+        for key in keys {
+            binders.entry(key.0).or_default().push(index);
+        }
+    }
+
+    // Empty sources means every source
+    let share_source = |a: &ControllerConfig, b: &ControllerConfig| {
+        a.sources.is_empty()
+            || b.sources.is_empty()
+            || a.sources.iter().any(|source| b.sources.contains(source))
+    };
+
+    binders
+        .into_iter()
+        .filter_map(|(key, indices)| {
+            let controllers: Vec<usize> = indices
+                .iter()
+                .copied()
+                .filter(|&a| {
+                    indices
+                        .iter()
+                        .any(|&b| a != b && share_source(&controllers[a], &controllers[b]))
+                })
+                .collect();
+
+            (controllers.len() > 1).then(|| Conflict {
+                key: KeyCode::new(key),
+                controllers,
+            })
+        })
+        .collect()
+}
+
+pub struct DeviceMapper {
+    /// Source devices read during mapping, of any kind. Each controller
+    /// listens to a subset of them or to all.
+    pub sources: Vec<InputDevice>,
+    pub controllers: Vec<VirtualController>,
+    pub running: Arc<Mutex<bool>>,
+    pub mapped_keys: Arc<RwLock<HashSet<KeyCode>>>,
+    /// Key events from the most recent mapping session.
+    pub recording: Arc<Mutex<Vec<RecordedEvent>>>,
+    pub settings: SessionSettings,
+    /// The profile last saved or loaded, reread on SIGHUP when
+    /// [`HangupAction::Reload`] is set.
+    pub profile_path: Option<PathBuf>,
+    /// Sources the user agreed to map without a grab after grabbing them was
+    /// refused. The system keeps seeing their keys as well.
+    pub ungrabbed: Vec<PathBuf>,
+    /// Wakes the running session's loop, if any.
+    waker: Option<Arc<device::Waker>>,
+    /// Name given with `--forward-name` to the virtual keyboards that pass
+    /// unmapped input through. Without one, each is named after its own
+    /// source. See [`device::source_forward_name`].
+    pub forward_name: Option<String>,
+    /// Signals that stop a running session, [`DEFAULT_STOP_SIGNALS`] unless
+    /// set otherwise. SIGHUP reloads instead when the session's `hangup` is
+    /// [`HangupAction::Reload`], whether or not it is listed. Signals not
+    /// listed keep their default action.
+    pub stop_signals: Vec<i32>,
+    /// Whether `exec` bindings run their commands, set by `--allow-exec`
+    /// only. The commands run with this program's privileges, usually root
+    /// for uinput access, so the switch is kept out of profiles: anyone able
+    /// to edit one could otherwise turn it on and run anything as root.
+    pub allow_exec: bool,
+}
+
+impl DeviceMapper {
+    pub fn new(keyboard: InputDevice) -> Self {
+        DeviceMapper {
+            sources: vec![keyboard],
+            controllers: Vec::new(),
+            running: Arc::new(Mutex::new(false)),
+            mapped_keys: Arc::new(RwLock::new(HashSet::new())),
+            recording: Arc::new(Mutex::new(Vec::new())),
+            settings: SessionSettings::default(),
+            profile_path: None,
+            ungrabbed: Vec::new(),
+            waker: None,
+            forward_name: None,
+            stop_signals: DEFAULT_STOP_SIGNALS.to_vec(),
+            allow_exec: false,
+        }
+    }
+
+    pub fn add_controller(&mut self, controller: VirtualController) {
+        // Update mapped_keys set with the controller's key mappings
+        let mut mapped_keys = self.mapped_keys.write();
+        mapped_keys.extend(controller.bound_keys());
+
+        self.controllers.push(controller);
+    }
+
+    /// Drops a controller, which destroys its uinput device, and stops
+    /// treating its keys as mapped. Refused while mapping, since the session
+    /// runs its own copies of the controllers.
+    pub fn remove_controller(&mut self, index: usize) -> Result<()> {
+        self.ensure_idle()?;
+
+        self.controllers.remove(index);
+        self.refresh_mapped_keys();
+
+        Ok(())
+    }
+
+    /// Swaps two controllers' places in the list, which sets their hotbar
+    /// keys and the order profiles save them in. Their uinput devices stay
+    /// as they are, so the kernel's js and event numbers, which many games
+    /// number players by, only follow once the devices are recreated, e.g.
+    /// by loading the saved profile. Refused while mapping.
+    pub fn swap_controllers(&mut self, a: usize, b: usize) -> Result<()> {
+        self.ensure_idle()?;
+
+        self.controllers.swap(a, b);
+
+        Ok(())
+    }
+
+    /// Rebuilds `mapped_keys` after a controller's bindings were edited, so
+    /// keys that are no longer bound get forwarded again.
+    pub fn refresh_mapped_keys(&mut self) {
+        let mut mapped_keys = self.mapped_keys.write();
+        mapped_keys.clear();
+
+        for controller in &self.controllers {
+            mapped_keys.extend(controller.bound_keys());
+        }
+    }
+
+    /// Points a controller at a single source device, adding the device to
+    /// the session if it isn't read yet. The device must still be present.
+    /// `None` makes the controller listen to every source again.
+    pub fn reassign_source(&mut self, index: usize, device: Option<InputDevice>) -> Result<()> {
+        self.ensure_idle()?;
+
+        let Some(device) = device else {
+            self.controllers[index].sources.clear();
+            return Ok(());
+        };
+
+        self.controllers[index].sources = vec![device.path.clone()];
+        self.include_source(device)
+    }
+
+    /// Lets a controller read from one more source device, e.g. a mouse or
+    /// macropad next to the keyboard.
+    pub fn add_source(&mut self, index: usize, device: InputDevice) -> Result<()> {
+        self.ensure_idle()?;
+
+        let sources = &mut self.controllers[index].sources;
+        if !sources.contains(&device.path) {
+            sources.push(device.path.clone());
+        }
+        self.include_source(device)
+    }
+
+    /// Adds another keyboard to the session, e.g. the other half of a split
+    /// keyboard. Controllers that read every source see its keys merged
+    /// with the first keyboard's.
+    pub fn add_keyboard(&mut self, device: InputDevice) -> Result<()> {
+        self.ensure_idle()?;
+        self.include_source(device)
+    }
+
+    /// Swaps the source at `old` for `device`, e.g. after the keyboard was
+    /// unplugged and reselected. Controllers reading from `old` read from
+    /// `device` instead, and the grab choice carries over.
+    pub fn replace_source(&mut self, old: &Path, mut device: InputDevice) -> Result<()> {
+        self.ensure_idle()?;
+
+        if let Some(index) = self.sources.iter().position(|s| s.path == old) {
+            device.grab = self.sources.remove(index).grab;
+        }
+        self.sources.retain(|s| s.path != device.path);
+
+        for controller in &mut self.controllers {
+            for source in &mut controller.sources {
+                if source == old {
+                    *source = device.path.clone();
+                }
+            }
+            controller.sources.dedup();
+        }
+
+        self.include_source(device)
+    }
+
+    /// Snapshot of the session settings and controllers, for saving.
+    pub fn profile(&self) -> Profile {
+        Profile {
+            global: self.settings.clone(),
+            controllers: self.controllers.iter().map(|c| c.config()).collect(),
+        }
+    }
+
+    /// Replaces the settings and controllers with those of `profile`. Source
+    /// devices the profile names are added when present.
+    pub fn apply_profile(&mut self, profile: Profile) -> Result<()> {
+        self.ensure_idle()?;
+
+        let mut controllers = Vec::new();
+        for config in &profile.controllers {
+            controllers.push(VirtualController::from_config(config)?);
+        }
+
+        for path in profile.controllers.iter().flat_map(|c| &c.sources) {
+            if let Ok(device) = Device::open(path) {
+                let _ = self.include_source(InputDevice::new(path.clone(), device));
+            }
+        }
+
+        self.settings = profile.global;
+        self.controllers = controllers;
+        self.refresh_mapped_keys();
+
+        Ok(())
+    }
+
+    /// Keys the controllers bind on top of each other. See
+    /// [`find_conflicts`].
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let configs: Vec<ControllerConfig> = self
+            .controllers
+            .iter()
+            .map(VirtualController::config)
+            .collect();
+        find_conflicts(&configs)
+    }
+
+    /// Sources that can be read but not grabbed because permission was
+    /// denied, e.g. by a sandbox. Each one is grabbed and released again to
+    /// find out. A grab another program holds fails with EBUSY instead and
+    /// isn't listed: reading without the grab would get none of its input.
+    pub fn denied_grabs(&self) -> Vec<PathBuf> {
+        self.sources
+            .iter()
+            .filter(|s| s.grab && !self.ungrabbed.contains(&s.path))
+            .filter(|s| {
+                let Ok(mut device) = Device::open(&s.path) else {
+                    return false;
+                };
+                match device.grab() {
+                    Ok(()) => {
+                        let _ = device.ungrab();
+                        false
+                    }
+                    Err(e) => e.kind() == io::ErrorKind::PermissionDenied,
+                }
+            })
+            .map(|s| s.path.clone())
+            .collect()
+    }
+
+    fn ensure_idle(&self) -> Result<()> {
+        if *self.running.lock() {
+            return Err(anyhow::anyhow!(
+                "Cannot change the session while mapping is active"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Adds `device` to the devices read during mapping, after making sure
+    /// it is still there.
+    fn include_source(&mut self, device: InputDevice) -> Result<()> {
+        Device::open(&device.path)?;
+
+        if !self.sources.iter().any(|s| s.path == device.path) {
+            self.sources.push(device);
+        }
+
+        Ok(())
+    }
+
+    /// Starts the mapping thread. The returned receiver carries
+    /// [`SessionEvent`]s and disconnects when the thread ends.
+    pub fn start_mapping(
+        &mut self,
+    ) -> Result<(thread::JoinHandle<Result<()>>, Receiver<SessionEvent>)> {
+        // Check if we have any controllers
+        if self.controllers.is_empty() {
+            return Err(anyhow::anyhow!("No controllers available to map"));
+        }
+
+        // Store the source device paths and whether to grab each one
+        let source_settings: Vec<_> = self
+            .sources
+            .iter()
+            .map(|k| (k.path.clone(), k.grab && !self.ungrabbed.contains(&k.path)))
+            .collect();
+
+        // Set up signal handler before anything is marked running, so a signal
+        // that can't be handled leaves the mapper idle. It is closed when the
+        // session ends, so a later session doesn't see its signals twice.
+        let hangup = self.settings.hangup;
+        let mut handled = self.stop_signals.clone();
+        if hangup == HangupAction::Reload && !handled.contains(&signal_hook::consts::SIGHUP) {
+            handled.push(signal_hook::consts::SIGHUP);
+        }
+        let mut signals = signal_hook::iterator::Signals::new(&handled)
+            .context("Cannot register the signal handlers")?;
+
+        // Store the settings and key mappings we need to recreate
+        let controller_settings: Vec<_> = self.controllers.iter().map(|c| c.config()).collect();
+
+        *self.running.lock() = true;
+        let running = self.running.clone();
+        let mapped_keys_arc = self.mapped_keys.clone();
+        let settings = self.settings.clone();
+        let allow_exec = self.allow_exec;
+        let recording = self.recording.clone();
+        recording.lock().clear();
+        let key_mappings: Vec<_> = self
+            .controllers
+            .iter()
+            .map(|c| c.key_mapping.clone())
+            .collect();
+        let enabled: Vec<_> = self.controllers.iter().map(|c| c.enabled.clone()).collect();
+        let profile_path = self.profile_path.clone();
+        let forward_name = self.forward_name.clone();
+
+        // Create a signal channel
+        let (signal_tx, signal_rx) = bounded(1);
+        let (reload_tx, reload_rx) = bounded(1);
+        let (session_tx, session_rx) = unbounded();
+
+        // Stop requests and signals wake the loop rather than waiting for
+        // its poll to time out
+        let waker = Arc::new(device::Waker::new()?);
+        self.waker = Some(waker.clone());
+        let signal_waker = waker.clone();
+
+        let signal_running = running.clone();
+        let signal_tx_clone = signal_tx.clone();
+
+        let signals_handle = signals.handle();
+        let _signal_thread = thread::spawn(move || {
+            for sig in signals.forever() {
+                if sig == signal_hook::consts::SIGHUP && hangup == HangupAction::Reload {
+                    // A reload already queued covers this one too
+                    let _ = reload_tx.try_send(());
+                    signal_waker.wake();
+                    continue;
+                }
+
+                log::info!("Received signal {:?}, shutting down...", sig);
+                *signal_running.lock() = false;
+                let _ = signal_tx_clone.send(());
+                signal_waker.wake();
+                break;
+            }
+        });
+
+        let handle = thread::spawn(move || -> Result<()> {
+            // Open every source device. Reads are non-blocking so one idle
+            // device can't hold up the others.
+            let mut sources = Vec::new();
+            for (path, grab) in source_settings {
+                let device = device::open_source(&path)?;
+                device.set_nonblocking(true)?;
+                let abs_ranges = device
+                    .get_absinfo()
+                    .map(|axes| {
+                        axes.map(|(axis, info)| (axis, (info.minimum(), info.maximum())))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                sources.push(Source {
+                    path,
+                    device,
+                    grab,
+                    forward: None,
+                    forward_frame: Vec::new(),
+                    lock_leds: None,
+                    takes_motion: false,
+                    bound_keys: HashSet::new(),
+                    motion: (0, 0),
+                    abs_ranges,
+                });
+            }
+
+            // Get the mapped keys
+            let mut mapped_keys = mapped_keys_arc.read().clone();
+
+            let mut dispatcher = Dispatcher::new(
+                &controller_settings,
+                Duration::from_millis(settings.pulse_ms),
+            );
+            let tick = Duration::from_millis(settings.tick_ms);
+
+            for source in &mut sources {
+                source.takes_motion = dispatcher.takes_motion(&source.path);
+                source.bound_keys = dispatcher.keys_bound_for(&source.path);
+            }
+
+            // Create the controller devices
+            let mut controllers = Vec::new();
+
+            for (index, config) in controller_settings.iter().enumerate() {
+                let mut controller = VirtualController::from_config(config)?;
+                let rumble_tx = session_tx.clone();
+                controller.on_rumble(move |strength| {
+                    let _ = rumble_tx.send(SessionEvent::Rumble {
+                        controller: index,
+                        strength,
+                    });
+                });
+                controllers.push(controller);
+            }
+
+            // Create virtual keyboards for passing through non-mapped keys.
+            // An ungrabbed source already reaches the system, so forwarding
+            // its keys would type them twice.
+            if settings.forward_unmapped {
+                for (index, source) in sources.iter_mut().filter(|s| s.grab).enumerate() {
+                    let name = device::source_forward_name(
+                        forward_name.as_deref(),
+                        source.device.name().unwrap_or_default(),
+                        index,
+                    );
+                    source.forward = Some(
+                        device::build_forward_device(&source.device, &name)
+                            .map_err(device::uinput_error)?,
+                    );
+                    source.lock_leds = device::LockLeds::new(&source.device);
+                }
+            }
+
+            // Grab each source exclusively, unless the user asked to keep it
+            // delivering input to the system as well. This comes last, once
+            // everything above has been set up, so a failure never leaves a
+            // keyboard taken with nothing reading it.
+            for index in 0..sources.len() {
+                let source = &mut sources[index];
+                if !source.grab {
+                    continue;
+                }
+
+                if let Err(e) = source.device.grab() {
+                    log::error!("Failed to grab {}: {}", source.path.display(), e);
+                    let error = if e.kind() == io::ErrorKind::PermissionDenied {
+                        AppError::GrabDenied(source.path.display().to_string()).into()
+                    } else if e.kind() == io::ErrorKind::ResourceBusy {
+                        anyhow::anyhow!(
+                            "{} is grabbed by another program; close it and try again",
+                            source.path.display()
+                        )
+                    } else {
+                        anyhow::anyhow!("Failed to grab keyboard: {}", e)
+                    };
+
+                    // The ones already grabbed go back before returning
+                    for source in sources[..index].iter_mut().filter(|s| s.grab) {
+                        if let Err(e) = source.device.ungrab() {
+                            log::warn!("Error releasing {}: {}", source.path.display(), e);
+                        }
+                    }
+                    return Err(error);
+                }
+                log::info!("Grabbed {}", source.path.display());
+            }
+
+            // Reading and emitting run on separate threads so that a slow
+            // emit never delays the next read, and the emitter can run at
+            // real-time priority
+            let (emit_tx, emit_rx) = bounded::<Emit>(EMIT_QUEUE_LEN);
+            let emitter =
+                thread::Builder::new()
+                    .name("emit".to_string())
+                    .spawn(move || -> Result<()> {
+                        match request_realtime_priority() {
+                            Ok(()) => log::info!("Emitting at real-time priority"),
+                            Err(e) => log::warn!(
+                                "Real-time priority not applied, emitting at normal priority: {}",
+                                e
+                            ),
+                        }
+                        run_emitter(&emit_rx, &mut controllers)
+                    })?;
+
+            // Buttons and axes each controller currently holds, for the menu
+            let names: Vec<_> = controller_settings.iter().map(|c| c.name.clone()).collect();
+            let mut active: HashSet<(usize, Target)> = HashSet::new();
+            let mut menu_open = false;
+            let mut panic_combo = PanicCombo::default();
+
+            let mut hotbar = settings.hotbar.then(|| Hotbar::new(&mapped_keys));
+            if let Some(shadowed) = hotbar.as_ref().map(Hotbar::shadowed)
+                && !shadowed.is_empty()
+            {
+                log::warn!(
+                    "Hotbar keys bound to a controller, left to their bindings: {:?}",
+                    shadowed
+                );
+            }
+
+            if settings.stop_key != 0 && mapped_keys.contains(&KeyCode::new(settings.stop_key)) {
+                log::warn!(
+                    "{} stops the session, so its binding does nothing",
+                    key_name(KeyCode::new(settings.stop_key))
+                );
+            }
+
+            let commands = controller_settings
+                .iter()
+                .map(|c| c.mapping.summary_counts().commands)
+                .sum::<usize>();
+            if commands > 0 && !allow_exec {
+                log::warn!(
+                    "Ignoring {} command binding(s); start with --allow-exec to run them",
+                    commands
+                );
+            }
+
+            // The waker goes last, after the sources
+            let mut fds: Vec<_> = sources.iter().map(|s| s.device.as_raw_fd()).collect();
+            fds.push(waker.fd());
+            let session_start = Instant::now();
+
+            // A read error that ended the session, returned after the teardown
+            let mut failure = None;
+
+            // Main processing loop
+            while *running.lock() {
+                // Use crossbeam's select for efficient waiting
+                select! {
+                    recv(signal_rx) -> _ => {
+                        // Signal received, exit loop
+                        log::info!("Signal received, exiting keyboard mapping");
+                        break;
+                    },
+                    recv(reload_rx) -> _ => {
+                        let configs = match reload_bindings(profile_path.as_deref(), &controller_settings) {
+                            Ok(configs) => configs,
+                            Err(e) => {
+                                log::warn!("Not reloading: {}", e);
+                                continue;
+                            }
+                        };
+
+                        // Keys held now may be bound differently afterwards,
+                        // so everything is let go first
+                        let released = release_active(&mut active, &dispatcher);
+                        if !released.is_empty() && emit_tx.send(Emit::outputs(released)).is_err() {
+                            *running.lock() = false;
+                        }
+
+                        dispatcher = Dispatcher::new(&configs, Duration::from_millis(settings.pulse_ms));
+                        for source in &mut sources {
+                            source.takes_motion = dispatcher.takes_motion(&source.path);
+                            source.bound_keys = dispatcher.keys_bound_for(&source.path);
+                        }
+                        for (key_mapping, config) in key_mappings.iter().zip(&configs) {
+                            *key_mapping.write() = config.mapping.clone();
+                        }
+                        mapped_keys = configs
+                            .iter()
+                            .flat_map(|c| c.bound_keys())
+                            .collect();
+                        *mapped_keys_arc.write() = mapped_keys.clone();
+                        log::info!("Reloaded bindings from the profile");
+                    },
+                    default => {
+                        // Wait briefly for input so `running` is rechecked
+                        // periodically even when every device is idle, and
+                        // no longer than the next pending tap release
+                        let timeout = dispatcher.next_deadline().map_or(tick, |due| {
+                            due.saturating_duration_since(Instant::now()).min(tick)
+                        });
+
+                        for idx in device::wait_readable(&fds, timeout)? {
+                            let Some(source) = sources.get_mut(idx) else {
+                                // Woken; `running` and the channels are
+                                // checked on the next turn
+                                waker.reset();
+                                continue;
+                            };
+
+                            let events: Vec<InputEvent> = match source.device.fetch_events() {
+                                Ok(events) => events.collect(),
+                                Err(e) if is_transient_io(&e) => continue,
+                                Err(e) => {
+                                    // Ends the session through the teardown
+                                    // below, so the other keyboards are
+                                    // ungrabbed and nothing is left held
+                                    failure = Some(device::read_error(&source.path, e));
+                                    *running.lock() = false;
+                                    break;
+                                }
+                            };
+
+                            // Controller changes from the whole read go to
+                            // the emitter together
+                            let mut batch = Vec::new();
+
+                            for ev in events {
+                                if ev.event_type() == EventType::KEY {
+                                    let key_code = KeyCode::new(ev.code());
+                                    let value = ev.value();
+
+                                    if panic_combo.handle(key_code, value) {
+                                        // The teardown below releases and
+                                        // ungrabs everything
+                                        log::warn!("Ctrl+Alt+K pressed, releasing everything");
+                                        *running.lock() = false;
+                                        let _ = session_tx.send(SessionEvent::Stopped);
+                                        break;
+                                    }
+
+                                    // The menu and its keys stay out of the
+                                    // dispatch, the forwarding and the recording
+                                    if menu_open {
+                                        if value == 1 {
+                                            match key_code {
+                                                KeyCode::KEY_2 => {
+                                                    *running.lock() = false;
+                                                    let _ = session_tx.send(SessionEvent::Stopped);
+                                                }
+                                                KeyCode::KEY_1 | KeyCode::KEY_ESC => {
+                                                    menu_open = false;
+                                                    let _ = session_tx.send(SessionEvent::MenuClosed);
+                                                }
+                                                key if (KeyCode::KEY_3.0..=KeyCode::KEY_9.0)
+                                                    .contains(&key.0) =>
+                                                {
+                                                    let index = usize::from(key.0 - KeyCode::KEY_3.0);
+                                                    let _ = session_tx.send(SessionEvent::SwitchProfile(index));
+                                                }
+                                                key if key.0 == settings.menu_key => {
+                                                    menu_open = false;
+                                                    let _ = session_tx.send(SessionEvent::MenuClosed);
+                                                }
+                                                _ => {}
+                                            }
+                                        }
+                                        continue;
+                                    }
+
+                                    if settings.stop_key != 0 && key_code.0 == settings.stop_key {
+                                        if value == 1 {
+                                            log::info!("Stop key pressed");
+                                            *running.lock() = false;
+                                            let _ = session_tx.send(SessionEvent::Stopped);
+                                        }
+                                        continue;
+                                    }
+
+                                    if settings.menu_key != 0 && key_code.0 == settings.menu_key {
+                                        if value == 1 {
+                                            menu_open = true;
+                                            let status = controller_status(&names, &active);
+                                            let _ = session_tx.send(SessionEvent::MenuOpened(status));
+                                        }
+                                        continue;
+                                    }
+
+                                    if let Some(hotbar) = hotbar.as_mut()
+                                        && let Some(action) = hotbar.handle(key_code, value, names.len())
+                                    {
+                                        let controller = hotbar.selected;
+                                        match action {
+                                            HotbarAction::Swallow => {}
+                                            HotbarAction::Select => {}
+                                            HotbarAction::Nudge(step) => {
+                                                // Tenths, so repeated nudges don't drift
+                                                let scale = dispatcher.axis_scale(controller) + step;
+                                                let scale = ((scale * 10.0).round() / 10.0).max(0.0);
+                                                dispatcher.set_axis_scale(controller, scale);
+                                            }
+                                            HotbarAction::Toggle => {
+                                                let was_enabled =
+                                                    enabled[controller].fetch_xor(true, Ordering::Relaxed);
+                                                // The released outputs go into
+                                                // the batch after anything
+                                                // earlier in it that pressed them
+                                                if was_enabled {
+                                                    batch.extend(release_controller(
+                                                        &mut active,
+                                                        &dispatcher,
+                                                        controller,
+                                                    ));
+                                                }
+                                            }
+                                        }
+                                        if !matches!(action, HotbarAction::Swallow) {
+                                            let _ = session_tx.send(SessionEvent::Tuning {
+                                                controller,
+                                                axis_scale: dispatcher.axis_scale(controller),
+                                            });
+                                        }
+                                        continue;
+                                    }
+
+                                    {
+                                        let mut recording = recording.lock();
+                                        if recording.len() < MAX_RECORDED_EVENTS {
+                                            recording.push(RecordedEvent {
+                                                offset_ms: session_start.elapsed().as_millis() as u64,
+                                                code: key_code.0,
+                                                value,
+                                            });
+                                        }
+                                    }
+
+                                    let shortcut =
+                                        dispatcher.takes_shortcut(Some(&source.path), key_code, value);
+                                    if source.bound_keys.contains(&key_code) || shortcut {
+                                        if value == 1 {
+                                            let _ = session_tx.send(SessionEvent::Input {
+                                                key: key_code,
+                                                offset: session_start.elapsed(),
+                                            });
+                                        }
+
+                                        let mut outputs =
+                                            dispatcher.handle_key(Some(&source.path), key_code, value);
+                                        drop_disabled(&mut outputs, &enabled);
+                                        track_active(&mut active, &dispatcher, &outputs);
+                                        // Like their outputs, a switched-off
+                                        // controller's commands don't run
+                                        for (controller, command) in dispatcher.take_commands() {
+                                            if allow_exec && enabled[controller].load(Ordering::Relaxed) {
+                                                spawn_command(command);
+                                            }
+                                        }
+                                        batch.extend(outputs);
+                                    } else {
+                                        // Unbound modifiers still count toward
+                                        // shortcuts, and reach the system as
+                                        // usual when none matches
+                                        dispatcher.track_modifier(key_code, value);
+                                        if source.forward.is_some() {
+                                            source.forward_frame.push(ev);
+                                            if let Some(leds) = source.lock_leds.as_mut()
+                                                && let Err(e) =
+                                                    leds.handle_key(&mut source.device, key_code, value)
+                                            {
+                                                log::warn!("Failed to set keyboard LEDs: {}", e);
+                                            }
+                                        }
+                                    }
+                                } else {
+                                    // Motion driving a stick is summed over
+                                    // the frame and kept from the pointer
+                                    if source.takes_motion && ev.event_type() == EventType::RELATIVE {
+                                        match RelativeAxisCode(ev.code()) {
+                                            RelativeAxisCode::REL_X => {
+                                                source.motion.0 += ev.value();
+                                                continue;
+                                            }
+                                            RelativeAxisCode::REL_Y => {
+                                                source.motion.1 += ev.value();
+                                                continue;
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+
+                                    if ev.event_type() == EventType::ABSOLUTE {
+                                        let axis = AbsoluteAxisCode(ev.code());
+                                        if let Some(&range) = source.abs_ranges.get(&axis) {
+                                            let mut outputs = dispatcher.handle_axis(
+                                                Some(&source.path),
+                                                axis,
+                                                ev.value(),
+                                                range,
+                                            );
+                                            drop_disabled(&mut outputs, &enabled);
+                                            track_active(&mut active, &dispatcher, &outputs);
+                                            batch.extend(outputs);
+                                        }
+                                    }
+
+                                    if ev.event_type() == EventType::SYNCHRONIZATION
+                                        && ev.code() == SynchronizationCode::SYN_REPORT.0
+                                        && source.motion != (0, 0)
+                                    {
+                                        let (dx, dy) = std::mem::take(&mut source.motion);
+                                        let mut outputs =
+                                            dispatcher.handle_motion(Some(&source.path), dx, dy);
+                                        drop_disabled(&mut outputs, &enabled);
+                                        track_active(&mut active, &dispatcher, &outputs);
+                                        batch.extend(outputs);
+                                    }
+
+                                    if let Some(forward) = source.forward.as_mut() {
+                                        // The frame is forwarded as a whole at
+                                        // its end; `emit` adds the SYN_REPORT
+                                        if ev.event_type() != EventType::SYNCHRONIZATION {
+                                            source.forward_frame.push(ev);
+                                        } else if ev.code() == SynchronizationCode::SYN_REPORT.0
+                                            && !source.forward_frame.is_empty()
+                                        {
+                                            forward.emit(&source.forward_frame)?;
+                                            source.forward_frame.clear();
+                                        }
+                                    }
+                                }
+                            }
+
+                            if !batch.is_empty() && emit_tx.send(Emit::outputs(batch)).is_err() {
+                                // The emitter failed; its error is reported
+                                // once it is joined below
+                                *running.lock() = false;
+                            }
+                        }
+
+                        let mut released = dispatcher.fire_due();
+                        drop_disabled(&mut released, &enabled);
+                        track_active(&mut active, &dispatcher, &released);
+                        if !released.is_empty() && emit_tx.send(Emit::outputs(released)).is_err() {
+                            *running.lock() = false;
+                        }
+                    }
+                }
+            }
+
+            // Teardown runs in a fixed order so a game still reading sees
+            // neither stuck inputs nor stray keys:
+            //  1. every button is released and every axis centered while the
+            //     keyboards are still grabbed, so the releases can't race raw
+            //     key events. This covers all of them, not just those seen
+            //     held, in case a game missed a release. The emitter
+            //     confirms they are written before anything else happens;
+            //  2. the keyboards are ungrabbed;
+            //  3. the forwarding keyboards go, now that the real ones type
+            //     directly again;
+            //  4. the controllers go last, when the emitter exits.
+            release_all_and_wait(&emit_tx);
+
+            for source in &mut sources {
+                if source.grab {
+                    match source.device.ungrab() {
+                        Ok(_) => log::info!("Released {}", source.path.display()),
+                        Err(e) => log::warn!("Error releasing {}: {}", source.path.display(), e),
+                    }
+                }
+            }
+
+            for source in &mut sources {
+                source.forward = None;
+            }
+
+            signals_handle.close();
+            drop(emit_tx);
+            let emitted = emitter.join().expect("Failed to join emit thread");
+
+            match failure {
+                Some(e) => Err(e),
+                None => emitted,
+            }
+        });
+
+        Ok((handle, session_rx))
+    }
+
+    /// Asks a running session to stop. Join its thread to wait for it.
+    pub fn stop_mapping(&self) {
+        *self.running.lock() = false;
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+    }
+
+    /// Opens every source for watching keys outside a session.
+    pub fn watch_keys(&self) -> Result<KeyWatcher> {
+        let mut sources = Vec::new();
+        for source in &self.sources {
+            let device = device::open_source(&source.path)?;
+            device.set_nonblocking(true)?;
+            sources.push((source.path.clone(), device));
+        }
+        let fds = sources
+            .iter()
+            .map(|(_, device)| device.as_raw_fd())
+            .collect();
+
+        Ok(KeyWatcher { sources, fds })
+    }
+
+    /// Like [`DeviceMapper::capture_key`], but Escape, Backspace and Delete
+    /// are read as [`CaptureResult::Skip`], [`CaptureResult::Back`] and
+    /// [`CaptureResult::Cancel`] rather than as keys to bind.
+    pub fn capture_mapping_key(&mut self) -> Result<CaptureResult> {
+        let key = self
+            .capture_key(None)?
+            .expect("capture without a timeout returns a key");
+
+        Ok(match key {
+            KeyCode::KEY_ESC => CaptureResult::Skip,
+            KeyCode::KEY_BACKSPACE => CaptureResult::Back,
+            KeyCode::KEY_DELETE => CaptureResult::Cancel,
+            key => CaptureResult::Key(key),
+        })
+    }
+
+    /// Waits for a key press on any source device. Returns `None` if
+    /// `timeout` passes first; without one it waits until a key is pressed.
+    pub fn capture_key(&mut self, timeout: Option<Duration>) -> Result<Option<KeyCode>> {
+        log::debug!("Waiting for a key to capture");
+
+        // Create a copy of the source paths for the capture thread, so keys
+        // on any source (keypad, mouse buttons) can be captured
+        let source_paths: Vec<_> = self.sources.iter().map(|s| s.path.clone()).collect();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // Spawn a thread to capture the key
+        let handle = thread::spawn(move || -> Result<Option<KeyCode>> {
+            let mut devices = Vec::new();
+            for path in &source_paths {
+                let device = device::open_source(path)?;
+                device.set_nonblocking(true)?;
+                devices.push(device);
+            }
+            let fds: Vec<_> = devices.iter().map(|d| d.as_raw_fd()).collect();
+
+            loop {
+                // Blocks in poll until a device has input or time is up
+                let wait = match deadline {
+                    Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                        Some(left) if !left.is_zero() => left,
+                        _ => return Ok(None),
+                    },
+                    None => Duration::MAX,
+                };
+
+                for idx in device::wait_readable(&fds, wait)? {
+                    let events = match devices[idx].fetch_events() {
+                        Ok(events) => events,
+                        Err(e) if is_transient_io(&e) => continue,
+                        Err(e) => return Err(device::read_error(&source_paths[idx], e)),
+                    };
+
+                    for event in events {
+                        if event.event_type() == EventType::KEY && event.value() == 1 {
+                            // Key press (not release)
+                            return Ok(Some(KeyCode::new(event.code())));
+                        }
+                    }
+                }
+            }
+        });
+
+        handle.join().expect("Failed to join key capture thread")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{DEFAULT_CHORD_WINDOW_MS, MAX_CHORD_WINDOW_MS};
+
+    const PULSE: Duration = Duration::from_millis(16);
+
+    /// A dispatcher on a manual clock for one controller with `bindings`.
+    fn dispatcher(bindings: &[(KeyCode, Binding)]) -> Dispatcher<ManualClock> {
+        let mut mapping = Mapping::new();
+        for (key, binding) in bindings {
+            mapping.insert(*key, binding.clone());
+        }
+        let config = ControllerConfig {
+            mapping,
+            ..ControllerConfig::default()
+        };
+
+        Dispatcher::with_clock(&[config], PULSE, ManualClock::new())
+    }
+
+    /// Moves the clock on by `ms` and returns what came due.
+    fn advance(dispatcher: &mut Dispatcher<ManualClock>, ms: u64) -> Vec<ControllerOutput> {
+        let now = dispatcher.clock_mut().now() + Duration::from_millis(ms);
+        dispatcher.clock_mut().advance_to(now);
+        dispatcher.fire_due()
+    }
+
+    fn button(button: KeyCode, value: i32) -> ControllerOutput {
+        ControllerOutput {
+            controller: 0,
+            target: Target::Button(button),
+            value,
+        }
+    }
+
+    /// A controller that keeps what it was last sent, shared with the test
+    /// while the emit thread owns it.
+    #[derive(Clone, Default)]
+    struct FakeSink(Arc<Mutex<FakeState>>);
+
+    #[derive(Default)]
+    struct FakeState {
+        values: HashMap<Target, i32>,
+        frames: usize,
+        /// Errors the next writes fail with, first to last.
+        failures: Vec<io::ErrorKind>,
+    }
+
+    impl Sink for FakeSink {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn emit_frame(&mut self, frame: &[(Target, i32)]) -> Result<()> {
+            let mut state = self.0.lock();
+            if !state.failures.is_empty() {
+                return Err(io::Error::from(state.failures.remove(0)).into());
+            }
+            state.frames += 1;
+            state.values.extend(frame.iter().copied());
+            Ok(())
+        }
+
+        fn release_all(&mut self) -> Result<()> {
+            // Slow enough that a teardown not waiting for it would get ahead
+            thread::sleep(Duration::from_millis(50));
+            self.0
+                .lock()
+                .values
+                .values_mut()
+                .for_each(|value| *value = 0);
+            Ok(())
+        }
+
+        fn answer_force_feedback(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs the emit loop on its own thread for `sink`, as a session does.
+    fn emitter(sink: &FakeSink) -> (Sender<Emit>, thread::JoinHandle<Result<()>>) {
+        let (emit_tx, emit_rx) = bounded(EMIT_QUEUE_LEN);
+        let mut controllers = vec![sink.clone()];
+        let handle = thread::spawn(move || run_emitter(&emit_rx, &mut controllers));
+        (emit_tx, handle)
+    }
+
+    #[test]
+    fn turbo_keeps_a_button_another_key_holds_down() {
+        let mut dispatcher = dispatcher(&[
+            (KeyCode::KEY_A, Binding::Button(KeyCode::BTN_SOUTH)),
+            (
+                KeyCode::KEY_B,
+                Binding::Turbo {
+                    button: KeyCode::BTN_SOUTH,
+                    hz: 10,
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_B, 1).is_empty());
+        assert!(advance(&mut dispatcher, 50).is_empty());
+
+        // Once A lets go the turbo flips as usual
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_A, 0).is_empty());
+        assert_eq!(
+            advance(&mut dispatcher, 50),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+        assert_eq!(
+            advance(&mut dispatcher, 50),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_B, 0),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+    }
+
+    #[test]
+    fn key_pressed_during_a_turbo_gap_presses_the_button() {
+        let mut dispatcher = dispatcher(&[
+            (KeyCode::KEY_A, Binding::Button(KeyCode::BTN_SOUTH)),
+            (
+                KeyCode::KEY_B,
+                Binding::Turbo {
+                    button: KeyCode::BTN_SOUTH,
+                    hz: 10,
+                },
+            ),
+        ]);
+
+        dispatcher.handle_key(None, KeyCode::KEY_B, 1);
+        assert_eq!(
+            advance(&mut dispatcher, 50),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        // Releasing the turbo key leaves it down for A
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_B, 0).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 0),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+    }
+
+    #[test]
+    fn sticky_latches_a_turbo_button_steady() {
+        let mut dispatcher = dispatcher(&[
+            (KeyCode::KEY_S, Binding::Sticky),
+            (
+                KeyCode::KEY_B,
+                Binding::Turbo {
+                    button: KeyCode::BTN_SOUTH,
+                    hz: 10,
+                },
+            ),
+        ]);
+
+        dispatcher.handle_key(None, KeyCode::KEY_S, 1);
+        dispatcher.handle_key(None, KeyCode::KEY_S, 0);
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_B, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert!(advance(&mut dispatcher, 200).is_empty());
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_B, 0).is_empty());
+
+        // The next press lets go of the latch
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_B, 1),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_B, 0).is_empty());
+        assert!(advance(&mut dispatcher, 200).is_empty());
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_W, Binding::Button(KeyCode::BTN_DPAD_UP));
+        mapping.insert(
+            KeyCode::KEY_D,
+            Binding::Axis {
+                axis: AbsoluteAxisCode::ABS_X,
+                value: STICK_MAX,
+                ramp_ms: 40,
+                deadzone_pct: 0,
+            },
+        );
+        mapping.insert(
+            KeyCode::KEY_T,
+            Binding::Turbo {
+                button: KeyCode::BTN_EAST,
+                hz: 12,
+            },
+        );
+        mapping.insert(KeyCode::KEY_LEFTSHIFT, Binding::Layer("alt".to_string()));
+        mapping.insert_layer_binding("alt", KeyCode::KEY_W, Binding::Tap(KeyCode::BTN_NORTH));
+        let config = ControllerConfig {
+            name: "Pad".to_string(),
+            mapping,
+            dpad_mode: DpadMode::Hat,
+            axis_scale: 0.5,
+            normalize_diagonals: true,
+            chords: vec![Chord {
+                keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                button: KeyCode::BTN_TL,
+                priority: DEFAULT_CHORD_PRIORITY,
+            }],
+            double_taps: vec![DoubleTap {
+                key: KeyCode::KEY_A,
+                button: KeyCode::BTN_TR,
+                window_ms: 200,
+                pulse_ms: 30,
+            }],
+            shortcuts: vec![Shortcut {
+                modifiers: vec![Modifier::Shift],
+                key: KeyCode::KEY_1,
+                button: KeyCode::BTN_START,
+                priority: DEFAULT_SHORTCUT_PRIORITY,
+            }],
+            ..ControllerConfig::default()
+        };
+
+        let imported = import_json(&export_json(std::slice::from_ref(&config))).unwrap();
+        assert_eq!(imported, [config]);
+    }
+
+    #[test]
+    fn json_import_refuses_commands() {
+        let plain = r#"{"controllers": [{"name": "Pad", "bindings": {"KEY_X": "exec reboot"}}]}"#;
+        assert!(import_json(plain).is_err());
+
+        let layered = r#"{"controllers": [{"name": "Pad", "bindings": {},
+            "layers": {"alt": {"KEY_X": "exec rm -rf /"}}}]}"#;
+        assert!(import_json(layered).is_err());
+    }
+
+    #[test]
+    fn teardown_waits_for_the_releases_before_going_on() {
+        let sink = FakeSink::default();
+        let (emit_tx, emitter) = emitter(&sink);
+
+        emit_tx
+            .send(Emit::outputs(vec![button(KeyCode::BTN_SOUTH, 1)]))
+            .unwrap();
+        release_all_and_wait(&emit_tx);
+
+        // Where the session would ungrab: the release is already out
+        assert_eq!(sink.0.lock().values[&Target::Button(KeyCode::BTN_SOUTH)], 0);
+
+        drop(emit_tx);
+        emitter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn teardown_goes_on_when_the_emitter_has_stopped() {
+        let (emit_tx, emit_rx) = bounded(1);
+        drop(emit_rx);
+        release_all_and_wait(&emit_tx);
+
+        // One that fails before answering drops the sender it was given
+        let (emit_tx, emit_rx) = bounded(1);
+        let emitter = thread::spawn(move || drop(emit_rx.recv()));
+        release_all_and_wait(&emit_tx);
+        emitter.join().unwrap();
+    }
+
+    #[test]
+    fn stopping_with_keys_held_leaves_every_button_up() {
+        let mut dispatcher = dispatcher(&[
+            (KeyCode::KEY_A, Binding::Button(KeyCode::BTN_SOUTH)),
+            (KeyCode::KEY_B, Binding::Button(KeyCode::BTN_EAST)),
+        ]);
+        let sink = FakeSink::default();
+        let (emit_tx, emitter) = emitter(&sink);
+
+        for key in [KeyCode::KEY_A, KeyCode::KEY_B] {
+            let outputs = dispatcher.handle_key(None, key, 1);
+            emit_tx.send(Emit::outputs(outputs)).unwrap();
+        }
+        release_all_and_wait(&emit_tx);
+
+        let state = sink.0.lock();
+        assert_eq!(state.values.len(), 2);
+        assert!(state.values.values().all(|&value| value == 0));
+        drop(state);
+
+        drop(emit_tx);
+        emitter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn source_axes_reach_the_controller_rescaled() {
+        let mut dispatcher = dispatcher(&[]);
+
+        // A stick reporting 0..=255 spans the controller's whole stick range
+        for (value, expected) in [(0, STICK_MIN), (255, STICK_MAX)] {
+            assert_eq!(
+                dispatcher.handle_axis(None, AbsoluteAxisCode::ABS_X, value, (0, 255)),
+                [axis(AbsoluteAxisCode::ABS_X, expected)]
+            );
+        }
+        assert_eq!(
+            dispatcher.handle_axis(None, AbsoluteAxisCode::ABS_RZ, 1023, (0, 1023)),
+            [axis(AbsoluteAxisCode::ABS_RZ, TRIGGER_MAX)]
+        );
+
+        // Axes the controller doesn't declare go nowhere
+        assert!(
+            dispatcher
+                .handle_axis(None, AbsoluteAxisCode::ABS_WHEEL, 10, (0, 255))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rescaling_keeps_the_ends_and_centre() {
+        assert_eq!(rescale_axis(-1, (-1, 1), (STICK_MIN, STICK_MAX)), STICK_MIN);
+        assert_eq!(rescale_axis(0, (STICK_MIN, STICK_MAX), (-1, 1)), 0);
+        assert_eq!(rescale_axis(500, (0, 255), (0, TRIGGER_MAX)), TRIGGER_MAX);
+        assert_eq!(rescale_axis(7, (3, 3), (STICK_MIN, STICK_MAX)), 0);
+    }
+
+    /// A dispatcher like [`dispatcher`]'s with the axis scale at `scale`.
+    fn scaled_dispatcher(bindings: &[(KeyCode, Binding)], scale: f32) -> Dispatcher<ManualClock> {
+        let mut dispatcher = dispatcher(bindings);
+        dispatcher.set_axis_scale(0, scale);
+        dispatcher
+    }
+
+    fn axis(axis: AbsoluteAxisCode, value: i32) -> ControllerOutput {
+        ControllerOutput {
+            controller: 0,
+            target: Target::Axis(axis),
+            value,
+        }
+    }
+
+    #[test]
+    fn scaled_axes_stay_within_their_own_range() {
+        let toggle = Binding::AxisToggle {
+            axis: AbsoluteAxisCode::ABS_Z,
+            value: 200,
+        };
+        let mut dispatcher = scaled_dispatcher(&[(KeyCode::KEY_A, toggle)], 2.0);
+
+        // Triggers end at their own maximum, not the stick's
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 1),
+            [axis(AbsoluteAxisCode::ABS_Z, TRIGGER_MAX)]
+        );
+    }
+
+    #[test]
+    fn axis_cycle_wraps_and_stays_within_range() {
+        let cycle = Binding::AxisCycle {
+            axis: AbsoluteAxisCode::ABS_RZ,
+            values: vec![100, 255],
+        };
+        let mut dispatcher = scaled_dispatcher(&[(KeyCode::KEY_B, cycle)], 2.0);
+
+        for expected in [200, TRIGGER_MAX, 200] {
+            assert_eq!(
+                dispatcher.handle_key(None, KeyCode::KEY_B, 1),
+                [axis(AbsoluteAxisCode::ABS_RZ, expected)]
+            );
+            assert!(dispatcher.handle_key(None, KeyCode::KEY_B, 0).is_empty());
+        }
+    }
+
+    /// J and K press their own buttons alone and BTN_MODE together; L is
+    /// no part of the chord.
+    fn chord_dispatcher() -> Dispatcher<ManualClock> {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.insert(KeyCode::KEY_L, Binding::Button(KeyCode::BTN_NORTH));
+        let config = ControllerConfig {
+            mapping,
+            chords: vec![Chord {
+                keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                button: KeyCode::BTN_MODE,
+                priority: DEFAULT_CHORD_PRIORITY,
+            }],
+            ..ControllerConfig::default()
+        };
+
+        Dispatcher::with_clock(&[config], PULSE, ManualClock::new())
+    }
+
+    #[test]
+    fn chord_keys_within_the_window_press_the_chord() {
+        let mut dispatcher = chord_dispatcher();
+
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert!(advance(&mut dispatcher, 10).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 1),
+            [button(KeyCode::BTN_MODE, 1)]
+        );
+
+        // Either key lets go of it, and the other's release goes nowhere
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 0),
+            [button(KeyCode::BTN_MODE, 0)]
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_K, 0).is_empty());
+        assert!(advance(&mut dispatcher, 100).is_empty());
+    }
+
+    #[test]
+    fn a_chord_key_alone_goes_through_when_the_window_ends() {
+        let mut dispatcher = chord_dispatcher();
+
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert!(advance(&mut dispatcher, DEFAULT_CHORD_WINDOW_MS - 1).is_empty());
+        assert_eq!(advance(&mut dispatcher, 1), [button(KeyCode::BTN_SOUTH, 1)]);
+
+        // Too late for the chord: K waits on its own and goes through alone
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_K, 1).is_empty());
+        assert_eq!(
+            advance(&mut dispatcher, DEFAULT_CHORD_WINDOW_MS),
+            [button(KeyCode::BTN_EAST, 1)]
+        );
+    }
+
+    #[test]
+    fn another_key_ends_the_chord_wait() {
+        let mut dispatcher = chord_dispatcher();
+
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_L, 1),
+            [button(KeyCode::BTN_SOUTH, 1), button(KeyCode::BTN_NORTH, 1)]
+        );
+        assert!(advance(&mut dispatcher, 100).is_empty());
+    }
+
+    #[test]
+    fn a_chord_key_released_early_is_a_plain_tap() {
+        let mut dispatcher = chord_dispatcher();
+
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 0),
+            [button(KeyCode::BTN_SOUTH, 1), button(KeyCode::BTN_SOUTH, 0)]
+        );
+        assert!(advance(&mut dispatcher, 100).is_empty());
+    }
+
+    #[test]
+    fn default_priorities_put_chords_before_layers_and_layers_before_single_keys() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.insert(KeyCode::KEY_LEFTSHIFT, Binding::Layer("alt".to_string()));
+        mapping.insert_layer_binding("alt", KeyCode::KEY_J, Binding::Button(KeyCode::BTN_WEST));
+        let config = ControllerConfig {
+            mapping,
+            chords: vec![Chord {
+                keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                button: KeyCode::BTN_MODE,
+                priority: DEFAULT_CHORD_PRIORITY,
+            }],
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[config], PULSE, ManualClock::new());
+
+        assert!(
+            dispatcher
+                .handle_key(None, KeyCode::KEY_LEFTSHIFT, 1)
+                .is_empty()
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 1),
+            [button(KeyCode::BTN_MODE, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 0),
+            [button(KeyCode::BTN_MODE, 0)]
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 0).is_empty());
+
+        // Alone, J gets the layer's binding rather than its own
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert_eq!(
+            advance(&mut dispatcher, DEFAULT_CHORD_WINDOW_MS),
+            [button(KeyCode::BTN_WEST, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 0),
+            [button(KeyCode::BTN_WEST, 0)]
+        );
+    }
+
+    #[test]
+    fn released_axes_return_to_their_rest() {
+        let mut mapping = Mapping::new();
+        mapping.insert(
+            KeyCode::KEY_A,
+            Binding::Axis {
+                axis: AbsoluteAxisCode::ABS_Z,
+                value: TRIGGER_MAX,
+                ramp_ms: 0,
+                deadzone_pct: 0,
+            },
+        );
+        mapping.insert(
+            KeyCode::KEY_B,
+            Binding::Axis {
+                axis: AbsoluteAxisCode::ABS_X,
+                value: STICK_MIN,
+                ramp_ms: 0,
+                deadzone_pct: 0,
+            },
+        );
+        let config = ControllerConfig {
+            mapping,
+            axis_rests: HashMap::from([(AbsoluteAxisCode::ABS_Z, 100)]),
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[config], PULSE, ManualClock::new());
+
+        for (key, target, deflection, rest) in [
+            (KeyCode::KEY_A, AbsoluteAxisCode::ABS_Z, TRIGGER_MAX, 100),
+            (KeyCode::KEY_B, AbsoluteAxisCode::ABS_X, STICK_MIN, 0),
+        ] {
+            assert_eq!(
+                dispatcher.handle_key(None, key, 1),
+                [axis(target, deflection)]
+            );
+            assert_eq!(dispatcher.handle_key(None, key, 0), [axis(target, rest)]);
+        }
+    }
+
+    #[test]
+    fn taps_release_when_the_pulse_is_over() {
+        let mut dispatcher = dispatcher(&[(KeyCode::KEY_A, Binding::Tap(KeyCode::BTN_SOUTH))]);
+
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_A, 0).is_empty());
+        assert!(advance(&mut dispatcher, PULSE.as_millis() as u64 - 1).is_empty());
+        assert_eq!(advance(&mut dispatcher, 1), [button(KeyCode::BTN_SOUTH, 0)]);
+        assert!(advance(&mut dispatcher, 100).is_empty());
+    }
+
+    #[test]
+    fn turbo_presses_hz_times_a_second() {
+        let mut dispatcher = dispatcher(&[(
+            KeyCode::KEY_A,
+            Binding::Turbo {
+                button: KeyCode::BTN_SOUTH,
+                hz: 10,
+            },
+        )]);
+
+        let mut outputs = dispatcher.handle_key(None, KeyCode::KEY_A, 1);
+        for _ in 0..99 {
+            outputs.extend(advance(&mut dispatcher, 10));
+        }
+
+        let presses = outputs.iter().filter(|output| output.value == 1).count();
+        let releases = outputs.iter().filter(|output| output.value == 0).count();
+        assert_eq!((presses, releases), (10, 10));
+    }
+
+    #[test]
+    fn the_mouse_stick_uses_the_axis_scale_and_rest() {
+        let config = ControllerConfig {
+            mouse_stick: Some(MouseStick {
+                sensitivity: 1000.0,
+                decay_ms: 50,
+            }),
+            axis_scale: 0.5,
+            axis_rests: HashMap::from([(AbsoluteAxisCode::ABS_RX, 1000)]),
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[config], PULSE, ManualClock::new());
+
+        assert_eq!(
+            dispatcher.handle_motion(None, 4, -100),
+            [
+                axis(AbsoluteAxisCode::ABS_RX, 3000),
+                axis(AbsoluteAxisCode::ABS_RY, STICK_MIN / 2)
+            ]
+        );
+
+        // Back to rest once the mouse has been still for the decay time
+        assert!(advance(&mut dispatcher, 49).is_empty());
+        assert_eq!(
+            advance(&mut dispatcher, 1),
+            [
+                axis(AbsoluteAxisCode::ABS_RX, 1000),
+                axis(AbsoluteAxisCode::ABS_RY, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn ramps_ease_linearly_to_the_deflection() {
+        let ramp = Duration::from_millis(100);
+        let at = |ms| ramp_value(0, 1000, Duration::from_millis(ms), ramp);
+
+        assert_eq!([at(0), at(10), at(50), at(99)], [0, 100, 500, 990]);
+        assert_eq!([at(100), at(1000)], [1000, 1000]);
+        assert_eq!(
+            ramp_value(-200, -1200, Duration::from_millis(50), ramp),
+            -700
+        );
+        assert_eq!(ramp_value(0, 1000, Duration::ZERO, Duration::ZERO), 1000);
+    }
+
+    #[test]
+    fn eased_axes_start_past_the_deadzone() {
+        let mut dispatcher = dispatcher(&[(
+            KeyCode::KEY_A,
+            Binding::Axis {
+                axis: AbsoluteAxisCode::ABS_X,
+                value: 10000,
+                ramp_ms: 100,
+                deadzone_pct: 20,
+            },
+        )]);
+
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_A, 1),
+            [axis(AbsoluteAxisCode::ABS_X, 2000)]
+        );
+        assert_eq!(
+            advance(&mut dispatcher, 50),
+            [axis(AbsoluteAxisCode::ABS_X, 6000)]
+        );
+        assert_eq!(
+            advance(&mut dispatcher, 50),
+            [axis(AbsoluteAxisCode::ABS_X, 10000)]
+        );
+        assert!(advance(&mut dispatcher, 50).is_empty());
+    }
+
+    /// D holds the D-pad right, and taps BTN_TR for 50 ms when pressed twice
+    /// within 250 ms.
+    fn dash_dispatcher() -> Dispatcher<ManualClock> {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_D, Binding::Button(KeyCode::BTN_DPAD_RIGHT));
+        let config = ControllerConfig {
+            mapping,
+            double_taps: vec![DoubleTap {
+                key: KeyCode::KEY_D,
+                button: KeyCode::BTN_TR,
+                window_ms: 250,
+                pulse_ms: 50,
+            }],
+            ..ControllerConfig::default()
+        };
+
+        Dispatcher::with_clock(&[config], PULSE, ManualClock::new())
+    }
+
+    /// Presses and releases D, returning what both did.
+    fn tap_d(dispatcher: &mut Dispatcher<ManualClock>) -> Vec<ControllerOutput> {
+        let mut outputs = dispatcher.handle_key(None, KeyCode::KEY_D, 1);
+        outputs.extend(dispatcher.handle_key(None, KeyCode::KEY_D, 0));
+        outputs
+    }
+
+    #[test]
+    fn a_quick_second_tap_dashes() {
+        let mut dispatcher = dash_dispatcher();
+        let right = |value| button(KeyCode::BTN_DPAD_RIGHT, value);
+
+        assert_eq!(tap_d(&mut dispatcher), [right(1), right(0)]);
+        advance(&mut dispatcher, 250);
+        assert_eq!(
+            tap_d(&mut dispatcher),
+            [right(1), button(KeyCode::BTN_TR, 1), right(0)]
+        );
+        assert!(advance(&mut dispatcher, 49).is_empty());
+        assert_eq!(advance(&mut dispatcher, 1), [button(KeyCode::BTN_TR, 0)]);
+
+        // The second tap of a dash doesn't start another
+        assert_eq!(tap_d(&mut dispatcher), [right(1), right(0)]);
+    }
+
+    #[test]
+    fn slow_taps_and_repeats_do_not_dash() {
+        let mut dispatcher = dash_dispatcher();
+
+        tap_d(&mut dispatcher);
+        advance(&mut dispatcher, 251);
+        assert!(!tap_d(&mut dispatcher).contains(&button(KeyCode::BTN_TR, 1)));
+
+        // A held key's autorepeat is no second press
+        advance(&mut dispatcher, 300);
+        dispatcher.handle_key(None, KeyCode::KEY_D, 1);
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_D, 2).is_empty());
+        assert!(advance(&mut dispatcher, 100).is_empty());
+    }
+
+    #[test]
+    fn only_eagain_and_eintr_are_transient() {
+        for kind in [io::ErrorKind::WouldBlock, io::ErrorKind::Interrupted] {
+            assert!(is_transient_io(&io::Error::from(kind)));
+            assert!(is_transient(&io::Error::from(kind).into()));
+        }
+        assert!(!is_transient_io(&io::Error::from_raw_os_error(
+            libc::ENODEV
+        )));
+        assert!(!is_transient_io(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        assert!(!is_transient(&anyhow::anyhow!("not an io error")));
+    }
+
+    #[test]
+    fn an_interrupted_emit_is_retried_and_the_loop_goes_on() {
+        let sink = FakeSink::default();
+        sink.0.lock().failures = vec![io::ErrorKind::Interrupted];
+        let frame = [(Target::Button(KeyCode::BTN_SOUTH), 1)];
+
+        emit_with_retry(&mut sink.clone(), &frame).unwrap();
+        assert_eq!(sink.0.lock().frames, 1);
+
+        // Failing twice drops the frame but keeps going
+        sink.0.lock().failures = vec![io::ErrorKind::WouldBlock, io::ErrorKind::Interrupted];
+        emit_with_retry(&mut sink.clone(), &frame).unwrap();
+        assert_eq!(sink.0.lock().frames, 1);
+
+        // Anything else ends the session
+        sink.0.lock().failures = vec![io::ErrorKind::NotFound];
+        assert!(emit_with_retry(&mut sink.clone(), &frame).is_err());
+    }
+
+    #[test]
+    fn frames_split_only_on_a_repeated_target() {
+        let on_second = |output: ControllerOutput| ControllerOutput {
+            controller: 1,
+            ..output
+        };
+        let outputs = [
+            button(KeyCode::BTN_SOUTH, 1),
+            axis(AbsoluteAxisCode::ABS_X, 100),
+            on_second(button(KeyCode::BTN_SOUTH, 1)),
+            button(KeyCode::BTN_SOUTH, 0),
+            button(KeyCode::BTN_EAST, 1),
+        ];
+        let south = Target::Button(KeyCode::BTN_SOUTH);
+
+        assert_eq!(
+            frames(&outputs, 3),
+            [
+                vec![
+                    vec![(south, 1), (Target::Axis(AbsoluteAxisCode::ABS_X), 100)],
+                    vec![(south, 0), (Target::Button(KeyCode::BTN_EAST), 1)],
+                ],
+                vec![vec![(south, 1)]],
+                vec![vec![]],
+            ]
+        );
+    }
+
+    #[test]
+    fn a_batch_is_one_write_per_frame_not_per_change() {
+        let sink = FakeSink::default();
+        let (emit_tx, emitter) = emitter(&sink);
+
+        // Four changes used to be four writes; together they are one
+        let batch = vec![
+            button(KeyCode::BTN_SOUTH, 1),
+            button(KeyCode::BTN_EAST, 1),
+            axis(AbsoluteAxisCode::ABS_X, STICK_MAX),
+            axis(AbsoluteAxisCode::ABS_Y, STICK_MIN),
+        ];
+        emit_tx.send(Emit::outputs(batch)).unwrap();
+
+        // A tap within the batch still takes two
+        let tap = vec![button(KeyCode::BTN_NORTH, 1), button(KeyCode::BTN_NORTH, 0)];
+        emit_tx.send(Emit::outputs(tap)).unwrap();
+
+        drop(emit_tx);
+        emitter.join().unwrap().unwrap();
+        assert_eq!(sink.0.lock().frames, 3);
+    }
+
+    #[test]
+    fn each_controller_reports_the_dpad_its_own_way() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_UP, Binding::Button(KeyCode::BTN_DPAD_UP));
+        mapping.insert(KeyCode::KEY_DOWN, Binding::Button(KeyCode::BTN_DPAD_DOWN));
+        let buttons = ControllerConfig {
+            mapping: mapping.clone(),
+            ..ControllerConfig::default()
+        };
+        let hat = ControllerConfig {
+            mapping,
+            dpad_mode: DpadMode::Hat,
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[buttons, hat], PULSE, ManualClock::new());
+        let on_hat = |value| ControllerOutput {
+            controller: 1,
+            ..axis(AbsoluteAxisCode::ABS_HAT0Y, value)
+        };
+
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_UP, 1),
+            [button(KeyCode::BTN_DPAD_UP, 1), on_hat(-1)]
+        );
+        // Opposite directions cancel on the hat, but are two buttons
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_DOWN, 1),
+            [button(KeyCode::BTN_DPAD_DOWN, 1), on_hat(0)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_UP, 0),
+            [button(KeyCode::BTN_DPAD_UP, 0), on_hat(1)]
+        );
+    }
+
+    #[test]
+    fn commands_carry_their_controller() {
+        let plain = ControllerConfig::default();
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_F1, Binding::Exec("notify-send hi".to_string()));
+        let scripted = ControllerConfig {
+            mapping,
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[plain, scripted], PULSE, ManualClock::new());
+
+        dispatcher.handle_key(None, KeyCode::KEY_F1, 1);
+        dispatcher.handle_key(None, KeyCode::KEY_F1, 0);
+        assert_eq!(
+            dispatcher.take_commands(),
+            [(1, "notify-send hi".to_string())]
+        );
+        assert!(dispatcher.take_commands().is_empty());
+    }
+
+    #[test]
+    fn emit_latency_keeps_the_mean_and_max() {
+        let mut latency = EmitLatency::default();
+        assert_eq!(latency.mean(), None);
+
+        for us in [100, 300, 200] {
+            latency.record(Duration::from_micros(us));
+        }
+        assert_eq!(latency.batches, 3);
+        assert_eq!(latency.mean(), Some(Duration::from_micros(200)));
+        assert_eq!(latency.max, Duration::from_micros(300));
+    }
+
+    #[test]
+    fn each_source_keeps_the_keys_its_controllers_leave_unbound() {
+        let keyboard = PathBuf::from("/dev/input/event3");
+        let macropad = PathBuf::from("/dev/input/event7");
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_A, Binding::Button(KeyCode::BTN_SOUTH));
+        let on_keyboard = ControllerConfig {
+            mapping,
+            sources: vec![keyboard.clone()],
+            ..ControllerConfig::default()
+        };
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_1, Binding::Button(KeyCode::BTN_EAST));
+        let on_macropad = ControllerConfig {
+            mapping,
+            sources: vec![macropad.clone()],
+            ..ControllerConfig::default()
+        };
+        let dispatcher =
+            Dispatcher::with_clock(&[on_keyboard, on_macropad], PULSE, ManualClock::new());
+
+        // The keyboard's 1 still types, and the macropad's A still reaches
+        // the system
+        assert_eq!(
+            dispatcher.keys_bound_for(&keyboard),
+            HashSet::from([KeyCode::KEY_A])
+        );
+        assert_eq!(
+            dispatcher.keys_bound_for(&macropad),
+            HashSet::from([KeyCode::KEY_1])
+        );
+        assert!(
+            dispatcher
+                .keys_bound_for(Path::new("/dev/input/event9"))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn keys_route_by_device_and_code() {
+        let keyboard = Path::new("/dev/input/event3");
+        let macropad = Path::new("/dev/input/event7");
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_1, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert_device_binding(macropad, KeyCode::KEY_2, Binding::Button(KeyCode::BTN_EAST));
+        mapping.insert_device_binding(
+            macropad,
+            KeyCode::KEY_1,
+            Binding::Button(KeyCode::BTN_NORTH),
+        );
+        let config = ControllerConfig {
+            mapping,
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[config], PULSE, ManualClock::new());
+
+        assert_eq!(
+            dispatcher.handle_key(Some(keyboard), KeyCode::KEY_1, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(Some(macropad), KeyCode::KEY_1, 1),
+            [button(KeyCode::BTN_NORTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(Some(macropad), KeyCode::KEY_1, 0),
+            [button(KeyCode::BTN_NORTH, 0)]
+        );
+
+        // The keyboard's 2 is left to type
+        assert!(
+            dispatcher
+                .handle_key(Some(keyboard), KeyCode::KEY_2, 1)
+                .is_empty()
+        );
+        assert_eq!(
+            dispatcher.keys_bound_for(keyboard),
+            HashSet::from([KeyCode::KEY_1])
+        );
+        assert_eq!(
+            dispatcher.keys_bound_for(macropad),
+            HashSet::from([KeyCode::KEY_1, KeyCode::KEY_2])
+        );
+    }
+
+    #[test]
+    fn chord_keys_let_through_keep_their_device() {
+        let macropad = Path::new("/dev/input/event7");
+        let mut dispatcher = chord_dispatcher();
+        dispatcher.controllers[0].mapping.insert_device_binding(
+            macropad,
+            KeyCode::KEY_J,
+            Binding::Button(KeyCode::BTN_WEST),
+        );
+
+        assert!(
+            dispatcher
+                .handle_key(Some(macropad), KeyCode::KEY_J, 1)
+                .is_empty()
+        );
+        assert_eq!(
+            advance(&mut dispatcher, DEFAULT_CHORD_WINDOW_MS),
+            [button(KeyCode::BTN_WEST, 1)]
+        );
+    }
+
+    #[test]
+    fn fixture_comments_leave_commands_whole() {
+        assert_eq!(
+            strip_fixture_comment("# inputmaster session fixture v1"),
+            ""
+        );
+        assert_eq!(
+            strip_fixture_comment("map 30 button 304  # KEY_A"),
+            "map 30 button 304"
+        );
+        assert_eq!(
+            strip_fixture_comment("input 0 30 1 # press"),
+            "input 0 30 1"
+        );
+        assert_eq!(
+            strip_fixture_comment("map 59 exec notify-send '#1 ready'  # KEY_F1"),
+            "map 59 exec notify-send '#1 ready'"
+        );
+        assert_eq!(
+            strip_fixture_comment("layer-map alt 59 exec echo a#b"),
+            "layer-map alt 59 exec echo a#b"
+        );
+        assert_eq!(
+            strip_fixture_comment("controller Player #1"),
+            "controller Player #1"
+        );
+        assert_eq!(
+            strip_fixture_comment("controller Player #1  # first pad"),
+            "controller Player #1"
+        );
+    }
+
+    /// A scratch fixture holding `contents`, named after `name`.
+    fn fixture_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "inputmaster-{}-{}.fixture",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn fixture_chord_window_is_range_checked() {
+        let path = fixture_file(
+            "chord-window",
+            &format!("controller Pad\nchord-window {}\n", MAX_CHORD_WINDOW_MS + 1),
+        );
+        assert!(load_fixture(&path).is_err());
+
+        fs::write(
+            &path,
+            format!("controller Pad\nchord-window {}\n", MAX_CHORD_WINDOW_MS),
+        )
+        .unwrap();
+        let fixture = load_fixture(&path).unwrap();
+        assert_eq!(fixture.controllers[0].chord_window_ms, MAX_CHORD_WINDOW_MS);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fixture_axis_rest_is_range_checked() {
+        let path = fixture_file("axis-rest", "controller Pad\naxis-rest 0 40000\n");
+        assert!(load_fixture(&path).is_err());
+
+        fs::write(&path, "controller Pad\naxis-rest 16 1\n").unwrap();
+        assert!(load_fixture(&path).is_err());
+
+        fs::write(&path, "controller Pad\naxis-rest 0 1000\n").unwrap();
+        let fixture = load_fixture(&path).unwrap();
+        assert_eq!(
+            fixture.controllers[0].axis_rests[&AbsoluteAxisCode::ABS_X],
+            1000
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_binding_ranked_above_a_chord_suppresses_it() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.set_priority(KeyCode::KEY_J, DEFAULT_CHORD_PRIORITY + 1);
+        let config = ControllerConfig {
+            mapping,
+            chords: vec![Chord {
+                keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                button: KeyCode::BTN_MODE,
+                priority: DEFAULT_CHORD_PRIORITY,
+            }],
+            ..ControllerConfig::default()
+        };
+        let mut dispatcher = Dispatcher::with_clock(&[config], PULSE, ManualClock::new());
+
+        // Neither key waits for the chord, and together they don't press it
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 1),
+            [button(KeyCode::BTN_EAST, 1)]
+        );
+        assert!(advance(&mut dispatcher, DEFAULT_CHORD_WINDOW_MS).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 0),
+            [button(KeyCode::BTN_EAST, 0)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 0),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+    }
+
+    #[test]
+    fn a_chord_ranked_above_a_binding_takes_its_key() {
+        let mut dispatcher = chord_dispatcher();
+        dispatcher.controllers[0]
+            .mapping
+            .set_priority(KeyCode::KEY_J, DEFAULT_CHORD_PRIORITY);
+
+        // A tie goes to the chord
+        assert!(dispatcher.handle_key(None, KeyCode::KEY_J, 1).is_empty());
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 1),
+            [button(KeyCode::BTN_MODE, 1)]
+        );
+    }
+
+    #[test]
+    fn a_binding_ranked_above_the_layer_wins_while_it_is_held() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.insert(KeyCode::KEY_LEFTSHIFT, Binding::Layer("alt".to_string()));
+        mapping.insert_layer_binding("alt", KeyCode::KEY_J, Binding::Button(KeyCode::BTN_WEST));
+        mapping.insert_layer_binding("alt", KeyCode::KEY_K, Binding::Button(KeyCode::BTN_NORTH));
+        mapping.set_priority(KeyCode::KEY_J, DEFAULT_LAYER_PRIORITY + 1);
+        let mut dispatcher = dispatcher(&[]);
+        dispatcher.controllers[0].mapping = mapping;
+
+        assert!(
+            dispatcher
+                .handle_key(None, KeyCode::KEY_LEFTSHIFT, 1)
+                .is_empty()
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_K, 1),
+            [button(KeyCode::BTN_NORTH, 1)]
+        );
+    }
+
+    #[test]
+    fn a_binding_ranked_above_a_shortcut_keeps_its_key() {
+        let mut dispatcher = dispatcher(&[(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH))]);
+        let slot = &mut dispatcher.controllers[0];
+        slot.shortcuts.push(Shortcut {
+            modifiers: vec![Modifier::Ctrl],
+            key: KeyCode::KEY_J,
+            button: KeyCode::BTN_MODE,
+            priority: DEFAULT_SHORTCUT_PRIORITY,
+        });
+        slot.mapping
+            .set_priority(KeyCode::KEY_J, DEFAULT_SHORTCUT_PRIORITY + 1);
+
+        assert!(
+            dispatcher
+                .handle_key(None, KeyCode::KEY_LEFTCTRL, 1)
+                .is_empty()
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 1),
+            [button(KeyCode::BTN_SOUTH, 1)]
+        );
+        assert_eq!(
+            dispatcher.handle_key(None, KeyCode::KEY_J, 0),
+            [button(KeyCode::BTN_SOUTH, 0)]
+        );
+    }
 
-        // Wait for the key to be captured
-        let key = rx.recv()?;
+    #[test]
+    fn fixture_priorities_are_read() {
+        let path = fixture_file(
+            "priorities",
+            "controller Pad\n\
+             map 36 button 304\n\
+             priority 36 25\n\
+             layer-map alt 36 button 308\n\
+             layer-priority alt 5\n\
+             chord 316 36 37\n\
+             chord-priority 15\n\
+             shortcut 316 36 Ctrl\n\
+             shortcut-priority 40\n",
+        );
 
-        // Join the thread
-        handle.join().expect("Failed to join key capture thread")?;
+        let fixture = load_fixture(&path).unwrap();
+        let controller = &fixture.controllers[0];
+        assert_eq!(controller.mapping.priority(&KeyCode::KEY_J), 25);
+        assert_eq!(controller.mapping.priority(&KeyCode::KEY_K), 0);
+        assert_eq!(controller.mapping.layer_priority("alt"), 5);
+        assert_eq!(controller.chords[0].priority, 15);
+        assert_eq!(controller.shortcuts[0].priority, 40);
 
-        Ok(key)
+        fs::remove_file(&path).unwrap();
     }
 }
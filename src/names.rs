@@ -0,0 +1,127 @@
+use evdev::{AbsoluteAxisCode, KeyCode};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Key codes with a name, up to the kernel's `KEY_MAX`.
+const KEY_COUNT: u16 = 0x300;
+
+/// Axis codes with a name, up to the kernel's `ABS_MAX`.
+const AXIS_COUNT: u16 = 0x40;
+
+/// Name of codes past the end of the tables, which no device sends.
+const UNKNOWN: &str = "UNKNOWN";
+
+static KEY_NAMES: OnceLock<Vec<String>> = OnceLock::new();
+static AXIS_NAMES: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Names of the codes below `count`, indexed by code. Codes evdev names
+/// get its name, e.g. `KEY_W`; the rest get their number, which the
+/// `*_from_name` lookups read back.
+fn name_table(
+    count: u16,
+    debug_name: impl Fn(u16) -> String,
+    named: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    (0..count)
+        .map(|code| {
+            let name = debug_name(code);
+            if named(&name) { name } else { code.to_string() }
+        })
+        .collect()
+}
+
+/// `key`'s name as evdev spells it, e.g. `KEY_W` or `BTN_DPAD_UP`. Codes
+/// with several names, like `KEY_ZOOM` and `KEY_FULL_SCREEN`, get evdev's
+/// first.
+pub fn key_name(key: KeyCode) -> &'static str {
+    let names = KEY_NAMES.get_or_init(|| {
+        name_table(
+            KEY_COUNT,
+            |code| format!("{:?}", KeyCode::new(code)),
+            |name| KeyCode::from_str(name).is_ok(),
+        )
+    });
+
+    names.get(key.0 as usize).map_or(UNKNOWN, String::as_str)
+}
+
+/// The key named `name`, as written by [`key_name`] or by hand: any of a
+/// code's names in any case, or the raw code.
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    let name = name.trim();
+    KeyCode::from_str(&name.to_ascii_uppercase())
+        .ok()
+        .or_else(|| name.parse().ok().map(KeyCode::new))
+}
+
+/// `axis`' name as evdev spells it, e.g. `ABS_X`.
+pub fn axis_name(axis: AbsoluteAxisCode) -> &'static str {
+    let names = AXIS_NAMES.get_or_init(|| {
+        name_table(
+            AXIS_COUNT,
+            |code| format!("{:?}", AbsoluteAxisCode(code)),
+            |name| AbsoluteAxisCode::from_str(name).is_ok(),
+        )
+    });
+
+    names.get(axis.0 as usize).map_or(UNKNOWN, String::as_str)
+}
+
+/// The axis named `name`, as written by [`axis_name`] or by hand, in any
+/// case, or the raw code.
+pub fn axis_from_name(name: &str) -> Option<AbsoluteAxisCode> {
+    let name = name.trim();
+    AbsoluteAxisCode::from_str(&name.to_ascii_uppercase())
+        .ok()
+        .or_else(|| name.parse().ok().map(AbsoluteAxisCode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{Capabilities, ControllerKind, DpadMode};
+
+    #[test]
+    fn controller_buttons_and_axes_round_trip() {
+        // A joystick declares every button, so it covers every layout too
+        for dpad_mode in [DpadMode::Buttons, DpadMode::Hat] {
+            let capabilities = Capabilities::new(ControllerKind::Joystick, dpad_mode);
+            for button in capabilities.buttons {
+                let name = key_name(button);
+                assert!(name.starts_with("BTN_"), "{} has no name", button.0);
+                assert_eq!(key_from_name(name), Some(button));
+            }
+            for capability in capabilities.axes {
+                let name = axis_name(capability.axis);
+                assert!(
+                    name.starts_with("ABS_"),
+                    "{} has no name",
+                    capability.axis.0
+                );
+                assert_eq!(axis_from_name(name), Some(capability.axis));
+            }
+        }
+    }
+
+    #[test]
+    fn every_code_round_trips() {
+        for code in 0..KEY_COUNT {
+            assert_eq!(
+                key_from_name(key_name(KeyCode::new(code))),
+                Some(KeyCode::new(code))
+            );
+        }
+        for code in 0..AXIS_COUNT {
+            let axis = AbsoluteAxisCode(code);
+            assert_eq!(axis_from_name(axis_name(axis)), Some(axis));
+        }
+    }
+
+    #[test]
+    fn names_read_in_any_case() {
+        assert_eq!(key_from_name(" key_w "), Some(KeyCode::KEY_W));
+        assert_eq!(key_from_name("17"), Some(KeyCode::KEY_W));
+        assert_eq!(axis_from_name("abs_rx"), Some(AbsoluteAxisCode::ABS_RX));
+        assert_eq!(key_from_name("KEY_NOPE"), None);
+    }
+}
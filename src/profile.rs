@@ -0,0 +1,727 @@
+use anyhow::{Context, Result};
+use evdev::{AbsoluteAxisCode, KeyCode};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::binding::{
+    Binding, Chord, DEFAULT_CHORD_PRIORITY, DEFAULT_DOUBLE_TAP_WINDOW_MS, DEFAULT_LAYER_PRIORITY,
+    DEFAULT_SHORTCUT_PRIORITY, DoubleTap, Mapping, Modifier, Shortcut, parse_modifiers,
+    valid_layer_name, valid_turbo_hz,
+};
+use crate::controller::{
+    Capabilities, ControllerConfig, ControllerKind, ControllerLayout, DEFAULT_CHORD_WINDOW_MS,
+    DpadMode, MouseStick, valid_axis_rest, valid_axis_scale, valid_chord_window,
+    valid_mouse_sensitivity,
+};
+use crate::mapping::{DEFAULT_PULSE_MS, SessionSettings};
+use crate::names::{axis_name, key_name};
+
+/// A saved session: the settings it ran with and its controllers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub global: SessionSettings,
+    pub controllers: Vec<ControllerConfig>,
+}
+
+// On-disk layout. Kept separate from the runtime types so the file format
+// doesn't change every time a struct gains a field.
+
+#[derive(Serialize, Deserialize)]
+struct ProfileFile {
+    #[serde(default)]
+    global: SessionSettings,
+    #[serde(default)]
+    controllers: Vec<ControllerEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControllerEntry {
+    name: String,
+    #[serde(default)]
+    kind: ControllerKind,
+    #[serde(default)]
+    layout: ControllerLayout,
+    #[serde(default)]
+    dpad_mode: DpadMode,
+    #[serde(default = "neutral_axis_scale")]
+    axis_scale: f32,
+    #[serde(default)]
+    normalize_diagonals: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    axis_rests: Vec<AxisRestEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mouse_stick: Option<MouseStick>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<PathBuf>,
+    #[serde(default)]
+    bindings: Vec<BindingEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<LayerEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    device_bindings: Vec<DeviceBindingsEntry>,
+    /// Priorities of keys' own bindings, where not the default.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    priorities: Vec<PriorityEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chords: Vec<ChordEntry>,
+    #[serde(default = "default_chord_window")]
+    chord_window_ms: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    double_taps: Vec<DoubleTapEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shortcuts: Vec<ShortcutEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LayerEntry {
+    name: String,
+    #[serde(default = "default_layer_priority")]
+    priority: i32,
+    #[serde(default)]
+    bindings: Vec<BindingEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceBindingsEntry {
+    /// Path of the source device whose keys these bindings are for.
+    device: PathBuf,
+    #[serde(default)]
+    bindings: Vec<BindingEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BindingEntry {
+    /// Raw evdev code of the source key.
+    key: u16,
+    /// Symbolic name of `key`, e.g. `KEY_A`, for people reading the file.
+    /// Ignored when loading; `key` is what counts.
+    #[serde(default)]
+    name: String,
+    /// The binding in its text form, e.g. `button 304`.
+    binding: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PriorityEntry {
+    /// Raw evdev code of the source key.
+    key: u16,
+    /// Symbolic name of `key`, for people reading the file. Ignored when
+    /// loading.
+    #[serde(default)]
+    name: String,
+    priority: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChordEntry {
+    /// Raw evdev codes of the source keys.
+    keys: Vec<u16>,
+    /// Raw evdev code of the controller button.
+    button: u16,
+    #[serde(default = "default_chord_priority")]
+    priority: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DoubleTapEntry {
+    /// Raw evdev code of the source key.
+    key: u16,
+    /// Raw evdev code of the controller button it taps.
+    button: u16,
+    #[serde(default = "default_double_tap_window")]
+    window_ms: u64,
+    #[serde(default = "default_double_tap_pulse")]
+    pulse_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShortcutEntry {
+    /// Names of the modifiers held, e.g. `Shift`.
+    modifiers: Vec<String>,
+    /// Raw evdev code of the source key.
+    key: u16,
+    /// Raw evdev code of the controller button it presses.
+    button: u16,
+    #[serde(default = "default_shortcut_priority")]
+    priority: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AxisRestEntry {
+    /// Raw evdev code of the axis.
+    axis: u16,
+    rest: i32,
+}
+
+pub fn neutral_axis_scale() -> f32 {
+    1.0
+}
+
+pub fn default_chord_window() -> u64 {
+    DEFAULT_CHORD_WINDOW_MS
+}
+
+pub fn default_double_tap_window() -> u64 {
+    DEFAULT_DOUBLE_TAP_WINDOW_MS
+}
+
+pub fn default_double_tap_pulse() -> u64 {
+    DEFAULT_PULSE_MS
+}
+
+pub fn default_layer_priority() -> i32 {
+    DEFAULT_LAYER_PRIORITY
+}
+
+pub fn default_chord_priority() -> i32 {
+    DEFAULT_CHORD_PRIORITY
+}
+
+pub fn default_shortcut_priority() -> i32 {
+    DEFAULT_SHORTCUT_PRIORITY
+}
+
+/// `bindings` as entries, sorted by key code.
+fn binding_entries<'a>(
+    bindings: impl Iterator<Item = (&'a KeyCode, &'a Binding)>,
+) -> Vec<BindingEntry> {
+    let mut entries: Vec<BindingEntry> = bindings
+        .map(|(key, binding)| BindingEntry {
+            key: key.0,
+            name: key_name(*key).to_string(),
+            binding: binding.to_string(),
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.key);
+
+    entries
+}
+
+/// Parses a saved binding, naming the key and controller if it's invalid.
+fn parse_binding(entry: &BindingEntry, controller: &str) -> Result<Binding> {
+    Binding::parse(&entry.binding).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Invalid binding \"{}\" for key {} in {}",
+            entry.binding,
+            entry.key,
+            controller
+        )
+    })
+}
+
+impl From<&ControllerConfig> for ControllerEntry {
+    fn from(config: &ControllerConfig) -> Self {
+        let bindings = binding_entries(config.mapping.iter());
+        let layers = config
+            .mapping
+            .layers()
+            .map(|(name, bindings)| LayerEntry {
+                name: name.to_string(),
+                priority: config.mapping.layer_priority(name),
+                bindings: binding_entries(bindings.iter()),
+            })
+            .collect();
+        let device_bindings = config
+            .mapping
+            .device_bindings()
+            .map(|(device, bindings)| DeviceBindingsEntry {
+                device: device.to_path_buf(),
+                bindings: binding_entries(bindings.iter()),
+            })
+            .collect();
+        let mut priorities: Vec<PriorityEntry> = config
+            .mapping
+            .priorities()
+            .map(|(key, priority)| PriorityEntry {
+                key: key.0,
+                name: key_name(key).to_string(),
+                priority,
+            })
+            .collect();
+        priorities.sort_by_key(|entry| entry.key);
+
+        let mut axis_rests: Vec<AxisRestEntry> = config
+            .axis_rests
+            .iter()
+            .map(|(axis, rest)| AxisRestEntry {
+                axis: axis.0,
+                rest: *rest,
+            })
+            .collect();
+        axis_rests.sort_by_key(|entry| entry.axis);
+
+        ControllerEntry {
+            name: config.name.clone(),
+            kind: config.kind,
+            layout: config.layout,
+            dpad_mode: config.dpad_mode,
+            axis_scale: config.axis_scale,
+            normalize_diagonals: config.normalize_diagonals,
+            axis_rests,
+            mouse_stick: config.mouse_stick,
+            sources: config.sources.clone(),
+            bindings,
+            layers,
+            device_bindings,
+            priorities,
+            chords: config
+                .chords
+                .iter()
+                .map(|chord| ChordEntry {
+                    keys: chord.keys.iter().map(|key| key.0).collect(),
+                    button: chord.button.0,
+                    priority: chord.priority,
+                })
+                .collect(),
+            chord_window_ms: config.chord_window_ms,
+            double_taps: config
+                .double_taps
+                .iter()
+                .map(|double_tap| DoubleTapEntry {
+                    key: double_tap.key.0,
+                    button: double_tap.button.0,
+                    window_ms: double_tap.window_ms,
+                    pulse_ms: double_tap.pulse_ms,
+                })
+                .collect(),
+            shortcuts: config
+                .shortcuts
+                .iter()
+                .map(|shortcut| ShortcutEntry {
+                    modifiers: shortcut.modifiers.iter().map(Modifier::to_string).collect(),
+                    key: shortcut.key.0,
+                    button: shortcut.button.0,
+                    priority: shortcut.priority,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<ControllerEntry> for ControllerConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: ControllerEntry) -> Result<Self> {
+        let mut mapping = Mapping::new();
+        for binding in &entry.bindings {
+            mapping.insert(
+                KeyCode::new(binding.key),
+                parse_binding(binding, &entry.name)?,
+            );
+        }
+        for layer in &entry.layers {
+            mapping.set_layer_priority(&layer.name, layer.priority);
+            for binding in &layer.bindings {
+                mapping.insert_layer_binding(
+                    &layer.name,
+                    KeyCode::new(binding.key),
+                    parse_binding(binding, &entry.name)?,
+                );
+            }
+        }
+        for device in &entry.device_bindings {
+            for binding in &device.bindings {
+                mapping.insert_device_binding(
+                    &device.device,
+                    KeyCode::new(binding.key),
+                    parse_binding(binding, &entry.name)?,
+                );
+            }
+        }
+        for priority in &entry.priorities {
+            mapping.set_priority(KeyCode::new(priority.key), priority.priority);
+        }
+
+        let shortcuts = entry
+            .shortcuts
+            .iter()
+            .map(|shortcut| {
+                let modifiers = parse_modifiers(shortcut.modifiers.iter().map(String::as_str))
+                    .with_context(|| {
+                        format!(
+                            "Invalid modifiers \"{}\" in {}",
+                            shortcut.modifiers.join("+"),
+                            entry.name
+                        )
+                    })?;
+                Ok(Shortcut {
+                    modifiers,
+                    key: KeyCode::new(shortcut.key),
+                    button: KeyCode::new(shortcut.button),
+                    priority: shortcut.priority,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let config = ControllerConfig {
+            name: entry.name,
+            mapping,
+            sources: entry.sources,
+            kind: entry.kind,
+            layout: entry.layout,
+            dpad_mode: entry.dpad_mode,
+            axis_scale: entry.axis_scale,
+            normalize_diagonals: entry.normalize_diagonals,
+            axis_rests: entry
+                .axis_rests
+                .iter()
+                .map(|rest| (AbsoluteAxisCode(rest.axis), rest.rest))
+                .collect(),
+            mouse_stick: entry.mouse_stick,
+            chords: entry
+                .chords
+                .iter()
+                .map(|chord| Chord {
+                    keys: chord.keys.iter().copied().map(KeyCode::new).collect(),
+                    button: KeyCode::new(chord.button),
+                    priority: chord.priority,
+                })
+                .collect(),
+            chord_window_ms: entry.chord_window_ms,
+            double_taps: entry
+                .double_taps
+                .iter()
+                .map(|double_tap| DoubleTap {
+                    key: KeyCode::new(double_tap.key),
+                    button: KeyCode::new(double_tap.button),
+                    window_ms: double_tap.window_ms,
+                    pulse_ms: double_tap.pulse_ms,
+                })
+                .collect(),
+            shortcuts,
+        };
+        validate_controller(&config)?;
+
+        Ok(config)
+    }
+}
+
+/// Checks the settings of a controller read from a file, which may have been
+/// edited by hand: the axis scale, mouse sensitivity, axis rests, turbo
+/// rates, layer names, chord sizes, double-tap durations and shortcut keys.
+pub fn validate_controller(config: &ControllerConfig) -> Result<()> {
+    if !valid_axis_scale(config.axis_scale) {
+        return Err(anyhow::anyhow!(
+            "Invalid axis scale {} for {}",
+            config.axis_scale,
+            config.name
+        ));
+    }
+
+    if let Some(mouse) = config.mouse_stick
+        && !valid_mouse_sensitivity(mouse.sensitivity)
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid mouse sensitivity {} for {}",
+            mouse.sensitivity,
+            config.name
+        ));
+    }
+
+    if !valid_chord_window(config.chord_window_ms) {
+        return Err(anyhow::anyhow!(
+            "Invalid chord window {} ms for {}",
+            config.chord_window_ms,
+            config.name
+        ));
+    }
+
+    let capabilities = Capabilities::new(config.kind, config.dpad_mode);
+    for (axis, rest) in &config.axis_rests {
+        if !valid_axis_rest(&capabilities, *axis, *rest) {
+            return Err(anyhow::anyhow!(
+                "Invalid rest {} for axis {} in {}",
+                rest,
+                axis_name(*axis),
+                config.name
+            ));
+        }
+    }
+
+    let layered = config
+        .mapping
+        .layers()
+        .flat_map(|(_, bindings)| bindings.iter());
+    let per_device = config
+        .mapping
+        .device_bindings()
+        .flat_map(|(_, bindings)| bindings.iter());
+    for (key, binding) in config.mapping.iter().chain(layered).chain(per_device) {
+        if let Binding::Turbo { hz, .. } = binding
+            && !valid_turbo_hz(*hz)
+        {
+            return Err(anyhow::anyhow!(
+                "Invalid turbo rate {} for {} in {}",
+                hz,
+                key_name(*key),
+                config.name
+            ));
+        }
+    }
+
+    for (name, _) in config.mapping.layers() {
+        if !valid_layer_name(name) {
+            return Err(anyhow::anyhow!(
+                "Invalid layer name \"{}\" in {}",
+                name,
+                config.name
+            ));
+        }
+    }
+
+    for chord in &config.chords {
+        if chord.keys.len() < 2 {
+            return Err(anyhow::anyhow!(
+                "Chord for button {} in {} needs at least two keys",
+                key_name(chord.button),
+                config.name
+            ));
+        }
+    }
+
+    for double_tap in &config.double_taps {
+        if double_tap.window_ms == 0 || double_tap.pulse_ms == 0 {
+            return Err(anyhow::anyhow!(
+                "Double-tap of {} in {} needs a window and pulse above 0 ms",
+                key_name(double_tap.key),
+                config.name
+            ));
+        }
+    }
+
+    for shortcut in &config.shortcuts {
+        if shortcut.modifiers.is_empty() || Modifier::of(shortcut.key).is_some() {
+            return Err(anyhow::anyhow!(
+                "Shortcut {} in {} needs a modifier and a key that isn't one",
+                shortcut,
+                config.name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes `profile` as TOML, with the session settings in a `global` table
+/// and one `[[controllers]]` table per controller.
+pub fn save_profile(path: &Path, profile: &Profile) -> Result<()> {
+    let file = ProfileFile {
+        global: profile.global.clone(),
+        controllers: profile
+            .controllers
+            .iter()
+            .map(ControllerEntry::from)
+            .collect(),
+    };
+
+    fs::write(path, toml::to_string(&file)?)?;
+
+    Ok(())
+}
+
+/// The bindings of the first controller in the profile at `path`, to use as
+/// the default mapping. `None` when there is no such file.
+pub fn load_default_mapping(path: &Path) -> Result<Option<Mapping>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let controller = load_profile(path)?
+        .controllers
+        .into_iter()
+        .next()
+        .with_context(|| format!("{} has no controllers", path.display()))?;
+
+    Ok(Some(controller.mapping))
+}
+
+/// Reads a profile written by [`save_profile`]. Missing settings fall back
+/// to their defaults.
+pub fn load_profile(path: &Path) -> Result<Profile> {
+    let contents = fs::read_to_string(path)?;
+    let file: ProfileFile =
+        toml::from_str(&contents).with_context(|| format!("Invalid profile {}", path.display()))?;
+
+    Ok(Profile {
+        global: file.global,
+        controllers: file
+            .controllers
+            .into_iter()
+            .map(ControllerConfig::try_from)
+            .collect::<Result<_>>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::MAX_CHORD_WINDOW_MS;
+    use crate::mapping::HangupAction;
+    use std::collections::HashMap;
+
+    #[test]
+    fn chord_window_is_bounded() {
+        let mut config = ControllerConfig {
+            chord_window_ms: MAX_CHORD_WINDOW_MS,
+            ..ControllerConfig::default()
+        };
+        assert!(validate_controller(&config).is_ok());
+
+        config.chord_window_ms = MAX_CHORD_WINDOW_MS + 1;
+        assert!(validate_controller(&config).is_err());
+    }
+
+    /// A scratch file for `name` that no other test or run uses.
+    fn scratch_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("inputmaster-{}-{}.toml", std::process::id(), name))
+    }
+
+    #[test]
+    fn profiles_round_trip_both_sections() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_W, Binding::Button(KeyCode::BTN_DPAD_UP));
+        mapping.insert(KeyCode::KEY_LEFTSHIFT, Binding::Layer("alt".to_string()));
+        mapping.insert_layer_binding("alt", KeyCode::KEY_W, Binding::Tap(KeyCode::BTN_NORTH));
+        let profile = Profile {
+            global: SessionSettings {
+                pulse_ms: 30,
+                tick_ms: 50,
+                forward_unmapped: false,
+                menu_key: 0,
+                stop_key: KeyCode::KEY_F12.0,
+                hangup: HangupAction::Reload,
+                hotbar: true,
+            },
+            controllers: vec![
+                ControllerConfig {
+                    name: "Pad".to_string(),
+                    mapping,
+                    dpad_mode: DpadMode::Hat,
+                    axis_scale: 0.5,
+                    axis_rests: HashMap::from([(AbsoluteAxisCode::ABS_Z, 10)]),
+                    mouse_stick: Some(MouseStick::default()),
+                    chord_window_ms: 40,
+                    ..ControllerConfig::default()
+                },
+                ControllerConfig {
+                    name: "Stick".to_string(),
+                    kind: ControllerKind::Joystick,
+                    layout: ControllerLayout::Switch,
+                    ..ControllerConfig::default()
+                },
+            ],
+        };
+
+        let path = scratch_path("round-trip");
+        save_profile(&path, &profile).unwrap();
+        let loaded = load_profile(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), profile);
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let path = scratch_path("defaults");
+        fs::write(&path, "[[controllers]]\nname = \"Pad\"\n").unwrap();
+        let loaded = load_profile(&path);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.global, SessionSettings::default());
+        assert_eq!(
+            loaded.controllers[0].chord_window_ms,
+            DEFAULT_CHORD_WINDOW_MS
+        );
+    }
+
+    #[test]
+    fn bindings_are_saved_with_key_names_and_loaded_by_code() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_W, Binding::Button(KeyCode::BTN_DPAD_UP));
+        let profile = Profile {
+            controllers: vec![ControllerConfig {
+                mapping,
+                ..ControllerConfig::default()
+            }],
+            ..Profile::default()
+        };
+
+        let path = scratch_path("key-names");
+        save_profile(&path, &profile).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("key = 17"));
+        assert!(saved.contains("name = \"KEY_W\""));
+
+        // The name is only for reading; a stale one changes nothing
+        fs::write(&path, saved.replace("KEY_W", "KEY_Q")).unwrap();
+        let loaded = load_profile(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), profile);
+    }
+
+    #[test]
+    fn bindings_for_one_device_round_trip() {
+        let macropad = PathBuf::from("/dev/input/by-id/usb-macropad-event-kbd");
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_1, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert_device_binding(
+            &macropad,
+            KeyCode::KEY_1,
+            Binding::Button(KeyCode::BTN_EAST),
+        );
+        let profile = Profile {
+            global: SessionSettings::default(),
+            controllers: vec![ControllerConfig {
+                name: "Pad".to_string(),
+                mapping,
+                sources: vec![PathBuf::from("/dev/input/event3"), macropad],
+                ..ControllerConfig::default()
+            }],
+        };
+
+        let path = scratch_path("per-device");
+        save_profile(&path, &profile).unwrap();
+        let loaded = load_profile(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), profile);
+    }
+
+    #[test]
+    fn priorities_round_trip() {
+        let mut mapping = Mapping::new();
+        mapping.insert(KeyCode::KEY_J, Binding::Button(KeyCode::BTN_SOUTH));
+        mapping.insert(KeyCode::KEY_K, Binding::Button(KeyCode::BTN_EAST));
+        mapping.set_priority(KeyCode::KEY_J, 25);
+        mapping.insert_layer_binding("alt", KeyCode::KEY_J, Binding::Button(KeyCode::BTN_WEST));
+        mapping.set_layer_priority("alt", 5);
+        let profile = Profile {
+            controllers: vec![ControllerConfig {
+                name: "Pad".to_string(),
+                mapping,
+                chords: vec![Chord {
+                    keys: vec![KeyCode::KEY_J, KeyCode::KEY_K],
+                    button: KeyCode::BTN_MODE,
+                    priority: 15,
+                }],
+                shortcuts: vec![Shortcut {
+                    modifiers: vec![Modifier::Ctrl],
+                    key: KeyCode::KEY_J,
+                    button: KeyCode::BTN_START,
+                    priority: 40,
+                }],
+                ..ControllerConfig::default()
+            }],
+            ..Profile::default()
+        };
+
+        let path = scratch_path("priorities");
+        save_profile(&path, &profile).unwrap();
+        let loaded = load_profile(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), profile);
+    }
+}
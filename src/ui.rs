@@ -3,33 +3,301 @@ use crossterm::{
     cursor::{Hide, MoveTo, Show},
     event::{self, Event, KeyCode as CtKeyCode, KeyEvent},
     execute,
-    style::{Color, Print, ResetColor, SetForegroundColor},
-    terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode},
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode, size},
 };
-use std::io::{Write, stdout};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write, stdin, stdout};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::controller::VirtualController;
-use crate::device::InputDevice;
-use crate::mapping::DeviceMapper;
+use crate::binding::{
+    Binding, Chord, DEFAULT_CHORD_PRIORITY, DEFAULT_DOUBLE_TAP_WINDOW_MS,
+    DEFAULT_SHORTCUT_PRIORITY, DEFAULT_TURBO_HZ, DoubleTap, MAX_TURBO_HZ, Modifier, Shortcut,
+    Target, valid_deadzone_pct, valid_layer_name, valid_turbo_hz,
+};
+use crate::cli::Options;
+use crate::controller::{
+    ControllerKind, ControllerLayout, DpadMode, MAX_CHORD_WINDOW_MS, VirtualController,
+    valid_axis_scale, valid_chord_window, valid_mouse_sensitivity,
+};
+use crate::device::{self, InputDevice};
+use crate::error::AppError;
+use crate::interop;
+use crate::mapping::{CaptureResult, Conflict, ControllerStatus, DeviceMapper, Dispatcher};
+use crate::names::{axis_name, key_name};
+
+/// How often the binding test looks for due taps and ramps when no key
+/// comes in.
+const TEST_POLL: Duration = Duration::from_millis(50);
+
+/// How long single-key prompts wait before offering to try again.
+const KEY_CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Smallest terminal the fixed-position screens are laid out for.
+const MIN_COLUMNS: u16 = 80;
+const MIN_ROWS: u16 = 24;
+
+/// Main menu entries; `show_main_menu` returns the 1-based position.
+const MAIN_MENU: [&str; 10] = [
+    "Create a new controller",
+    "List active controllers",
+    "Start mapping (begin using controllers)",
+    "Edit a controller",
+    "Remove a controller",
+    "Export last session as test fixture",
+    "Save profile",
+    "Load profile",
+    "Exit",
+    "Reorder controllers",
+];
+
+/// Row of the main menu's selection prompt, below the entries.
+const MENU_PROMPT_ROW: u16 = 4 + MAIN_MENU.len() as u16 + 1;
+
+/// Inputs listed by the training overlay.
+const TRAINING_HISTORY_LEN: usize = 10;
+
+/// Window the training overlay's inputs-per-second rate is counted over.
+const TRAINING_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Recent presses for the training overlay, as offsets into the session.
+#[derive(Default)]
+struct Training {
+    /// The last few presses, oldest first.
+    history: VecDeque<(evdev::KeyCode, Duration)>,
+    /// Presses within the rate window, oldest first.
+    window: VecDeque<Duration>,
+}
+
+impl Training {
+    fn record(&mut self, key: evdev::KeyCode, offset: Duration) {
+        // Offsets restart with each session
+        if self.history.back().is_some_and(|(_, last)| *last > offset) {
+            *self = Training::default();
+        }
+
+        self.history.push_back((key, offset));
+        if self.history.len() > TRAINING_HISTORY_LEN {
+            self.history.pop_front();
+        }
+
+        self.window.push_back(offset);
+        while self
+            .window
+            .front()
+            .is_some_and(|first| offset - *first >= TRAINING_RATE_WINDOW)
+        {
+            self.window.pop_front();
+        }
+    }
+
+    /// Presses per second over the rate window.
+    fn rate(&self) -> f64 {
+        self.window.len() as f64 / TRAINING_RATE_WINDOW.as_secs_f64()
+    }
+
+    /// The history, newest first, with the gap since the press before each.
+    fn lines(&self) -> Vec<String> {
+        let mut previous = None;
+        let mut lines: Vec<String> = self
+            .history
+            .iter()
+            .map(|(key, offset)| {
+                let line = match previous {
+                    Some(previous) => {
+                        format!(
+                            "{}  +{}ms",
+                            key_name(*key),
+                            (*offset - previous).as_millis()
+                        )
+                    }
+                    None => key_name(*key).to_string(),
+                };
+                previous = Some(*offset);
+                line
+            })
+            .collect();
+        lines.reverse();
+
+        lines
+    }
+}
+
+/// Whether the full-screen UI has taken over the terminal, for the panic
+/// hook, which has no [`UI`] to ask.
+static FULL_SCREEN: AtomicBool = AtomicBool::new(false);
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Hands the terminal back in a state the shell can use: out of raw mode
+/// with the cursor shown. Does nothing unless the full-screen UI holds it,
+/// so it is safe to call more than once.
+fn restore_terminal() {
+    if FULL_SCREEN.swap(false, Ordering::SeqCst) {
+        let _ = disable_raw_mode();
+        let _ = execute!(stdout(), Show, Print("\r\n"));
+    }
+}
 
 pub struct UI {
     stdout: std::io::Stdout,
+    /// Keep the menu on screen and append status below it rather than
+    /// clearing the terminal when mapping starts.
+    compact: bool,
+    /// Line-based mode: no raw mode or cursor movement, choices are typed
+    /// and confirmed with Enter. Used where the full-screen UI misbehaves.
+    simple: bool,
+    /// Suppress the banner and any "press a key" pauses that aren't needed.
+    quiet: bool,
+    /// Title shown at the top of each screen; empty in quiet mode.
+    banner: String,
+    /// Input statistics shown while mapping, when enabled.
+    training: Option<Training>,
 }
 
 impl UI {
-    pub fn new() -> Self {
-        UI { stdout: stdout() }
+    pub fn new(options: &Options) -> Self {
+        UI {
+            stdout: stdout(),
+            compact: options.compact,
+            simple: options.simple,
+            quiet: options.quiet,
+            banner: if options.quiet {
+                String::new()
+            } else {
+                options.banner.clone()
+            },
+            training: options.training.then(Training::default),
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Reads one line from stdin in simple mode, without the trailing newline.
+    fn read_line(&mut self) -> Result<String> {
+        self.stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin().lock().read_line(&mut line)? == 0 {
+            return Err(anyhow::anyhow!("Standard input closed"));
+        }
+
+        Ok(line.trim().to_string())
+    }
+
+    /// Reads a 1-based choice in simple mode. Returns `None` for an empty line.
+    fn read_choice(&mut self, count: usize) -> Result<Option<usize>> {
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(None);
+            }
+
+            match line.parse::<usize>() {
+                Ok(n) if (1..=count).contains(&n) => return Ok(Some(n - 1)),
+                _ => print!("Enter a number between 1 and {}: ", count),
+            }
+        }
+    }
+
+    /// Like [`UI::read_choice`], for several numbers separated by spaces or
+    /// commas. Duplicates are dropped and the order is kept.
+    fn read_choices(&mut self, count: usize) -> Result<Option<Vec<usize>>> {
+        'ask: loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(None);
+            }
+
+            let mut choices = Vec::new();
+            for part in line.split([' ', ',']).filter(|part| !part.is_empty()) {
+                match part.parse::<usize>() {
+                    Ok(n) if (1..=count).contains(&n) => {
+                        if !choices.contains(&(n - 1)) {
+                            choices.push(n - 1);
+                        }
+                    }
+                    _ => {
+                        print!("Enter numbers between 1 and {}: ", count);
+                        continue 'ask;
+                    }
+                }
+            }
+
+            return Ok(Some(choices));
+        }
+    }
+
+    /// Waits until the terminal is at least `MIN_COLUMNS` x `MIN_ROWS`,
+    /// showing a notice rather than drawing a garbled screen.
+    fn ensure_terminal_size(&mut self) -> Result<()> {
+        if self.simple {
+            return Ok(());
+        }
+
+        loop {
+            let (columns, rows) = size()?;
+            if columns >= MIN_COLUMNS && rows >= MIN_ROWS {
+                return Ok(());
+            }
+
+            execute!(
+                self.stdout,
+                Clear(ClearType::All),
+                MoveTo(0, 0),
+                SetForegroundColor(Color::Red),
+                Print("Terminal too small"),
+                ResetColor,
+                MoveTo(0, 1),
+                Print(format!(
+                    "{}x{}, need {}x{}",
+                    columns, rows, MIN_COLUMNS, MIN_ROWS
+                )),
+                MoveTo(0, 2),
+                Print("Resize the window to continue.")
+            )?;
+
+            // Any event, usually a resize, triggers another check
+            event::read()?;
+        }
     }
 
     pub fn init(&mut self) -> Result<()> {
+        if self.simple {
+            if !self.banner.is_empty() {
+                println!("{}", self.banner);
+            }
+            return Ok(());
+        }
+
+        // Restoring before the default hook runs keeps the panic message
+        // readable instead of staircased by raw mode. A panic on another
+        // thread takes the session down with it, as every thread is joined
+        // with `expect`.
+        PANIC_HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore_terminal();
+                previous(info);
+            }));
+        });
+
         enable_raw_mode()?;
+        FULL_SCREEN.store(true, Ordering::SeqCst);
+        self.ensure_terminal_size()?;
         execute!(
             self.stdout,
             Clear(ClearType::All),
             Hide,
             MoveTo(0, 0),
             SetForegroundColor(Color::Cyan),
-            Print("Keyboard to Controller Mapper"),
+            Print(&self.banner),
             ResetColor
         )?;
 
@@ -37,62 +305,64 @@ impl UI {
     }
 
     pub fn cleanup(&mut self) -> Result<()> {
+        if self.simple {
+            return Ok(());
+        }
+
+        FULL_SCREEN.store(false, Ordering::SeqCst);
         disable_raw_mode()?;
         execute!(self.stdout, Clear(ClearType::All), Show, MoveTo(0, 0))?;
 
         Ok(())
     }
 
-    pub fn show_devices(&mut self, devices: &[InputDevice]) -> Result<()> {
-        execute!(
-            self.stdout,
-            MoveTo(0, 2),
-            SetForegroundColor(Color::Yellow),
-            Print("Available Keyboard Devices:"),
-            ResetColor,
-            MoveTo(0, 3)
-        )?;
+    /// Lets the user pick the keyboard to map from. Returns `None` if they
+    /// back out with Escape, or an empty line in simple mode.
+    pub fn select_device(
+        &mut self,
+        devices: &[InputDevice],
+        last_used: Option<usize>,
+    ) -> Result<Option<usize>> {
+        let items = device_items(devices, last_used);
 
-        for (i, device) in devices.iter().enumerate() {
-            execute!(
-                self.stdout,
-                MoveTo(2, 5 + i as u16),
-                Print(format!(
-                    "{}. {} ({})",
-                    i + 1,
-                    device.name,
-                    device.path.display()
-                ))
-            )?;
-        }
+        self.select_from_list_at(
+            "Available Keyboard Devices:",
+            &items,
+            last_used.unwrap_or(0),
+        )
+    }
 
-        execute!(
-            self.stdout,
-            MoveTo(0, 5 + devices.len() as u16 + 2),
-            Print("Select a keyboard device (1-"),
-            Print(devices.len()),
-            Print("): ")
-        )?;
+    /// Lets the user pick one or more keyboards to map from, e.g. both
+    /// halves of a split keyboard. Returns an empty list if they back out.
+    pub fn select_devices(
+        &mut self,
+        devices: &[InputDevice],
+        last_used: Option<usize>,
+    ) -> Result<Vec<usize>> {
+        let items = device_items(devices, last_used);
 
-        Ok(())
+        Ok(self
+            .pick_from_list(
+                "Available Keyboard Devices:",
+                &items,
+                last_used.unwrap_or(0),
+                true,
+            )?
+            .unwrap_or_default())
     }
 
-    pub fn select_device(&mut self, devices: &[InputDevice]) -> Result<usize> {
-        loop {
-            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
-                if let CtKeyCode::Char(c) = code {
-                    if let Some(idx) = c.to_digit(10) {
-                        let idx = idx as usize;
-                        if idx >= 1 && idx <= devices.len() {
-                            return Ok(idx - 1);
-                        }
-                    }
+    pub fn prompt_yes_no(&mut self, question: &str) -> Result<bool> {
+        if self.simple {
+            loop {
+                print!("{} (y/n): ", question);
+                match self.read_line()?.as_str() {
+                    "y" | "Y" => return Ok(true),
+                    "n" | "N" => return Ok(false),
+                    _ => { /* Ask again */ }
                 }
             }
         }
-    }
 
-    pub fn prompt_yes_no(&mut self, question: &str) -> Result<bool> {
         execute!(
             self.stdout,
             Clear(ClearType::All),
@@ -111,11 +381,231 @@ impl UI {
         }
     }
 
+    /// Reads a line of text typed by the user. Returns `None` on Escape.
+    pub fn prompt_input(&mut self, question: &str) -> Result<Option<String>> {
+        if self.simple {
+            print!("{} ", question);
+            let line = self.read_line()?;
+            return Ok((!line.is_empty()).then_some(line));
+        }
+
+        let mut input = String::new();
+
+        loop {
+            execute!(
+                self.stdout,
+                Clear(ClearType::All),
+                MoveTo(2, 2),
+                Print(format!("{} {}", question, input))
+            )?;
+
+            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+                match code {
+                    CtKeyCode::Char(c) => input.push(c),
+                    CtKeyCode::Backspace => {
+                        input.pop();
+                    }
+                    CtKeyCode::Enter => return Ok(Some(input.trim().to_string())),
+                    CtKeyCode::Esc => return Ok(None),
+                    _ => { /* Ignore other keys */ }
+                }
+            }
+        }
+    }
+
+    /// Asks for a file to write, turning the answer into a path with
+    /// `resolve`. If it already exists the user confirms the overwrite or
+    /// enters another name. Returns `None` when cancelled.
+    pub fn prompt_save_path(
+        &mut self,
+        question: &str,
+        resolve: fn(&str) -> Result<PathBuf, AppError>,
+    ) -> Result<Option<PathBuf>> {
+        loop {
+            let Some(input) = self.prompt_input(question)? else {
+                return Ok(None);
+            };
+            if input.is_empty() {
+                return Ok(None);
+            }
+
+            let path = match resolve(&input) {
+                Ok(path) => path,
+                Err(e) => {
+                    self.prompt_yes_no(&format!("{}. Continue?", e))?;
+                    return Ok(None);
+                }
+            };
+            if !path.exists()
+                || self
+                    .prompt_yes_no(&format!("{} already exists. Overwrite it?", path.display()))?
+            {
+                return Ok(Some(path));
+            }
+        }
+    }
+
+    /// Prompts for and captures one key straight from the evdev device. If
+    /// no key comes within [`KEY_CAPTURE_TIMEOUT`] the user can try again;
+    /// `None` means they gave up.
+    fn capture_single_key(
+        &mut self,
+        prompt: &str,
+        mapper: &mut DeviceMapper,
+    ) -> Result<Option<evdev::KeyCode>> {
+        loop {
+            let key = if self.simple {
+                print!("{}", prompt);
+                self.stdout.flush()?;
+                let key = mapper.capture_key(Some(KEY_CAPTURE_TIMEOUT))?;
+                match key {
+                    Some(key) => println!("{}", key_name(key)),
+                    None => println!(),
+                }
+                key
+            } else {
+                execute!(
+                    self.stdout,
+                    Clear(ClearType::All),
+                    MoveTo(2, 2),
+                    Print(prompt)
+                )?;
+                self.stdout.flush()?;
+
+                // Temporarily disable raw mode to allow direct evdev input
+                disable_raw_mode()?;
+                let key = mapper.capture_key(Some(KEY_CAPTURE_TIMEOUT));
+                enable_raw_mode()?;
+                key?
+            };
+
+            if key.is_some() || !self.prompt_yes_no("No key pressed. Try again?")? {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Captures a key for `binding` on controller `index`, like
+    /// [`UI::capture_single_key`]. A key the controller already binds to
+    /// something else is only taken once the user agrees to replace that
+    /// binding; otherwise they press another.
+    fn capture_key_to_bind(
+        &mut self,
+        prompt: &str,
+        mapper: &mut DeviceMapper,
+        index: usize,
+        binding: &Binding,
+    ) -> Result<Option<evdev::KeyCode>> {
+        loop {
+            let Some(key) = self.capture_single_key(prompt, mapper)? else {
+                return Ok(None);
+            };
+            let Some(warning) = key_conflict(&mapper.controllers[index], key, binding) else {
+                return Ok(Some(key));
+            };
+            if self.prompt_yes_no(&format!("{}. Reassign it?", warning))? {
+                return Ok(Some(key));
+            }
+        }
+    }
+
+    /// Captures a key for `button` while mapping `controller`. A key already
+    /// bound to something else on the controller must be pressed a second
+    /// time to take it over; pressing a different key tries that one
+    /// instead. In the full-screen UI the warning goes on `row`.
+    fn capture_key_for(
+        &mut self,
+        controller: &VirtualController,
+        button: evdev::KeyCode,
+        mapper: &mut DeviceMapper,
+        row: u16,
+    ) -> Result<CaptureResult> {
+        let mut key = match mapper.capture_mapping_key()? {
+            CaptureResult::Key(key) => key,
+            other => return Ok(other),
+        };
+
+        loop {
+            let Some(warning) = key_conflict(controller, key, &Binding::Button(button)) else {
+                return Ok(CaptureResult::Key(key));
+            };
+
+            let warning = format!("{}; press it again to reassign", warning);
+            if self.simple {
+                print!("\n{}: ", warning);
+                self.stdout.flush()?;
+            } else {
+                execute!(
+                    self.stdout,
+                    MoveTo(40, row),
+                    Clear(ClearType::UntilNewLine),
+                    SetForegroundColor(Color::Yellow),
+                    Print(warning),
+                    ResetColor
+                )?;
+            }
+
+            let next = match mapper.capture_mapping_key()? {
+                CaptureResult::Key(next) => next,
+                other => return Ok(other),
+            };
+            if next == key {
+                return Ok(CaptureResult::Key(key));
+            }
+            key = next;
+        }
+    }
+
+    /// Prompts for a key for each button of `controller`. `order` lists the
+    /// buttons to ask for first; `None` keeps the full default order.
+    /// Returns `false` if the user cancelled, leaving the bindings as they
+    /// were before.
     pub fn map_controller_buttons(
         &mut self,
         controller: &mut VirtualController,
         mapper: &mut DeviceMapper,
-    ) -> Result<()> {
+        order: Option<&[evdev::KeyCode]>,
+    ) -> Result<bool> {
+        let buttons_to_map = match order {
+            Some(order) => controller.buttons_in_order(order),
+            None => controller.get_available_button_mappings(),
+        };
+        let before = controller.key_mapping.read().clone();
+
+        // The key bound to each button so far, so going back can undo it
+        let mut mapped: Vec<Option<evdev::KeyCode>> = Vec::new();
+
+        if self.simple {
+            println!("\nMapping for {}", controller.name);
+            println!("Escape leaves a button unbound, Backspace goes back one, Delete cancels.");
+            while let Some(&(button_code, button_name)) = buttons_to_map.get(mapped.len()) {
+                print!("Press a key to map to {}: ", button_name);
+                self.stdout.flush()?;
+
+                let result = self.capture_key_for(controller, button_code, mapper, 0)?;
+                match result {
+                    CaptureResult::Key(key_code) => println!("mapped to {}", key_name(key_code)),
+                    CaptureResult::Skip => println!("left unbound"),
+                    CaptureResult::Back => println!("going back"),
+                    CaptureResult::Cancel => {
+                        *controller.key_mapping.write() = before;
+                        print!("cancelled. Press Enter to continue.");
+                        self.read_line()?;
+                        return Ok(false);
+                    }
+                }
+
+                apply_capture(controller, &buttons_to_map, &mut mapped, result);
+            }
+
+            // The captured keys were also echoed to the terminal; this line
+            // swallows them so they don't leak into the next prompt
+            print!("Mapping complete! Press Enter to continue.");
+            self.read_line()?;
+
+            return Ok(true);
+        }
+
         execute!(
             self.stdout,
             Clear(ClearType::All),
@@ -124,20 +614,23 @@ impl UI {
             Print(format!("Mapping for {}", controller.name)),
             ResetColor,
             MoveTo(0, 2),
-            Print("Press keyboard keys to map to the following controller buttons:\n"),
-            Print("(Press the key on your keyboard when prompted)")
+            Print("Press keyboard keys to map to the following controller buttons:"),
+            MoveTo(0, 3),
+            Print("(Press the key on your keyboard when prompted. Escape leaves a button"),
+            MoveTo(0, 4),
+            Print("unbound, Backspace goes back one, Delete cancels.)")
         )?;
 
-        let buttons_to_map = VirtualController::get_available_button_mappings();
-
         // Temporarily disable raw mode to allow direct evdev input
         disable_raw_mode()?;
 
-        for (i, (button_code, button_name)) in buttons_to_map.iter().enumerate() {
+        while let Some(&(button_code, button_name)) = buttons_to_map.get(mapped.len()) {
+            let row = 6 + mapped.len() as u16;
+
             // Clear the line
             execute!(
                 self.stdout,
-                MoveTo(2, 5 + i as u16),
+                MoveTo(2, row),
                 Clear(ClearType::CurrentLine),
                 Print(format!("Press a key to map to {}: ", button_name))
             )?;
@@ -145,112 +638,1699 @@ impl UI {
             self.stdout.flush()?;
 
             // Capture key press from the keyboard
-            let key_code = mapper.capture_key()?;
+            let result = self.capture_key_for(controller, button_code, mapper, row)?;
+            if result == CaptureResult::Cancel {
+                *controller.key_mapping.write() = before;
+                enable_raw_mode()?;
+                return Ok(false);
+            }
 
+            let status = match result {
+                CaptureResult::Key(key_code) => format!("Mapped to {}", key_name(key_code)),
+                CaptureResult::Skip => "Left unbound".to_string(),
+                // The previous line is prompted again next
+                CaptureResult::Back | CaptureResult::Cancel => String::new(),
+            };
             execute!(
                 self.stdout,
-                MoveTo(40, 5 + i as u16),
-                Print(format!("Mapped to {:?}", key_code))
+                MoveTo(40, row),
+                Clear(ClearType::UntilNewLine),
+                Print(status)
             )?;
 
-            // Add the mapping
-            controller
-                .key_mapping
-                .write()
-                .insert(key_code, *button_code);
+            apply_capture(controller, &buttons_to_map, &mut mapped, result);
         }
 
         // Re-enable raw mode for the UI
         enable_raw_mode()?;
 
+        if self.quiet {
+            return Ok(true);
+        }
+
         execute!(
             self.stdout,
-            MoveTo(2, 5 + buttons_to_map.len() as u16 + 2),
+            MoveTo(2, 6 + buttons_to_map.len() as u16 + 2),
             Print("Mapping complete! Press any key to continue.")
         )?;
 
         // Wait for a key press
         event::read()?;
 
-        Ok(())
+        Ok(true)
     }
 
-    pub fn show_main_menu(&mut self) -> Result<u8> {
-        execute!(
-            self.stdout,
-            Clear(ClearType::All),
-            MoveTo(2, 2),
-            SetForegroundColor(Color::Cyan),
-            Print("Keyboard to Controller Mapper"),
-            ResetColor,
-            MoveTo(2, 4),
-            Print("1. Create a new controller"),
-            MoveTo(2, 5),
-            Print("2. List active controllers"),
-            MoveTo(2, 6),
-            Print("3. Start mapping (begin using controllers)"),
-            MoveTo(2, 7),
-            Print("4. Exit"),
-            MoveTo(2, 9),
-            Print("Select an option (1-4): ")
-        )?;
+    /// Shows `items` as a vertical list and lets the user move a highlight
+    /// with the arrow keys. Returns the chosen index, or `None` on Escape.
+    pub fn select_from_list(&mut self, title: &str, items: &[String]) -> Result<Option<usize>> {
+        self.select_from_list_at(title, items, 0)
+    }
+
+    /// [`UI::select_from_list`] with the highlight starting on `initial`.
+    fn select_from_list_at(
+        &mut self,
+        title: &str,
+        items: &[String],
+        initial: usize,
+    ) -> Result<Option<usize>> {
+        Ok(self
+            .pick_from_list(title, items, initial, false)?
+            .and_then(|picked| picked.first().copied()))
+    }
+
+    /// The list behind [`UI::select_from_list_at`]. With `multi`, Space
+    /// marks items and Enter returns the marked ones, or the highlighted
+    /// one if none are marked; without it Enter returns the highlighted one.
+    fn pick_from_list(
+        &mut self,
+        title: &str,
+        items: &[String],
+        initial: usize,
+        multi: bool,
+    ) -> Result<Option<Vec<usize>>> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+
+        if self.simple {
+            println!("\n{}", title);
+            for (i, item) in items.iter().enumerate() {
+                println!("  {}. {}", i + 1, item);
+            }
+
+            if multi {
+                print!("Choices, separated by spaces (empty to go back): ");
+                return self.read_choices(items.len());
+            }
+            print!("Choice (empty to go back): ");
+            return Ok(self.read_choice(items.len())?.map(|choice| vec![choice]));
+        }
+
+        let mut selected = initial.min(items.len() - 1);
+        let mut marked = vec![false; items.len()];
+        let mut first_visible = 0;
 
         loop {
+            self.ensure_terminal_size()?;
+
+            // Leave room for the title above and the help line below, and
+            // scroll the window so the selection stays on screen
+            let (cols, rows) = size()?;
+            let visible = (rows as usize).saturating_sub(7).max(1);
+            if selected < first_visible {
+                first_visible = selected;
+            } else if selected >= first_visible + visible {
+                first_visible = selected + 1 - visible;
+            }
+            let shown = items.len().saturating_sub(first_visible).min(visible);
+
+            execute!(
+                self.stdout,
+                Clear(ClearType::All),
+                MoveTo(2, 2),
+                SetForegroundColor(Color::Yellow),
+                Print(title),
+                ResetColor
+            )?;
+
+            for (row, (i, item)) in items
+                .iter()
+                .enumerate()
+                .skip(first_visible)
+                .take(shown)
+                .enumerate()
+            {
+                let marker = if i == selected { ">" } else { " " };
+                let line = match (multi, marked[i]) {
+                    (false, _) => format!("{} {}", marker, item),
+                    (true, true) => format!("{} [x] {}", marker, item),
+                    (true, false) => format!("{} [ ] {}", marker, item),
+                };
+                // Long lines, such as device names, would wrap into the next
+                let line = clip(&line, (cols as usize).saturating_sub(4));
+                execute!(self.stdout, MoveTo(2, 4 + row as u16), Print(line))?;
+            }
+
+            let select = if multi {
+                "Space to mark, Enter to select"
+            } else {
+                "Enter to select"
+            };
+            let help = if items.len() > visible {
+                format!(
+                    "Up/Down/PgUp/PgDn to move, {}, Esc to go back ({}-{} of {})",
+                    select,
+                    first_visible + 1,
+                    first_visible + shown,
+                    items.len()
+                )
+            } else {
+                format!("Up/Down to move, {}, Esc to go back", select)
+            };
+            execute!(self.stdout, MoveTo(2, 5 + shown as u16), Print(help))?;
+
             if let Event::Key(KeyEvent { code, .. }) = event::read()? {
-                if let CtKeyCode::Char(c) = code {
-                    if let Some(option) = c.to_digit(10) {
-                        if option >= 1 && option <= 4 {
-                            return Ok(option as u8);
+                match code {
+                    CtKeyCode::Up => {
+                        selected = selected.checked_sub(1).unwrap_or(items.len() - 1);
+                    }
+                    CtKeyCode::Down => selected = (selected + 1) % items.len(),
+                    CtKeyCode::PageUp => selected = selected.saturating_sub(visible),
+                    CtKeyCode::PageDown => selected = (selected + visible).min(items.len() - 1),
+                    CtKeyCode::Char(' ') if multi => marked[selected] = !marked[selected],
+                    CtKeyCode::Enter => {
+                        let picked: Vec<usize> = (0..items.len()).filter(|&i| marked[i]).collect();
+                        if picked.is_empty() {
+                            return Ok(Some(vec![selected]));
                         }
+                        return Ok(Some(picked));
                     }
+                    CtKeyCode::Esc => return Ok(None),
+                    _ => { /* Ignore other keys */ }
                 }
             }
         }
     }
 
-    pub fn list_controllers(&mut self, controllers: &[VirtualController]) -> Result<()> {
-        execute!(
-            self.stdout,
-            Clear(ClearType::All),
-            MoveTo(2, 2),
-            SetForegroundColor(Color::Yellow),
-            Print("Active Controllers:"),
-            ResetColor
-        )?;
+    pub fn select_controller(
+        &mut self,
+        controllers: &[VirtualController],
+    ) -> Result<Option<usize>> {
+        let names: Vec<String> = controllers.iter().map(|c| c.name.clone()).collect();
+        self.select_from_list("Select a controller:", &names)
+    }
 
-        if controllers.is_empty() {
-            execute!(
-                self.stdout,
-                MoveTo(2, 4),
-                Print("No controllers created yet.")
-            )?;
-        } else {
-            for (i, controller) in controllers.iter().enumerate() {
-                execute!(
-                    self.stdout,
-                    MoveTo(2, 4 + i as u16),
-                    Print(format!(
-                        "{}. {} ({} keys mapped)",
-                        i + 1,
-                        controller.name,
-                        controller.key_mapping.read().len()
-                    ))
-                )?;
-            }
-        }
+    /// Lets the user pick the button labels for a new controller.
+    pub fn select_layout(&mut self) -> Result<Option<ControllerLayout>> {
+        let names: Vec<String> = ControllerLayout::ALL
+            .iter()
+            .map(|layout| layout.to_string())
+            .collect();
 
-        execute!(
-            self.stdout,
-            MoveTo(2, 6 + controllers.len() as u16),
-            Print("Press any key to continue...")
-        )?;
+        Ok(self
+            .select_from_list("Button layout:", &names)?
+            .map(|idx| ControllerLayout::ALL[idx]))
+    }
 
-        event::read()?;
+    /// Lets the user pick one of the controller's buttons, showing the keys
+    /// currently bound to each.
+    fn select_button(
+        &mut self,
+        title: &str,
+        controller: &VirtualController,
+    ) -> Result<Option<evdev::KeyCode>> {
+        let buttons = controller.get_available_button_mappings();
+        let mapping = controller.key_mapping.read().clone();
 
-        Ok(())
+        let items: Vec<String> = buttons
+            .iter()
+            .map(|(button, name)| {
+                let keys: Vec<String> = mapping
+                    .keys_for_button(*button)
+                    .iter()
+                    .map(|key| key_name(*key).to_string())
+                    .collect();
+
+                if keys.is_empty() {
+                    format!("{:<16} (unbound)", name)
+                } else {
+                    format!("{:<16} {}", name, keys.join(", "))
+                }
+            })
+            .collect();
+
+        Ok(self
+            .select_from_list(title, &items)?
+            .map(|idx| buttons[idx].0))
     }
 
-    pub fn show_mapping_active(&mut self) -> Result<()> {
+    pub fn edit_controller(&mut self, mapper: &mut DeviceMapper, index: usize) -> Result<()> {
+        let options = vec![
+            "Swap two bindings".to_string(),
+            "Add axis toggle".to_string(),
+            "Add tap binding".to_string(),
+            "Add sticky key".to_string(),
+            "Reassign source device".to_string(),
+            "Add source device".to_string(),
+            "Switch D-pad mode (buttons/hat)".to_string(),
+            "Switch controller type (gamepad/joystick)".to_string(),
+            "Import bindings from a Steam Input config".to_string(),
+            "Test rumble".to_string(),
+            "Set axis scale".to_string(),
+            "Add axis cycle".to_string(),
+            "Rebind or clear a button".to_string(),
+            "Configure turbo".to_string(),
+            "Add button combo".to_string(),
+            "Drive the right stick with the mouse".to_string(),
+            "Add held axis key".to_string(),
+            "Add half-press key".to_string(),
+            "Create a layer".to_string(),
+            "Bind a button in a layer".to_string(),
+            "Add key chord".to_string(),
+            "Set chord window".to_string(),
+            "Add double-tap (e.g. dash)".to_string(),
+            "Switch on or off while mapping".to_string(),
+            "Remap a gamepad's buttons".to_string(),
+            "Round stick diagonals (on/off)".to_string(),
+            "Add modifier shortcut (e.g. Shift+1)".to_string(),
+            "Test bindings (nothing is sent)".to_string(),
+            "Bind a button for one source device only".to_string(),
+            "Back".to_string(),
+        ];
+
+        loop {
+            let controller = &mapper.controllers[index];
+            let mut title = format!(
+                "Editing {} ({} bound keys)",
+                controller.name,
+                controller.mapping_count()
+            );
+            if !controller.enabled.load(Ordering::Relaxed) {
+                title.push_str(" (off while mapping)");
+            }
+
+            match self.select_from_list(&title, &options)? {
+                Some(0) => {
+                    let controller = &mut mapper.controllers[index];
+
+                    let Some(first) = self.select_button("Select the first button:", controller)?
+                    else {
+                        continue;
+                    };
+                    let Some(second) =
+                        self.select_button("Select the button to swap with:", controller)?
+                    else {
+                        continue;
+                    };
+
+                    controller.swap_bindings(first, second);
+                }
+                Some(1) => {
+                    let targets = mapper.controllers[index].get_available_axis_targets();
+                    let items: Vec<String> = targets
+                        .iter()
+                        .map(|(_, _, name)| name.to_string())
+                        .collect();
+
+                    let Some(choice) =
+                        self.select_from_list("Select the axis direction to latch:", &items)?
+                    else {
+                        continue;
+                    };
+                    let (axis, value, name) = targets[choice];
+
+                    let binding = Binding::AxisToggle { axis, value };
+                    let Some(key) = self.capture_key_to_bind(
+                        &format!("Press a key to toggle {}: ", name),
+                        mapper,
+                        index,
+                        &binding,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, binding);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(2) => {
+                    let Some(button) = self
+                        .select_button("Select the button to tap:", &mapper.controllers[index])?
+                    else {
+                        continue;
+                    };
+
+                    let Some(key) = self.capture_key_to_bind(
+                        &format!("Press a key to tap {}: ", key_name(button)),
+                        mapper,
+                        index,
+                        &Binding::Tap(button),
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, Binding::Tap(button));
+                    mapper.refresh_mapped_keys();
+                }
+                Some(3) => {
+                    let Some(key) = self.capture_key_to_bind(
+                        "Press the key that makes the next button stick: ",
+                        mapper,
+                        index,
+                        &Binding::Sticky,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, Binding::Sticky);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(4) => {
+                    let mut devices = device::discover_sources();
+
+                    let mut items = vec!["All source devices".to_string()];
+                    items.extend(devices.iter().map(describe_device));
+
+                    let Some(choice) =
+                        self.select_from_list("Select the source device:", &items)?
+                    else {
+                        continue;
+                    };
+                    let device = choice.checked_sub(1).map(|idx| devices.swap_remove(idx));
+
+                    if let Err(e) = mapper.reassign_source(index, device) {
+                        self.prompt_yes_no(&format!(
+                            "Failed to reassign source: {}. Continue?",
+                            e
+                        ))?;
+                    }
+                }
+                Some(5) => {
+                    let mut devices = device::discover_sources();
+                    let items: Vec<String> = devices.iter().map(describe_device).collect();
+
+                    let Some(choice) =
+                        self.select_from_list("Select a source device to add:", &items)?
+                    else {
+                        continue;
+                    };
+
+                    if let Err(e) = mapper.add_source(index, devices.swap_remove(choice)) {
+                        self.prompt_yes_no(&format!("Failed to add source: {}. Continue?", e))?;
+                    }
+                }
+                Some(6) => {
+                    let controller = &mut mapper.controllers[index];
+                    let mode = match controller.dpad_mode {
+                        DpadMode::Buttons => DpadMode::Hat,
+                        DpadMode::Hat => DpadMode::Buttons,
+                    };
+
+                    let message = match controller.set_dpad_mode(mode) {
+                        Ok(()) => format!("D-pad now reported as {}. Continue?", mode),
+                        Err(e) => format!("Failed to switch D-pad mode: {}. Continue?", e),
+                    };
+                    self.prompt_yes_no(&message)?;
+                }
+                Some(7) => {
+                    let controller = &mut mapper.controllers[index];
+                    let kind = match controller.kind {
+                        ControllerKind::Gamepad => ControllerKind::Joystick,
+                        ControllerKind::Joystick => ControllerKind::Gamepad,
+                    };
+
+                    let message = match controller.set_kind(kind) {
+                        Ok(()) => format!(
+                            "{} is now a {}.{} Continue?",
+                            controller.name,
+                            kind,
+                            describe_unsupported(std::slice::from_ref(controller))
+                        ),
+                        Err(e) => format!("Failed to switch controller type: {}. Continue?", e),
+                    };
+                    self.prompt_yes_no(&message)?;
+                }
+                Some(8) => {
+                    let Some(path) = self.prompt_input("Steam Input .vdf file (Esc to cancel):")?
+                    else {
+                        continue;
+                    };
+
+                    let message = match interop::import_steam_vdf(Path::new(&path)) {
+                        Ok(bindings) => {
+                            let mut mapping = mapper.controllers[index].key_mapping.write();
+                            for (key, button) in &bindings {
+                                mapping.insert(*key, Binding::Button(*button));
+                            }
+                            drop(mapping);
+                            mapper.refresh_mapped_keys();
+
+                            format!("Imported {} binding(s). Continue?", bindings.len())
+                        }
+                        Err(e) => format!("Failed to import {}: {}. Continue?", path, e),
+                    };
+                    self.prompt_yes_no(&message)?;
+                }
+                Some(9) => {
+                    let controller = &mut mapper.controllers[index];
+
+                    let message = match controller.test_rumble() {
+                        Ok(()) => format!(
+                            "{} accepted and played a rumble effect. Continue?",
+                            controller.name
+                        ),
+                        Err(e) => format!("Rumble test failed: {}. Continue?", e),
+                    };
+                    self.prompt_yes_no(&message)?;
+                }
+                Some(10) => {
+                    let question = format!(
+                        "Axis scale for {}, e.g. 0.5 for half deflection (currently {}):",
+                        mapper.controllers[index].name, mapper.controllers[index].axis_scale
+                    );
+                    let Some(input) = self.prompt_input(&question)? else {
+                        continue;
+                    };
+
+                    match input.trim().parse::<f32>() {
+                        Ok(scale) if valid_axis_scale(scale) => {
+                            mapper.controllers[index].axis_scale = scale;
+                        }
+                        _ => {
+                            self.prompt_yes_no(&format!(
+                                "\"{}\" is not a valid scale. Continue?",
+                                input.trim()
+                            ))?;
+                        }
+                    }
+                }
+                Some(11) => {
+                    let axes = mapper.controllers[index].capabilities().axes.clone();
+                    let items: Vec<String> = axes
+                        .iter()
+                        .map(|axis| {
+                            format!("{} ({} to {})", axis_name(axis.axis), axis.min, axis.max)
+                        })
+                        .collect();
+
+                    let Some(choice) =
+                        self.select_from_list("Select the axis to cycle:", &items)?
+                    else {
+                        continue;
+                    };
+                    let axis = axes[choice];
+
+                    let Some(input) = self.prompt_input(
+                        "Values to cycle through, separated by spaces (Esc to cancel):",
+                    )?
+                    else {
+                        continue;
+                    };
+                    let values: Option<Vec<i32>> = input
+                        .split_whitespace()
+                        .map(|value| value.parse().ok())
+                        .map(|value| value.filter(|v| (axis.min..=axis.max).contains(v)))
+                        .collect();
+                    let Some(values) = values.filter(|values| !values.is_empty()) else {
+                        self.prompt_yes_no(&format!(
+                            "Enter one or more whole numbers from {} to {}. Continue?",
+                            axis.min, axis.max
+                        ))?;
+                        continue;
+                    };
+
+                    let binding = Binding::AxisCycle {
+                        axis: axis.axis,
+                        values,
+                    };
+                    let Some(key) = self.capture_key_to_bind(
+                        &format!("Press a key to cycle {}: ", axis_name(axis.axis)),
+                        mapper,
+                        index,
+                        &binding,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, binding);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(12) => self.edit_controller_bindings(mapper, index)?,
+                Some(13) => self.configure_turbo(mapper, index)?,
+                Some(14) => {
+                    // Buttons are added one at a time until Escape
+                    let mut buttons = Vec::new();
+                    while let Some(button) = self.select_button(
+                        &format!(
+                            "Select button {} of the combo (Esc when done):",
+                            buttons.len() + 1
+                        ),
+                        &mapper.controllers[index],
+                    )? {
+                        if !buttons.contains(&button) {
+                            buttons.push(button);
+                        }
+                    }
+
+                    if buttons.len() < 2 {
+                        self.prompt_yes_no("A combo needs at least two buttons. Continue?")?;
+                        continue;
+                    }
+
+                    let combo = Binding::Combo(buttons);
+                    let name = describe_binding(&mapper.controllers[index], &combo);
+                    let Some(key) = self.capture_key_to_bind(
+                        &format!("Press a key for {}: ", name),
+                        mapper,
+                        index,
+                        &combo,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, combo);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(15) => self.configure_mouse_stick(mapper, index)?,
+                Some(16) => {
+                    let targets = mapper.controllers[index].get_available_axis_targets();
+                    let items: Vec<String> = targets
+                        .iter()
+                        .map(|(_, _, name)| name.to_string())
+                        .collect();
+
+                    let Some(choice) =
+                        self.select_from_list("Select the axis direction to hold:", &items)?
+                    else {
+                        continue;
+                    };
+                    let (axis, value, name) = targets[choice];
+
+                    let Some(input) = self.prompt_input(
+                        "Milliseconds to ease to full deflection (empty or 0 for none):",
+                    )?
+                    else {
+                        continue;
+                    };
+                    let ramp_ms = match input.trim() {
+                        "" => 0,
+                        input => match input.parse() {
+                            Ok(ramp_ms) => ramp_ms,
+                            Err(_) => {
+                                self.prompt_yes_no(&format!(
+                                    "\"{}\" is not a valid duration. Continue?",
+                                    input
+                                ))?;
+                                continue;
+                            }
+                        },
+                    };
+
+                    let deadzone_pct = if ramp_ms == 0 {
+                        0
+                    } else {
+                        let Some(input) = self.prompt_input(
+                            "Percent of the way to start easing from, past the game's deadzone \
+                             (empty or 0 for none):",
+                        )?
+                        else {
+                            continue;
+                        };
+                        match input.trim() {
+                            "" => 0,
+                            input => match input.parse() {
+                                Ok(pct) if valid_deadzone_pct(pct) => pct,
+                                _ => {
+                                    self.prompt_yes_no(&format!(
+                                        "\"{}\" is not a percentage from 0 to 100. Continue?",
+                                        input
+                                    ))?;
+                                    continue;
+                                }
+                            },
+                        }
+                    };
+
+                    let binding = Binding::Axis {
+                        axis,
+                        value,
+                        ramp_ms,
+                        deadzone_pct,
+                    };
+                    let Some(key) = self.capture_key_to_bind(
+                        &format!("Press a key to hold {}: ", name),
+                        mapper,
+                        index,
+                        &binding,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, binding);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(17) => {
+                    let Some(key) = self.capture_key_to_bind(
+                        "Press the key that holds axes at half deflection: ",
+                        mapper,
+                        index,
+                        &Binding::HalfPress,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, Binding::HalfPress);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(18) => {
+                    let Some(name) =
+                        self.prompt_input("Layer name (one word, e.g. \"combat\"):")?
+                    else {
+                        continue;
+                    };
+                    let name = name.trim().to_string();
+                    if !valid_layer_name(&name) {
+                        self.prompt_yes_no(&format!(
+                            "\"{}\" is not a valid layer name. Continue?",
+                            name
+                        ))?;
+                        continue;
+                    }
+
+                    let prompt = format!("Press the key that activates {} while held: ", name);
+                    let binding = Binding::Layer(name);
+                    let Some(key) = self.capture_key_to_bind(&prompt, mapper, index, &binding)?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert(key, binding);
+                    mapper.refresh_mapped_keys();
+                }
+                Some(19) => {
+                    let layers = mapper.controllers[index].key_mapping.read().layer_names();
+                    if layers.is_empty() {
+                        self.prompt_yes_no("No layers yet; create one first. Continue?")?;
+                        continue;
+                    }
+
+                    let Some(choice) = self.select_from_list("Select the layer:", &layers)? else {
+                        continue;
+                    };
+                    let layer = &layers[choice];
+
+                    let Some(button) = self.select_button(
+                        &format!("Select the button to bind in {}:", layer),
+                        &mapper.controllers[index],
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    let Some(key) = self.capture_single_key(
+                        &format!("Press the key for this button while {} is held: ", layer),
+                        mapper,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert_layer_binding(layer, key, Binding::Button(button));
+                    mapper.refresh_mapped_keys();
+                }
+                Some(20) => {
+                    let Some(button) = self.select_button(
+                        "Select the button the chord presses:",
+                        &mapper.controllers[index],
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    let mut keys = Vec::new();
+                    loop {
+                        let Some(key) = self.capture_single_key(
+                            &format!("Press key {} of the chord: ", keys.len() + 1),
+                            mapper,
+                        )?
+                        else {
+                            break;
+                        };
+                        if !keys.contains(&key) {
+                            keys.push(key);
+                        }
+                        if keys.len() >= 2
+                            && !self.prompt_yes_no("Add another key to the chord?")?
+                        {
+                            break;
+                        }
+                    }
+                    if keys.len() < 2 {
+                        continue;
+                    }
+
+                    mapper.controllers[index].chords.push(Chord {
+                        keys,
+                        button,
+                        priority: DEFAULT_CHORD_PRIORITY,
+                    });
+                    mapper.refresh_mapped_keys();
+                }
+                Some(21) => {
+                    let question = format!(
+                        "Milliseconds a chord waits for all its keys, up to {} (currently {}):",
+                        MAX_CHORD_WINDOW_MS, mapper.controllers[index].chord_window_ms
+                    );
+                    let Some(input) = self.prompt_input(&question)? else {
+                        continue;
+                    };
+
+                    match input.trim().parse() {
+                        Ok(window) if valid_chord_window(window) => {
+                            mapper.controllers[index].chord_window_ms = window
+                        }
+                        _ => {
+                            self.prompt_yes_no(&format!(
+                                "\"{}\" is not a valid duration. Continue?",
+                                input.trim()
+                            ))?;
+                        }
+                    }
+                }
+                Some(22) => {
+                    let Some(key) =
+                        self.capture_single_key("Press the key to double-tap: ", mapper)?
+                    else {
+                        continue;
+                    };
+                    let Some(button) = self.select_button(
+                        "Select the button a double-tap taps:",
+                        &mapper.controllers[index],
+                    )?
+                    else {
+                        continue;
+                    };
+                    let Some(window_ms) = self.prompt_millis(
+                        "Most milliseconds between the two presses",
+                        DEFAULT_DOUBLE_TAP_WINDOW_MS,
+                    )?
+                    else {
+                        continue;
+                    };
+                    let Some(pulse_ms) = self.prompt_millis(
+                        "Milliseconds the button is held",
+                        mapper.settings.pulse_ms,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    // One double-tap per key; adding another replaces it
+                    let double_taps = &mut mapper.controllers[index].double_taps;
+                    double_taps.retain(|double_tap| double_tap.key != key);
+                    double_taps.push(DoubleTap {
+                        key,
+                        button,
+                        window_ms,
+                        pulse_ms,
+                    });
+                    mapper.refresh_mapped_keys();
+                }
+                Some(23) => {
+                    mapper.controllers[index]
+                        .enabled
+                        .fetch_xor(true, Ordering::Relaxed);
+                }
+                Some(24) => {
+                    let mut gamepads: Vec<InputDevice> = device::discover_sources()
+                        .into_iter()
+                        .filter(|d| d.kind == Some(device::DeviceKind::Gamepad))
+                        .collect();
+                    if gamepads.is_empty() {
+                        self.prompt_yes_no("No gamepads found. Continue?")?;
+                        continue;
+                    }
+
+                    let items: Vec<String> = gamepads.iter().map(describe_device).collect();
+                    let Some(choice) =
+                        self.select_from_list("Select the gamepad to remap:", &items)?
+                    else {
+                        continue;
+                    };
+                    let gamepad = gamepads.swap_remove(choice);
+                    let buttons: Vec<evdev::KeyCode> = gamepad
+                        .device
+                        .supported_keys()
+                        .map(|keys| keys.iter().collect())
+                        .unwrap_or_default();
+
+                    // The gamepad becomes the only source, and is grabbed
+                    // so games see just the corrected controller
+                    if let Err(e) = mapper.reassign_source(index, Some(gamepad)) {
+                        self.prompt_yes_no(&format!(
+                            "Failed to use the gamepad: {}. Continue?",
+                            e
+                        ))?;
+                        continue;
+                    }
+                    let mirrored = mapper.controllers[index].mirror_buttons(buttons);
+                    mapper.refresh_mapped_keys();
+
+                    self.prompt_yes_no(&format!(
+                        "Passing {} gamepad buttons, the sticks and the triggers through \
+                         as they are; swap or rebind buttons to correct the layout. Continue?",
+                        mirrored
+                    ))?;
+                }
+                Some(25) => {
+                    let controller = &mut mapper.controllers[index];
+                    controller.normalize_diagonals = !controller.normalize_diagonals;
+
+                    let message = if controller.normalize_diagonals {
+                        "Diagonals now stay on the stick's circle. Continue?"
+                    } else {
+                        "Diagonals now reach the corners, for games expecting square gates. Continue?"
+                    };
+                    self.prompt_yes_no(message)?;
+                }
+                Some(26) => {
+                    let Some(button) = self.select_button(
+                        "Select the button the shortcut presses:",
+                        &mapper.controllers[index],
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    // The modifiers come one at a time, as capturing stops at
+                    // the first key down; the first other key ends them
+                    let mut modifiers = Vec::new();
+                    let key = loop {
+                        let Some(key) = self.capture_single_key(
+                            "Press a modifier (Ctrl, Shift, Alt or Meta), or the shortcut's key: ",
+                            mapper,
+                        )?
+                        else {
+                            break None;
+                        };
+                        match Modifier::of(key) {
+                            Some(modifier) => {
+                                if !modifiers.contains(&modifier) {
+                                    modifiers.push(modifier);
+                                }
+                            }
+                            None => break Some(key),
+                        }
+                    };
+                    let Some(key) = key else {
+                        continue;
+                    };
+                    if modifiers.is_empty() {
+                        self.prompt_yes_no(
+                            "A shortcut needs a modifier first; use Rebind for a plain key. Continue?",
+                        )?;
+                        continue;
+                    }
+                    modifiers.sort();
+
+                    let shortcut = Shortcut {
+                        modifiers,
+                        key,
+                        button,
+                        priority: DEFAULT_SHORTCUT_PRIORITY,
+                    };
+                    let message = format!(
+                        "{} now presses {}; the key alone and the modifiers work as before. \
+                         Continue?",
+                        shortcut,
+                        key_name(button)
+                    );
+                    // One shortcut per key and modifiers; adding another
+                    // replaces it
+                    let shortcuts = &mut mapper.controllers[index].shortcuts;
+                    shortcuts.retain(|other| {
+                        other.key != shortcut.key || other.modifiers != shortcut.modifiers
+                    });
+                    shortcuts.push(shortcut);
+                    self.prompt_yes_no(&message)?;
+                }
+                Some(27) => self.test_bindings(mapper, index)?,
+                Some(28) => {
+                    // The devices the controller reads, all when it has none
+                    let wanted = &mapper.controllers[index].sources;
+                    let (paths, items): (Vec<PathBuf>, Vec<String>) = mapper
+                        .sources
+                        .iter()
+                        .filter(|source| wanted.is_empty() || wanted.contains(&source.path))
+                        .map(|source| (source.path.clone(), describe_device(source)))
+                        .unzip();
+
+                    let Some(choice) =
+                        self.select_from_list("Select the device the key is on:", &items)?
+                    else {
+                        continue;
+                    };
+                    let Some(button) =
+                        self.select_button("Select the button:", &mapper.controllers[index])?
+                    else {
+                        continue;
+                    };
+                    let Some(key) = self.capture_single_key(
+                        &format!(
+                            "Press the key on {} for {}: ",
+                            items[choice],
+                            key_name(button)
+                        ),
+                        mapper,
+                    )?
+                    else {
+                        continue;
+                    };
+
+                    // The same key on the other devices keeps its binding
+                    mapper.controllers[index]
+                        .key_mapping
+                        .write()
+                        .insert_device_binding(&paths[choice], key, Binding::Button(button));
+                    mapper.refresh_mapped_keys();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Asks for a duration above 0 in milliseconds, with Enter taking
+    /// `default`. Returns `None` when cancelled or the answer is invalid,
+    /// after telling the user.
+    fn prompt_millis(&mut self, question: &str, default: u64) -> Result<Option<u64>> {
+        let Some(input) = self.prompt_input(&format!("{} (Enter for {}):", question, default))?
+        else {
+            return Ok(None);
+        };
+        if input.trim().is_empty() {
+            return Ok(Some(default));
+        }
+
+        match input.trim().parse() {
+            Ok(millis) if millis > 0 => Ok(Some(millis)),
+            _ => {
+                self.prompt_yes_no(&format!(
+                    "\"{}\" is not a valid duration. Continue?",
+                    input.trim()
+                ))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Shows what the controller's bindings would do as keys are pressed,
+    /// without grabbing the keyboard or sending anything. Bindings whose key
+    /// is down or whose output is away from rest are highlighted. Escape on
+    /// the watched keyboard ends it.
+    fn test_bindings(&mut self, mapper: &mut DeviceMapper, index: usize) -> Result<()> {
+        let controller = &mapper.controllers[index];
+        let config = controller.config();
+        let mut dispatcher = Dispatcher::new(
+            std::slice::from_ref(&config),
+            Duration::from_millis(mapper.settings.pulse_ms),
+        );
+        let bindings = controller.bindings_snapshot();
+
+        let mut watcher = mapper.watch_keys()?;
+        let mut held: HashSet<evdev::KeyCode> = HashSet::new();
+        let mut values: HashMap<Target, i32> = HashMap::new();
+        let mut redraw = true;
+
+        if self.simple {
+            println!(
+                "\nTesting {}. Press keys to see what they do; nothing is sent. Escape ends.",
+                controller.name
+            );
+        }
+
+        loop {
+            if redraw && !self.simple {
+                self.draw_binding_test(controller, &bindings, &held, &values, &dispatcher)?;
+            }
+
+            let timeout = dispatcher.next_deadline().map_or(TEST_POLL, |due| {
+                due.saturating_duration_since(Instant::now()).min(TEST_POLL)
+            });
+            let events = watcher.read(timeout)?;
+            if events
+                .iter()
+                .any(|(_, key, value)| *key == evdev::KeyCode::KEY_ESC && *value == 1)
+            {
+                break;
+            }
+            // Held keys change the highlight even when they drive nothing
+            redraw = !events.is_empty();
+
+            let mut outputs = Vec::new();
+            for (path, key, value) in events {
+                match value {
+                    0 => held.remove(&key),
+                    _ => held.insert(key),
+                };
+                outputs.extend(dispatcher.handle_key(Some(&path), key, value));
+            }
+            outputs.extend(dispatcher.fire_due());
+            // Commands are only ever shown
+            dispatcher.take_commands();
+
+            redraw |= !outputs.is_empty();
+            for output in outputs {
+                if self.simple {
+                    println!("  {} -> {}", describe_target(&output.target), output.value);
+                }
+                values.insert(output.target, output.value);
+            }
+        }
+
+        // The keys also went to the terminal; drop them so the menus don't
+        // act on them
+        if !self.simple {
+            while event::poll(Duration::ZERO)? {
+                event::read()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_binding_test(
+        &mut self,
+        controller: &VirtualController,
+        bindings: &[(evdev::KeyCode, Binding)],
+        held: &HashSet<evdev::KeyCode>,
+        values: &HashMap<Target, i32>,
+        dispatcher: &Dispatcher,
+    ) -> Result<()> {
+        let (_, rows) = size()?;
+
+        execute!(
+            self.stdout,
+            Clear(ClearType::All),
+            MoveTo(2, 1),
+            SetForegroundColor(Color::Yellow),
+            Print(format!("Testing {}", controller.name)),
+            ResetColor,
+            MoveTo(2, 2),
+            Print("Press keys to see what they do; nothing is sent. Escape ends.")
+        )?;
+
+        if bindings.is_empty() {
+            execute!(self.stdout, MoveTo(2, 4), Print("No bindings yet."))?;
+        }
+
+        let shown = bindings.len().min(rows.saturating_sub(5) as usize);
+        for (i, (key, binding)) in bindings.iter().take(shown).enumerate() {
+            let moved = binding.targets().iter().any(|target| {
+                values
+                    .get(target)
+                    .is_some_and(|value| *value != dispatcher.rest(0, *target))
+            });
+            let color = if held.contains(key) || moved {
+                Color::Green
+            } else {
+                Color::Reset
+            };
+
+            execute!(
+                self.stdout,
+                MoveTo(2, 4 + i as u16),
+                SetForegroundColor(color),
+                Print(format!(
+                    "{:<20} {}",
+                    key_name(*key).to_string(),
+                    describe_binding(controller, binding)
+                )),
+                ResetColor
+            )?;
+        }
+        if shown < bindings.len() {
+            execute!(
+                self.stdout,
+                MoveTo(2, 4 + shown as u16),
+                Print(format!("... and {} more", bindings.len() - shown))
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Asks for the sensitivity of the mouse-driven right stick, 0 turning it
+    /// off, and then for how long it holds after the mouse stops.
+    fn configure_mouse_stick(&mut self, mapper: &mut DeviceMapper, index: usize) -> Result<()> {
+        let current = mapper.controllers[index].mouse_stick;
+        let question = format!(
+            "Stick units per count of mouse motion for {}, 0 to turn off (currently {}):",
+            mapper.controllers[index].name,
+            current.map_or("off".to_string(), |mouse| mouse.sensitivity.to_string())
+        );
+        let Some(input) = self.prompt_input(&question)? else {
+            return Ok(());
+        };
+
+        let sensitivity = match input.trim().parse::<f32>() {
+            Ok(0.0) => {
+                mapper.controllers[index].mouse_stick = None;
+                return Ok(());
+            }
+            Ok(sensitivity) if valid_mouse_sensitivity(sensitivity) => sensitivity,
+            _ => {
+                self.prompt_yes_no(&format!(
+                    "\"{}\" is not a valid sensitivity. Continue?",
+                    input.trim()
+                ))?;
+                return Ok(());
+            }
+        };
+
+        let mut mouse = current.unwrap_or_default();
+        mouse.sensitivity = sensitivity;
+
+        let question = format!(
+            "Milliseconds the stick holds after the mouse stops (currently {}, empty keeps it):",
+            mouse.decay_ms
+        );
+        if let Some(input) = self.prompt_input(&question)?
+            && !input.trim().is_empty()
+        {
+            match input.trim().parse() {
+                Ok(decay_ms) => mouse.decay_ms = decay_ms,
+                Err(_) => {
+                    self.prompt_yes_no(&format!(
+                        "\"{}\" is not a valid duration; keeping {} ms. Continue?",
+                        input.trim(),
+                        mouse.decay_ms
+                    ))?;
+                }
+            }
+        }
+        mapper.controllers[index].mouse_stick = Some(mouse);
+
+        // Motion only reaches the controller from a source being read
+        let has_mouse = mapper
+            .sources
+            .iter()
+            .any(|source| source.kind == Some(device::DeviceKind::Mouse));
+        if !has_mouse {
+            self.prompt_yes_no(
+                "Add the mouse as a source device so its motion is read. Continue?",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the controller's button bindings and lets the user turn turbo on
+    /// or off for each, or change its rate, until Escape.
+    pub fn configure_turbo(&mut self, mapper: &mut DeviceMapper, index: usize) -> Result<()> {
+        loop {
+            let controller = &mapper.controllers[index];
+            let bindings: Vec<(evdev::KeyCode, evdev::KeyCode, Option<u32>)> = controller
+                .bindings_snapshot()
+                .into_iter()
+                .filter_map(|(key, binding)| match binding {
+                    Binding::Button(button) => Some((key, button, None)),
+                    Binding::Turbo { button, hz } => Some((key, button, Some(hz))),
+                    _ => None,
+                })
+                .collect();
+
+            let items: Vec<String> = bindings
+                .iter()
+                .map(|(key, button, hz)| {
+                    let name = describe_binding(controller, &Binding::Button(*button));
+                    match hz {
+                        Some(hz) => {
+                            format!("{:<16} {} (turbo {} Hz)", key_name(*key), name, hz)
+                        }
+                        None => format!("{:<16} {}", key_name(*key), name),
+                    }
+                })
+                .collect();
+            if items.is_empty() {
+                self.prompt_yes_no("No button bindings to configure. Continue?")?;
+                return Ok(());
+            }
+
+            let Some(choice) = self.select_from_list("Select a binding for turbo:", &items)? else {
+                return Ok(());
+            };
+            let (key, button, hz) = bindings[choice];
+
+            let rate = hz.unwrap_or(DEFAULT_TURBO_HZ);
+            let question = format!(
+                "Turbo presses per second for {}, up to {}, 0 for off (Enter for {}):",
+                key_name(key),
+                MAX_TURBO_HZ,
+                rate
+            );
+            let Some(input) = self.prompt_input(&question)? else {
+                continue;
+            };
+            let input = input.trim();
+
+            let binding = match input.parse::<u32>() {
+                Ok(0) => Binding::Button(button),
+                Ok(hz) if valid_turbo_hz(hz) => Binding::Turbo { button, hz },
+                Err(_) if input.is_empty() => Binding::Turbo { button, hz: rate },
+                _ => {
+                    self.prompt_yes_no(&format!("\"{}\" is not a valid rate. Continue?", input))?;
+                    continue;
+                }
+            };
+            mapper.controllers[index]
+                .key_mapping
+                .write()
+                .insert(key, binding);
+        }
+    }
+
+    /// Lists the controller's buttons with their keys and lets the user give
+    /// one button a new key or clear it, until Escape. The other bindings
+    /// are left alone.
+    pub fn edit_controller_bindings(
+        &mut self,
+        mapper: &mut DeviceMapper,
+        index: usize,
+    ) -> Result<()> {
+        let actions = [
+            "Bind a new key".to_string(),
+            "Clear the binding".to_string(),
+        ];
+
+        loop {
+            let Some(button) =
+                self.select_button("Select a button to rebind:", &mapper.controllers[index])?
+            else {
+                return Ok(());
+            };
+
+            let name = describe_binding(&mapper.controllers[index], &Binding::Button(button));
+            let key = match self.select_from_list(&name, &actions)? {
+                Some(0) => {
+                    let prompt = format!("Press a key to map to {}: ", name);
+                    let binding = Binding::Button(button);
+                    let Some(key) = self.capture_key_to_bind(&prompt, mapper, index, &binding)?
+                    else {
+                        continue;
+                    };
+                    Some(key)
+                }
+                Some(_) => None,
+                None => continue,
+            };
+
+            mapper.controllers[index].rebind(button, key);
+            mapper.refresh_mapped_keys();
+        }
+    }
+
+    pub fn show_main_menu(&mut self) -> Result<u8> {
+        if self.simple {
+            println!();
+            for (i, item) in MAIN_MENU.iter().enumerate() {
+                println!("{}. {}", i + 1, item);
+            }
+            print!("Select an option (1-{}): ", MAIN_MENU.len());
+
+            loop {
+                if let Some(option) = self.read_choice(MAIN_MENU.len())? {
+                    return Ok(option as u8 + 1);
+                }
+            }
+        }
+
+        self.ensure_terminal_size()?;
+
+        execute!(
+            self.stdout,
+            Clear(ClearType::All),
+            MoveTo(2, 2),
+            SetForegroundColor(Color::Cyan),
+            Print(&self.banner),
+            ResetColor
+        )?;
+
+        let mut selected = 0;
+        loop {
+            // Only the items are redrawn, so a compact status below the
+            // menu stays put
+            for (i, item) in MAIN_MENU.iter().enumerate() {
+                let background = if i == selected {
+                    Color::DarkBlue
+                } else {
+                    Color::Reset
+                };
+                execute!(
+                    self.stdout,
+                    MoveTo(2, 4 + i as u16),
+                    SetBackgroundColor(background),
+                    Print(format!("{}. {}", i + 1, item)),
+                    ResetColor
+                )?;
+            }
+            execute!(
+                self.stdout,
+                MoveTo(2, MENU_PROMPT_ROW),
+                Print("Select an option (1-9, 0 for 10, or Up/Down and Enter): ")
+            )?;
+
+            if let Event::Key(KeyEvent { code, .. }) = event::read()? {
+                match code {
+                    CtKeyCode::Up => {
+                        selected = selected.checked_sub(1).unwrap_or(MAIN_MENU.len() - 1);
+                    }
+                    CtKeyCode::Down => selected = (selected + 1) % MAIN_MENU.len(),
+                    CtKeyCode::Enter => return Ok(selected as u8 + 1),
+                    CtKeyCode::Char(c) => {
+                        // 0 picks the tenth entry
+                        if let Some(option) = c.to_digit(10).map(|d| if d == 0 { 10 } else { d })
+                            && (1..=MAIN_MENU.len() as u32).contains(&option)
+                        {
+                            return Ok(option as u8);
+                        }
+                    }
+                    _ => { /* Ignore other keys */ }
+                }
+            }
+        }
+    }
+
+    /// Lets the user swap controllers' places in the list, one pair at a
+    /// time, until Escape. The list shows the current order each time.
+    pub fn reorder_controllers(&mut self, mapper: &mut DeviceMapper) -> Result<()> {
+        let mut initial = 0;
+        loop {
+            let names: Vec<String> = mapper.controllers.iter().map(|c| c.name.clone()).collect();
+            let Some(first) = self.select_from_list_at(
+                "Select a controller to move (Esc when done):",
+                &names,
+                initial,
+            )?
+            else {
+                return Ok(());
+            };
+            let Some(second) =
+                self.select_from_list_at(&format!("Swap {} with:", names[first]), &names, first)?
+            else {
+                continue;
+            };
+
+            if let Err(e) = mapper.swap_controllers(first, second) {
+                self.prompt_yes_no(&format!("Failed to reorder: {}. Continue?", e))?;
+                return Ok(());
+            }
+            initial = second;
+        }
+    }
+
+    pub fn list_controllers(&mut self, controllers: &[VirtualController]) -> Result<()> {
+        if self.simple {
+            println!("\nActive Controllers:");
+            if controllers.is_empty() {
+                println!("  No controllers created yet.");
+            }
+            for (i, controller) in controllers.iter().enumerate() {
+                println!("  {}. {}", i + 1, describe_controller(controller));
+            }
+
+            return Ok(());
+        }
+
+        self.ensure_terminal_size()?;
+
+        execute!(
+            self.stdout,
+            Clear(ClearType::All),
+            MoveTo(2, 2),
+            SetForegroundColor(Color::Yellow),
+            Print("Active Controllers:"),
+            ResetColor
+        )?;
+
+        if controllers.is_empty() {
+            execute!(
+                self.stdout,
+                MoveTo(2, 4),
+                Print("No controllers created yet.")
+            )?;
+        } else {
+            for (i, controller) in controllers.iter().enumerate() {
+                execute!(
+                    self.stdout,
+                    MoveTo(2, 4 + i as u16),
+                    Print(format!("{}. {}", i + 1, describe_controller(controller)))
+                )?;
+            }
+        }
+
+        execute!(
+            self.stdout,
+            MoveTo(2, 6 + controllers.len() as u16),
+            Print("Press any key to continue...")
+        )?;
+
+        event::read()?;
+
+        Ok(())
+    }
+
+    /// Shows the session menu opened by the menu key while mapping. It is
+    /// driven by keys on the grabbed source, which the mapping thread reads.
+    pub fn show_session_menu(
+        &mut self,
+        status: &[ControllerStatus],
+        profiles: &[PathBuf],
+    ) -> Result<()> {
+        let lines: Vec<String> = status
+            .iter()
+            .map(|controller| {
+                let active: Vec<String> = controller.active.iter().map(describe_target).collect();
+                if active.is_empty() {
+                    format!("{}: idle", controller.name)
+                } else {
+                    format!("{}: {}", controller.name, active.join(", "))
+                }
+            })
+            .collect();
+        // Number keys 3 to 9 pick a profile
+        let switches: Vec<String> = profiles
+            .iter()
+            .take(7)
+            .enumerate()
+            .map(|(i, path)| {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                format!("{}: Switch to {}", i + 3, name)
+            })
+            .collect();
+
+        if self.simple {
+            println!("\nSession menu (controllers paused):");
+            for line in &lines {
+                println!("  {}", line);
+            }
+            println!("1/Esc: Resume  2: Stop mapping");
+            for switch in &switches {
+                println!("{}", switch);
+            }
+            return Ok(());
+        }
+
+        execute!(
+            self.stdout,
+            Clear(ClearType::All),
+            MoveTo(2, 2),
+            SetForegroundColor(Color::Yellow),
+            Print("Session Menu (controllers paused)"),
+            ResetColor
+        )?;
+
+        for (i, line) in lines.iter().enumerate() {
+            execute!(self.stdout, MoveTo(4, 4 + i as u16), Print(line))?;
+        }
+
+        execute!(
+            self.stdout,
+            MoveTo(2, 5 + lines.len() as u16),
+            Print("1/Esc: Resume  2: Stop mapping")
+        )?;
+        for (i, switch) in switches.iter().enumerate() {
+            execute!(
+                self.stdout,
+                MoveTo(2, 6 + (lines.len() + i) as u16),
+                Print(switch)
+            )?;
+        }
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Tells the user that mapping ended without them pressing the stop key.
+    /// Line mode still has a reader waiting for Enter, so ask for it; the
+    /// full-screen menus discard the terminal input queued meanwhile.
+    pub fn notify_session_ended(&mut self) -> Result<()> {
+        if self.simple {
+            print!("Mapping stopped. Press Enter to return to the menu.");
+            self.stdout.flush()?;
+        } else {
+            // Keys typed into the terminal while mapping (from a keyboard
+            // that wasn't grabbed) are still queued; drop them so the main
+            // menu doesn't take them as choices
+            while event::poll(Duration::ZERO)? {
+                event::read()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Updates the training overlay with a key press, if it is enabled.
+    pub fn show_training_input(&mut self, key: evdev::KeyCode, offset: Duration) -> Result<()> {
+        let Some(training) = self.training.as_mut() else {
+            return Ok(());
+        };
+        training.record(key, offset);
+
+        let rate = format!("{:.0} inputs/s", training.rate());
+        if self.simple {
+            println!("{}  ({})", key_name(key), rate);
+            return Ok(());
+        }
+        let lines = training.lines();
+
+        // Below the status lines of the active screen
+        let top = if self.compact { MENU_PROMPT_ROW + 4 } else { 8 };
+
+        execute!(
+            self.stdout,
+            MoveTo(2, top),
+            Clear(ClearType::FromCursorDown),
+            SetForegroundColor(Color::Cyan),
+            Print(rate),
+            ResetColor
+        )?;
+        for (i, line) in lines.iter().enumerate() {
+            execute!(self.stdout, MoveTo(4, top + 2 + i as u16), Print(line))?;
+        }
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Shows the controller the hotbar is tuning, with its axis scale and
+    /// bindings, in the column right of the training overlay.
+    pub fn show_tuning(&mut self, controller: &VirtualController, index: usize) -> Result<()> {
+        let mut title = format!(
+            "F{}: {} at {}x axis scale",
+            index + 1,
+            controller.name,
+            controller.axis_scale
+        );
+        if !controller.enabled.load(Ordering::Relaxed) {
+            title.push_str(", off");
+        }
+
+        let lines: Vec<String> = controller
+            .bindings_snapshot()
+            .iter()
+            .map(|(key, binding)| {
+                format!(
+                    "{}: {}",
+                    key_name(*key),
+                    describe_binding(controller, binding)
+                )
+            })
+            .collect();
+
+        if self.simple {
+            println!("{}", title);
+            return Ok(());
+        }
+
+        let top = if self.compact { MENU_PROMPT_ROW + 4 } else { 8 };
+        // Only as many bindings as fit on the screen
+        let (_, rows) = size()?;
+        let room = rows.saturating_sub(top + 3) as usize;
+
+        execute!(
+            self.stdout,
+            MoveTo(40, top),
+            Clear(ClearType::UntilNewLine),
+            SetForegroundColor(Color::Cyan),
+            Print(title),
+            ResetColor
+        )?;
+        for (i, line) in lines.iter().take(room).enumerate() {
+            execute!(
+                self.stdout,
+                MoveTo(42, top + 2 + i as u16),
+                Clear(ClearType::UntilNewLine),
+                Print(line)
+            )?;
+        }
+
+        self.stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// `stop_key` is the session's stop key code, 0 if it has none.
+    pub fn show_mapping_active(&mut self, stop_key: u16) -> Result<()> {
+        // Both are read from the grabbed keyboards, not the terminal
+        let stop = if stop_key != 0 {
+            format!(
+                "Press {} to stop and return to the menu, even while a game has focus.",
+                key_name(evdev::KeyCode::new(stop_key))
+            )
+        } else {
+            "Stop from the session menu.".to_string()
+        };
+        let panic =
+            "Ctrl+Alt+K stops at once and releases everything, even from a fullscreen game.";
+
+        if self.simple {
+            println!("\nMapping active. {}", stop);
+            println!("{}", panic);
+            return Ok(());
+        }
+
+        if self.compact {
+            // Leave the menu visible and print the status underneath it
+            execute!(
+                self.stdout,
+                MoveTo(2, MENU_PROMPT_ROW + 2),
+                Clear(ClearType::FromCursorDown),
+                SetForegroundColor(Color::Green),
+                Print("Mapping active. "),
+                ResetColor,
+                Print(stop)
+            )?;
+
+            self.stdout.flush()?;
+
+            return Ok(());
+        }
+
         execute!(
             self.stdout,
             Clear(ClearType::All),
@@ -261,7 +2341,9 @@ impl UI {
             MoveTo(2, 4),
             Print("Your keyboard inputs are now being sent to the virtual controllers."),
             MoveTo(2, 6),
-            Print("Press Delete to stop and return to the menu.")
+            Print(stop),
+            MoveTo(2, 7),
+            Print(panic)
         )?;
 
         self.stdout.flush()?;
@@ -269,3 +2351,211 @@ impl UI {
         Ok(())
     }
 }
+
+impl Drop for UI {
+    /// Covers an error returned from `main` between [`UI::init`] and
+    /// [`UI::cleanup`], which would otherwise leave the shell in raw mode.
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// One-line description of a controller for the controller list.
+fn describe_controller(controller: &VirtualController) -> String {
+    let mut line = format!(
+        "{} ({})",
+        controller.name,
+        controller.key_mapping.read().summary_counts()
+    );
+
+    if controller.kind == ControllerKind::Joystick {
+        line.push_str(" as joystick");
+    }
+    if controller.dpad_mode == DpadMode::Hat {
+        line.push_str(" with hat D-pad");
+    }
+    if controller.axis_scale != 1.0 {
+        line.push_str(&format!(" at {}x axis scale", controller.axis_scale));
+    }
+    if controller.normalize_diagonals {
+        line.push_str(" with round diagonals");
+    }
+
+    if !controller.sources.is_empty() {
+        let sources: Vec<String> = controller
+            .sources
+            .iter()
+            .map(|s| s.display().to_string())
+            .collect();
+        line.push_str(&format!(" from {}", sources.join(", ")));
+    }
+    if !controller.enabled.load(Ordering::Relaxed) {
+        line.push_str(", off while mapping");
+    }
+
+    line
+}
+
+/// Warns about bindings whose target the controller doesn't declare, or
+/// returns an empty string when every binding is usable.
+pub fn describe_unsupported(controllers: &[VirtualController]) -> String {
+    let mismatches: Vec<String> = controllers
+        .iter()
+        .filter_map(|controller| {
+            let keys = controller.unsupported_bindings();
+            (!keys.is_empty()).then(|| {
+                let keys: Vec<&str> = keys.iter().map(|key| key_name(*key)).collect();
+                format!("{} ({})", controller.name, keys.join(", "))
+            })
+        })
+        .collect();
+
+    if mismatches.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        " These keys drive buttons or axes the controller lacks and will do nothing: {}.",
+        mismatches.join("; ")
+    )
+}
+
+/// Lists conflicting keys with the controllers binding them, e.g.
+/// `KEY_W (Controller 1, Controller 2)`. `names` are the controllers' names
+/// by index.
+pub fn describe_conflicts(names: &[String], conflicts: &[Conflict]) -> String {
+    conflicts
+        .iter()
+        .map(|conflict| {
+            let controllers: Vec<&str> = conflict
+                .controllers
+                .iter()
+                .map(|&index| names[index].as_str())
+                .collect();
+            format!("{} ({})", key_name(conflict.key), controllers.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Records what was pressed for the next button of a mapping walkthrough.
+/// `mapped` holds the key bound to each button done so far. A skipped
+/// button loses any key bound to it, and going back unbinds the previous
+/// button's key so it can be pressed again.
+fn apply_capture(
+    controller: &VirtualController,
+    buttons: &[(evdev::KeyCode, &str)],
+    mapped: &mut Vec<Option<evdev::KeyCode>>,
+    result: CaptureResult,
+) {
+    let mut mapping = controller.key_mapping.write();
+    let button = buttons[mapped.len()].0;
+
+    match result {
+        CaptureResult::Key(key) => {
+            mapping.insert(key, Binding::Button(button));
+            mapped.push(Some(key));
+        }
+        CaptureResult::Skip => {
+            for key in mapping.keys_for_button(button) {
+                mapping.remove(&key);
+            }
+            mapped.push(None);
+        }
+        CaptureResult::Back => {
+            if let Some(Some(key)) = mapped.pop() {
+                mapping.remove(&key);
+            }
+        }
+        // The caller restores the bindings it started with
+        CaptureResult::Cancel => {}
+    }
+}
+
+/// What a binding drives, using the controller's button names.
+/// What binding `key` to `binding` on `controller` would replace, if the key
+/// is bound to anything else there.
+fn key_conflict(
+    controller: &VirtualController,
+    key: evdev::KeyCode,
+    binding: &Binding,
+) -> Option<String> {
+    let existing = controller.key_mapping.read().get(&key).cloned()?;
+    (existing != *binding).then(|| {
+        format!(
+            "{} is mapped to {}",
+            key_name(key),
+            describe_binding(controller, &existing)
+        )
+    })
+}
+
+fn describe_binding(controller: &VirtualController, binding: &Binding) -> String {
+    if let Some(button) = binding.button()
+        && let Some((_, name)) = controller
+            .get_available_button_mappings()
+            .into_iter()
+            .find(|(available, _)| *available == button)
+    {
+        return name.to_string();
+    }
+
+    match binding {
+        Binding::Combo(buttons) => buttons
+            .iter()
+            .map(|button| describe_binding(controller, &Binding::Button(*button)))
+            .collect::<Vec<_>>()
+            .join(" + "),
+        Binding::Exec(command) => format!("the command \"{}\"", command),
+        Binding::Sticky => "the sticky key".to_string(),
+        Binding::HalfPress => "the half-press key".to_string(),
+        Binding::Layer(name) => format!("the {} layer", name),
+        _ => binding
+            .targets()
+            .iter()
+            .map(describe_target)
+            .collect::<Vec<_>>()
+            .join(" + "),
+    }
+}
+
+fn describe_target(target: &Target) -> String {
+    match target {
+        Target::Button(button) => key_name(*button).to_string(),
+        Target::Axis(axis) => axis_name(*axis).to_string(),
+    }
+}
+
+/// One-line description of a source device for selection lists.
+fn describe_device(device: &InputDevice) -> String {
+    let kind = device.kind.map_or("device", |k| k.label());
+    format!(
+        "{} ({:04x}:{:04x} {}) [{}] {}",
+        device.name,
+        device.vendor_id,
+        device.product_id,
+        device.bus_type,
+        kind,
+        device.path.display()
+    )
+}
+
+/// Cuts `line` to `width` characters, ending it with "..." when shortened.
+fn clip(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+
+    let kept: String = line.chars().take(width.saturating_sub(3)).collect();
+    format!("{}...", kept)
+}
+
+/// [`describe_device`] for each keyboard, with the last used one marked.
+fn device_items(devices: &[InputDevice], last_used: Option<usize>) -> Vec<String> {
+    let mut items: Vec<String> = devices.iter().map(describe_device).collect();
+    if let Some(item) = last_used.and_then(|index| items.get_mut(index)) {
+        item.push_str(" (last used)");
+    }
+
+    items
+}